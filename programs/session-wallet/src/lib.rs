@@ -1,5 +1,21 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::compute_units::sol_remaining_compute_units;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::hash::{hash, hashv};
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::nonce::state::State as NonceState;
+use anchor_lang::solana_program::program::{invoke, invoke_signed};
+use anchor_lang::solana_program::system_instruction;
+#[allow(deprecated)]
+use anchor_lang::solana_program::sysvar::recent_blockhashes;
+use anchor_lang::solana_program::sysvar::instructions::{
+    self as instructions_sysvar, get_instruction_relative, load_instruction_at_checked,
+};
+use anchor_spl::associated_token::{get_associated_token_address, AssociatedToken};
+use anchor_spl::token::spl_token::instruction::AuthorityType;
+use anchor_spl::token::{
+    self, CloseAccount, Mint, MintTo, SetAuthority, Token, TokenAccount, Transfer,
+};
 
 declare_id!("SXqp6LiVF2GTCf6o7xiXJasav7DNyuGAeyp7kLm6Prk");
 
@@ -7,42 +23,217 @@ declare_id!("SXqp6LiVF2GTCf6o7xiXJasav7DNyuGAeyp7kLm6Prk");
 pub mod session_wallet {
     use super::*;
 
+    /// Create the global config; only the program's upgrade authority may call this
+    pub fn initialize_config(ctx: Context<InitializeConfig>, admin: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+
+        config.admin = admin;
+        config.volume_window_slots = 0;
+        config.max_window_volume = 0;
+        config.bump = ctx.bumps.config;
+
+        emit!(ConfigInitialized {
+            admin,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Create the program-owned treasury and vault for a mint
+    pub fn initialize_treasury(ctx: Context<InitializeTreasury>) -> Result<()> {
+        let treasury = &mut ctx.accounts.treasury;
+
+        treasury.mint = ctx.accounts.mint.key();
+        treasury.vault = ctx.accounts.treasury_vault.key();
+        treasury.total_deposited = 0;
+        treasury.total_withdrawn = 0;
+        treasury.total_disbursed = 0;
+        treasury.total_refunded = 0;
+        treasury.total_fees = 0;
+        treasury.total_escheated = 0;
+        treasury.bump = ctx.bumps.treasury;
+
+        emit!(TreasuryInitialized {
+            treasury: treasury.key(),
+            mint: treasury.mint,
+            vault: treasury.vault,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Deposit tokens into a treasury vault
+    pub fn deposit_treasury(ctx: Context<DepositTreasury>, amount: u64) -> Result<()> {
+        let treasury = &mut ctx.accounts.treasury;
+
+        treasury.total_deposited = treasury
+            .total_deposited
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.depositor_token_account.to_account_info(),
+            to: ctx.accounts.treasury_vault.to_account_info(),
+            authority: ctx.accounts.depositor.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        token::transfer(cpi_ctx, amount)?;
+
+        emit!(TreasuryDeposited {
+            treasury: treasury.key(),
+            depositor: ctx.accounts.depositor.key(),
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw tokens from a treasury vault (admin only)
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>, amount: u64) -> Result<()> {
+        let treasury = &mut ctx.accounts.treasury;
+
+        treasury.total_withdrawn = treasury
+            .total_withdrawn
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        transfer_from_treasury(
+            treasury,
+            &ctx.accounts.treasury_vault,
+            &ctx.accounts.destination_token_account.to_account_info(),
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        emit!(TreasuryWithdrawn {
+            treasury: treasury.key(),
+            destination: ctx.accounts.destination_token_account.key(),
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
     /// Initialize a new session wallet
     pub fn initialize_session(
         ctx: Context<InitializeSession>,
         session_id: String,
         initial_funding: u64,
     ) -> Result<()> {
-        let session_wallet = &mut ctx.accounts.session_wallet;
+        let session_key = ctx.accounts.session_wallet.key();
 
-        session_wallet.authority = ctx.accounts.authority.key();
-        session_wallet.session_id = session_id;
-        session_wallet.created_at = Clock::get()?.unix_timestamp;
-        session_wallet.last_activity = Clock::get()?.unix_timestamp;
-        session_wallet.initial_balance = initial_funding;
-        session_wallet.current_balance = initial_funding;
-        session_wallet.is_active = true;
-        session_wallet.bump = ctx.bumps.session_wallet;
+        // Sessions opened for a registered agent are refused while it owes credit
+        let agent_pubkey = match &ctx.accounts.agent_account {
+            Some(agent_account) => {
+                require!(
+                    agent_account.outstanding_debt == 0,
+                    ErrorCode::DebtOutstanding
+                );
+                Some(agent_account.agent)
+            }
+            None => None,
+        };
 
-        // Transfer initial funding from treasury to session wallet
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.treasury_token_account.to_account_info(),
-            to: ctx.accounts.session_token_account.to_account_info(),
-            authority: ctx.accounts.authority.to_account_info(),
+        // Transfer initial funding from treasury vault to session wallet
+        disburse_from_treasury(
+            &mut ctx.accounts.treasury,
+            &ctx.accounts.treasury_vault,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.token_program,
+            initial_funding,
+        )?;
+
+        // Optionally create a durable nonce so pre-signed transactions outlive the blockhash window.
+        // The session data is written afterwards, since the CPI passes the session account.
+        let nonce_account = match &ctx.accounts.session_nonce_account {
+            Some(session_nonce_account) => {
+                let recent_blockhashes = ctx
+                    .accounts
+                    .recent_blockhashes
+                    .as_ref()
+                    .ok_or(ErrorCode::InvalidNonceAccount)?;
+
+                let nonce_pubkey = Pubkey::create_with_seed(
+                    &session_key,
+                    SESSION_NONCE_SEED,
+                    &System::id(),
+                )
+                .map_err(|_| error!(ErrorCode::InvalidNonceAccount))?;
+                require_keys_eq!(
+                    session_nonce_account.key(),
+                    nonce_pubkey,
+                    ErrorCode::InvalidNonceAccount
+                );
+
+                let seeds = &[
+                    SESSION_SEED,
+                    session_id.as_bytes(),
+                    &[ctx.bumps.session_wallet],
+                ];
+                let signer = &[&seeds[..]];
+
+                let lamports = Rent::get()?.minimum_balance(NonceState::size());
+                let nonce_instructions = system_instruction::create_nonce_account_with_seed(
+                    &ctx.accounts.authority.key(),
+                    &nonce_pubkey,
+                    &session_key,
+                    SESSION_NONCE_SEED,
+                    &ctx.accounts.authority.key(),
+                    lamports,
+                );
+                let account_infos = [
+                    ctx.accounts.authority.to_account_info(),
+                    session_nonce_account.to_account_info(),
+                    ctx.accounts.session_wallet.to_account_info(),
+                    recent_blockhashes.to_account_info(),
+                    ctx.accounts.rent.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                ];
+                for ix in nonce_instructions.iter() {
+                    invoke_signed(ix, &account_infos, signer)?;
+                }
+
+                emit!(SessionNonceCreated {
+                    session_id: session_id.clone(),
+                    nonce_account: nonce_pubkey,
+                    nonce_authority: ctx.accounts.authority.key(),
+                    timestamp: Clock::get()?.unix_timestamp,
+                });
+
+                Some(nonce_pubkey)
+            }
+            None => None,
         };
 
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        let mut session_wallet = ctx.accounts.session_wallet.load_init()?;
 
-        token::transfer(cpi_ctx, initial_funding)?;
+        open_session(
+            &mut session_wallet,
+            ctx.accounts.authority.key(),
+            &session_id,
+            ctx.accounts.treasury.mint,
+            initial_funding,
+            ctx.bumps.session_wallet,
+        )?;
+        session_wallet.set_agent_pubkey(agent_pubkey);
+        session_wallet.set_nonce_account(nonce_account);
 
         emit!(SessionCreated {
-            session_id: session_wallet.session_id.clone(),
-            pda: session_wallet.key(),
+            session_id,
+            pda: session_key,
             initial_funding,
             timestamp: Clock::get()?.unix_timestamp,
         });
 
+        session_wallet.record_event()?;
+
         Ok(())
     }
 
@@ -52,61 +243,583 @@ pub mod session_wallet {
         amount: u64,
         service_id: String,
     ) -> Result<()> {
-        let session_wallet = &mut ctx.accounts.session_wallet;
+        let session_wallet = &ctx.accounts.session_wallet;
+        check_composition(&*session_wallet.load()?, &ctx.accounts.instructions)?;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
 
-        require!(session_wallet.is_active, ErrorCode::SessionClosed);
+        // Everything downstream sees the discounted price
+        let amount = apply_trial_discount(provider_account.as_ref(), &*session_wallet.load()?, &service_id, amount)?;
+
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider: ctx.accounts.service_provider_token_account.owner,
+            service_id: &service_id,
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+            credit_available: 0,
+            currency_balance: match &ctx.accounts.currency_balance {
+                Some(currency_balance) => Some(currency_balance.load()?.current_balance),
+                None => None,
+            },
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: ctx.accounts.service_provider_token_account.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        preflight_purchase(
+            &*session_wallet.load()?,
+            ctx.accounts.policy.as_ref(),
+            &purchase,
+            &ctx.accounts.config,
+            &ctx.accounts.global_stats,
+            ctx.accounts.authority.key(),
+            ctx.accounts.role_assignment.as_deref(),
+        )?;
+
+        // Secondary mints are outside the session-mint volume window
+        if ctx.accounts.currency_balance.is_none() {
+            track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+        }
+
+        charge_operator(
+            &*session_wallet.load()?,
+            ctx.accounts.authority.key(),
+            ctx.accounts.role_assignment.as_mut(),
+            amount,
+        )?;
+
+        // The platform fee is cut from the provider's share, in the mint the purchase pays in
+        let fee = collect_fee(
+            &*session_wallet.load()?,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &mut ctx.accounts.treasury,
+            &purchase,
+        )?;
+
+        // Secondary mints pay out of their own vault
+        let remaining_balance = match &ctx.accounts.currency_balance {
+            Some(currency_balance) => {
+                settle_currency_purchase(
+                    session_wallet,
+                    currency_balance,
+                    &ctx.accounts.session_token_account,
+                    &ctx.accounts.service_provider_token_account,
+                    &ctx.accounts.token_program,
+                    amount,
+                    (&ctx.accounts.treasury_vault, fee),
+                )?;
+                currency_balance.load()?.current_balance
+            }
+            None => {
+                settle_purchase(
+                    session_wallet,
+                    &ctx.accounts.session_token_account,
+                    &ctx.accounts.service_provider_token_account,
+                    &ctx.accounts.token_program,
+                    &service_id,
+                    amount,
+                    (&ctx.accounts.treasury_vault, fee),
+                )?;
+                session_wallet.load()?.current_balance
+            }
+        };
+
+        emit!(PurchaseExecuted {
+            session_id: session_wallet.load()?.session_id().to_string(),
+            service_id,
+            category_code,
+            amount,
+            remaining_balance,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.load_mut()?.record_event()?;
+
+        Ok(())
+    }
+
+    /// Execute a service purchase and mint a receipt token to the signer
+    ///
+    /// The token's name, symbol, `uri` and the purchase it stands for are
+    /// recorded in a `ReceiptMetadata` PDA keyed by its mint, so wallets and
+    /// indexers can tell it is a receipt.
+    pub fn execute_purchase_with_receipt(
+        ctx: Context<ExecutePurchaseWithReceipt>,
+        amount: u64,
+        service_id: String,
+        uri: String,
+    ) -> Result<()> {
         require!(
-            session_wallet.current_balance >= amount,
-            ErrorCode::InsufficientBalance
+            service_id.len() <= PurchaseReceipt::MAX_SERVICE_ID_LEN,
+            ErrorCode::ServiceIdTooLong
         );
+        require!(uri.len() <= ReceiptMetadata::MAX_URI_LEN, ErrorCode::ReceiptUriTooLong);
 
-        // Update balance
-        session_wallet.current_balance = session_wallet
-            .current_balance
-            .checked_sub(amount)
-            .ok_or(ErrorCode::Overflow)?;
+        let session_wallet = &ctx.accounts.session_wallet;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
+        check_composition(&*session_wallet.load()?, &ctx.accounts.instructions)?;
+        let purchase_index = session_wallet.load()?.purchase_count;
 
-        session_wallet.last_activity = Clock::get()?.unix_timestamp;
+        let amount = apply_trial_discount(provider_account.as_ref(), &*session_wallet.load()?, &service_id, amount)?;
 
-        // Transfer USDC from session wallet to service provider
-        let session_id = session_wallet.session_id.clone();
-        let seeds = &[
-            b"session",
-            session_id.as_bytes(),
-            &[session_wallet.bump],
-        ];
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider: ctx.accounts.service_provider_token_account.owner,
+            service_id: &service_id,
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+            credit_available: 0,
+            currency_balance: None,
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: ctx.accounts.service_provider_token_account.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        check_purchase(&*session_wallet.load()?, ctx.accounts.policy.as_ref(), &purchase)?;
+
+        track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+
+        charge_operator(
+            &*session_wallet.load()?,
+            ctx.accounts.authority.key(),
+            ctx.accounts.role_assignment.as_mut(),
+            amount,
+        )?;
+
+        let fee = collect_fee(
+            &*session_wallet.load()?,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &mut ctx.accounts.treasury,
+            &purchase,
+        )?;
+
+        settle_purchase(
+            session_wallet,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.service_provider_token_account,
+            &ctx.accounts.token_program,
+            &service_id,
+            amount,
+            (&ctx.accounts.treasury_vault, fee),
+        )?;
+
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        let receipt = &mut ctx.accounts.receipt;
+        receipt.session = session_wallet.key();
+        receipt.mint = ctx.accounts.receipt_mint.key();
+        receipt.provider = ctx.accounts.service_provider_token_account.owner;
+        receipt.service_id = service_id.clone();
+        receipt.category_code = category_code;
+        receipt.amount = amount;
+        receipt.purchase_index = purchase_index;
+        receipt.timestamp = timestamp;
+        receipt.bump = ctx.bumps.receipt;
+
+        let receipt_metadata = &mut ctx.accounts.receipt_metadata;
+        receipt_metadata.mint = receipt.mint;
+        receipt_metadata.receipt = receipt.key();
+        receipt_metadata.name = format!("Receipt #{}", purchase_index);
+        receipt_metadata.symbol = ReceiptMetadata::SYMBOL.to_string();
+        receipt_metadata.uri = uri;
+        receipt_metadata.service_id = service_id.clone();
+        receipt_metadata.amount = amount;
+        receipt_metadata.bump = ctx.bumps.receipt_metadata;
+
+        // Mint exactly one receipt token, then drop the mint authority so supply stays fixed
+        let (session_id, remaining_balance, session_seeds) = {
+            let session = session_wallet.load()?;
+            (session.session_id().to_string(), session.current_balance, session.signer_seeds())
+        };
+        let seeds = session_seeds.seeds();
         let signer = &[&seeds[..]];
 
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.session_token_account.to_account_info(),
-            to: ctx.accounts.service_provider_token_account.to_account_info(),
+        let cpi_accounts = MintTo {
+            mint: ctx.accounts.receipt_mint.to_account_info(),
+            to: ctx.accounts.receipt_token_account.to_account_info(),
             authority: session_wallet.to_account_info(),
         };
 
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
 
-        token::transfer(cpi_ctx, amount)?;
+        token::mint_to(cpi_ctx, 1)?;
+
+        let cpi_accounts = SetAuthority {
+            current_authority: session_wallet.to_account_info(),
+            account_or_mint: ctx.accounts.receipt_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        token::set_authority(cpi_ctx, AuthorityType::MintTokens, None)?;
 
         emit!(PurchaseExecuted {
+            session_id: session_id.clone(),
+            service_id,
+            category_code,
+            amount,
+            remaining_balance,
+            timestamp,
+        });
+
+        emit!(ReceiptMinted {
             session_id,
+            receipt: receipt.key(),
+            mint: receipt.mint,
+            metadata: receipt_metadata.key(),
+            purchase_index,
+            amount,
+            timestamp,
+        });
+
+        session_wallet.load_mut()?.record_event()?;
+
+        Ok(())
+    }
+
+    /// Register the agent key whose signatures authorize purchase intents
+    pub fn set_agent_key(ctx: Context<SetAgentKey>, agent_pubkey: Pubkey) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+        require_agent_clear(&ctx.accounts.agent_account)?;
+
+        session_wallet.set_agent_pubkey(Some(agent_pubkey));
+        session_wallet.last_activity = Clock::get()?.unix_timestamp;
+
+        emit!(AgentKeySet {
+            session_id: session_wallet.session_id().to_string(),
+            agent_pubkey,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Execute a service purchase authorized by an ed25519 signature from the agent key
+    ///
+    /// The transaction must carry an ed25519 program instruction immediately
+    /// before this one, verifying the agent's signature over
+    /// `purchase_intent_message(session, pay_to, service_id, amount, nonce)`,
+    /// so the intent only pays the `service_provider_token_account` it names.
+    /// The agent key spends as a session member: unless it is the session
+    /// authority it needs an Operator assignment, whose limits apply.
+    pub fn execute_purchase_with_intent(
+        ctx: Context<ExecutePurchaseWithIntent>,
+        amount: u64,
+        service_id: String,
+        nonce: u64,
+    ) -> Result<()> {
+        let session_wallet = &ctx.accounts.session_wallet;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
+        check_composition(&*session_wallet.load()?, &ctx.accounts.instructions)?;
+
+        let agent_pubkey = {
+            let mut session = session_wallet.load_mut()?;
+            let agent_pubkey = session.agent_pubkey().ok_or(ErrorCode::AgentKeyNotSet)?;
+            require!(
+                nonce > session.last_intent_nonce,
+                ErrorCode::IntentNonceReused
+            );
+
+            let pay_to = ctx.accounts.service_provider_token_account.key();
+            let message = purchase_intent_message(&session_wallet.key(), &pay_to, &service_id, amount, nonce);
+            verify_ed25519_instruction(&ctx.accounts.instructions, &agent_pubkey, &message)?;
+
+            session.last_intent_nonce = nonce;
+            agent_pubkey
+        };
+        require!(
+            has_role(session_wallet, agent_pubkey, ctx.accounts.role_assignment.as_ref(), Role::Operator)?,
+            ErrorCode::Unauthorized
+        );
+
+        let amount = apply_trial_discount(provider_account.as_ref(), &*session_wallet.load()?, &service_id, amount)?;
+
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider: ctx.accounts.service_provider_token_account.owner,
+            service_id: &service_id,
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+            credit_available: 0,
+            currency_balance: None,
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: ctx.accounts.service_provider_token_account.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        preflight_purchase(
+            &*session_wallet.load()?,
+            ctx.accounts.policy.as_ref(),
+            &purchase,
+            &ctx.accounts.config,
+            &ctx.accounts.global_stats,
+            agent_pubkey,
+            ctx.accounts.role_assignment.as_deref(),
+        )?;
+
+        track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+
+        charge_operator(
+            &*session_wallet.load()?,
+            agent_pubkey,
+            ctx.accounts.role_assignment.as_mut(),
+            amount,
+        )?;
+
+        let fee = collect_fee(
+            &*session_wallet.load()?,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &mut ctx.accounts.treasury,
+            &purchase,
+        )?;
+
+        settle_purchase(
+            session_wallet,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.service_provider_token_account,
+            &ctx.accounts.token_program,
+            &service_id,
+            amount,
+            (&ctx.accounts.treasury_vault, fee),
+        )?;
+
+        session_wallet.load_mut()?.record_event()?;
+
+        let session = session_wallet.load()?;
+
+        emit!(PurchaseExecuted {
+            session_id: session.session_id().to_string(),
             service_id,
+            category_code,
             amount,
-            remaining_balance: session_wallet.current_balance,
+            remaining_balance: session.current_balance,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        emit!(PurchaseIntentVerified {
+            session_id: session.session_id().to_string(),
+            agent_pubkey,
+            nonce,
             timestamp: Clock::get()?.unix_timestamp,
         });
 
         Ok(())
     }
 
+    /// Settle an HTTP 402 payment voucher signed by the session's agent key
+    ///
+    /// The transaction must carry an ed25519 program instruction immediately
+    /// before this one, verifying the agent's signature over
+    /// `http402_voucher_message(session, pay_to, voucher)`. The agent key is
+    /// held to the same role and Operator limits as for purchase intents.
+    pub fn redeem_http402_voucher(
+        ctx: Context<RedeemHttp402Voucher>,
+        voucher: Http402Voucher,
+    ) -> Result<()> {
+        let session_wallet = &ctx.accounts.session_wallet;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        check_composition(&*session_wallet.load()?, &ctx.accounts.instructions)?;
+        let pay_to = ctx.accounts.service_provider_token_account.key();
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        require!(timestamp <= voucher.expiry, ErrorCode::VoucherExpired);
+
+        let agent_pubkey = session_wallet
+            .load()?
+            .agent_pubkey()
+            .ok_or(ErrorCode::AgentKeyNotSet)?;
+
+        let message = http402_voucher_message(&session_wallet.key(), &pay_to, &voucher);
+        verify_ed25519_instruction(&ctx.accounts.instructions, &agent_pubkey, &message)?;
+        require!(
+            has_role(session_wallet, agent_pubkey, ctx.accounts.role_assignment.as_ref(), Role::Operator)?,
+            ErrorCode::Unauthorized
+        );
+
+        // Vouchers carry no service id, so category allowlists never match
+        // them and no service listing applies
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), "");
+        let purchase = PurchaseContext {
+            provider: ctx.accounts.service_provider_token_account.owner,
+            service_id: "",
+            amount: voucher.amount,
+            timestamp,
+            credit_available: 0,
+            currency_balance: None,
+            category_code,
+            service_listing: None,
+            payout_mint: ctx.accounts.service_provider_token_account.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        preflight_purchase(
+            &*session_wallet.load()?,
+            ctx.accounts.policy.as_ref(),
+            &purchase,
+            &ctx.accounts.config,
+            &ctx.accounts.global_stats,
+            agent_pubkey,
+            ctx.accounts.role_assignment.as_deref(),
+        )?;
+
+        track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, voucher.amount)?;
+
+        charge_operator(
+            &*session_wallet.load()?,
+            agent_pubkey,
+            ctx.accounts.role_assignment.as_mut(),
+            voucher.amount,
+        )?;
+
+        let fee = collect_fee(
+            &*session_wallet.load()?,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &mut ctx.accounts.treasury,
+            &purchase,
+        )?;
+
+        settle_purchase(
+            session_wallet,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.service_provider_token_account,
+            &ctx.accounts.token_program,
+            "",
+            voucher.amount,
+            (&ctx.accounts.treasury_vault, fee),
+        )?;
+
+        // The record's PDA is keyed on the voucher hash, so a second redemption fails at init
+        let redeemed_voucher = &mut ctx.accounts.redeemed_voucher;
+        redeemed_voucher.session = session_wallet.key();
+        redeemed_voucher.resource_hash = voucher.resource_hash;
+        redeemed_voucher.pay_to = pay_to;
+        redeemed_voucher.amount = voucher.amount;
+        redeemed_voucher.redeemed_at = timestamp;
+        redeemed_voucher.bump = ctx.bumps.redeemed_voucher;
+
+        session_wallet.load_mut()?.record_event()?;
+
+        let session = session_wallet.load()?;
+
+        emit!(VoucherRedeemed {
+            session_id: session.session_id().to_string(),
+            resource_hash: voucher.resource_hash,
+            pay_to,
+            amount: voucher.amount,
+            remaining_balance: session.current_balance,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
     /// Add funds to session wallet
     pub fn fund_session(
         ctx: Context<FundSession>,
         amount: u64,
     ) -> Result<()> {
-        let session_wallet = &mut ctx.accounts.session_wallet;
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+
+        if !session_wallet.is_active() {
+            return reject_funding(
+                &session_wallet,
+                ctx.accounts.funder.key(),
+                amount,
+                RejectionReason::SessionClosed,
+            );
+        }
+
+        // Outstanding credit for the session's agent is repaid before anything is credited
+        let repaid = match (
+            ctx.accounts.agent_account.as_mut(),
+            ctx.accounts.debt.as_mut(),
+            ctx.accounts.treasury.as_mut(),
+            ctx.accounts.treasury_vault.as_ref(),
+        ) {
+            (Some(agent_account), Some(debt), Some(treasury), Some(treasury_vault)) => {
+                require!(
+                    session_wallet.agent_pubkey() == Some(agent_account.agent),
+                    ErrorCode::InvalidCreditAccounts
+                );
+                require_keys_eq!(
+                    debt.agent_account,
+                    agent_account.key(),
+                    ErrorCode::InvalidCreditAccounts
+                );
+                require_keys_eq!(debt.mint, session_wallet.mint, ErrorCode::InvalidCreditAccounts);
+                require_keys_eq!(treasury.mint, debt.mint, ErrorCode::InvalidCreditAccounts);
+                require_keys_eq!(
+                    treasury_vault.key(),
+                    treasury.vault,
+                    ErrorCode::InvalidCreditAccounts
+                );
 
-        require!(session_wallet.is_active, ErrorCode::SessionClosed);
+                let repaid = amount.min(debt.outstanding);
+                if repaid > 0 {
+                    debt.outstanding -= repaid;
+                    debt.total_repaid = debt
+                        .total_repaid
+                        .checked_add(repaid)
+                        .ok_or(ErrorCode::Overflow)?;
+                    agent_account.outstanding_debt = agent_account
+                        .outstanding_debt
+                        .checked_sub(repaid)
+                        .ok_or(ErrorCode::Overflow)?;
+                    treasury.total_refunded = treasury
+                        .total_refunded
+                        .checked_add(repaid)
+                        .ok_or(ErrorCode::Overflow)?;
+
+                    let cpi_accounts = Transfer {
+                        from: ctx.accounts.funder_token_account.to_account_info(),
+                        to: treasury_vault.to_account_info(),
+                        authority: ctx.accounts.funder.to_account_info(),
+                    };
+
+                    let cpi_program = ctx.accounts.token_program.to_account_info();
+                    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+                    token::transfer(cpi_ctx, repaid)?;
+
+                    emit!(DebtRepaid {
+                        agent: agent_account.agent,
+                        mint: debt.mint,
+                        amount: repaid,
+                        outstanding: debt.outstanding,
+                        timestamp: Clock::get()?.unix_timestamp,
+                    });
+                }
+                repaid
+            }
+            (None, None, None, None) => 0,
+            _ => return err!(ErrorCode::InvalidCreditAccounts),
+        };
+        let amount = amount - repaid;
+
+        if session_wallet.exceeds_funding_cap(amount) {
+            return reject_funding(
+                &session_wallet,
+                ctx.accounts.funder.key(),
+                amount,
+                RejectionReason::FundingCapExceeded,
+            );
+        }
 
         // Update balance
         session_wallet.current_balance = session_wallet
@@ -114,6 +827,11 @@ pub mod session_wallet {
             .checked_add(amount)
             .ok_or(ErrorCode::Overflow)?;
 
+        session_wallet.total_funded = session_wallet
+            .total_funded
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
         session_wallet.last_activity = Clock::get()?.unix_timestamp;
 
         // Transfer USDC from funder to session wallet
@@ -129,37 +847,58 @@ pub mod session_wallet {
         token::transfer(cpi_ctx, amount)?;
 
         emit!(FundsAdded {
-            session_id: session_wallet.session_id.clone(),
+            session_id: session_wallet.session_id().to_string(),
             amount,
             new_balance: session_wallet.current_balance,
             timestamp: Clock::get()?.unix_timestamp,
         });
 
+        session_wallet.record_event()?;
+
         Ok(())
     }
 
     /// Close session and refund remaining balance
     pub fn close_session(ctx: Context<CloseSession>) -> Result<()> {
-        let session_wallet = &mut ctx.accounts.session_wallet;
+        let (remaining_balance, session_seeds) = {
+            let session_wallet = ctx.accounts.session_wallet.load()?;
+
+            require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+            require!(session_wallet.escrowed_balance == 0, ErrorCode::EscrowOutstanding);
 
-        require!(session_wallet.is_active, ErrorCode::SessionClosed);
+            // The automation thread may only close a session that has expired
+            let is_owner = has_role(
+                &ctx.accounts.session_wallet,
+                ctx.accounts.authority.key(),
+                ctx.accounts.role_assignment.as_ref(),
+                Role::Owner,
+            )?;
+            if !is_owner {
+                require!(
+                    session_wallet.expires_at != 0
+                        && Clock::get()?.unix_timestamp > session_wallet.expires_at,
+                    ErrorCode::SessionNotExpired
+                );
+            }
 
-        let remaining_balance = session_wallet.current_balance;
+            (session_wallet.current_balance, session_wallet.signer_seeds())
+        };
 
-        // Refund remaining balance to treasury
+        // Refund remaining balance to treasury vault
         if remaining_balance > 0 {
-            let session_id = session_wallet.session_id.clone();
-            let seeds = &[
-                b"session",
-                session_id.as_bytes(),
-                &[session_wallet.bump],
-            ];
+            let treasury = &mut ctx.accounts.treasury;
+            treasury.total_refunded = treasury
+                .total_refunded
+                .checked_add(remaining_balance)
+                .ok_or(ErrorCode::Overflow)?;
+
+            let seeds = session_seeds.seeds();
             let signer = &[&seeds[..]];
 
             let cpi_accounts = Transfer {
                 from: ctx.accounts.session_token_account.to_account_info(),
-                to: ctx.accounts.treasury_token_account.to_account_info(),
-                authority: session_wallet.to_account_info(),
+                to: ctx.accounts.treasury_vault.to_account_info(),
+                authority: ctx.accounts.session_wallet.to_account_info(),
             };
 
             let cpi_program = ctx.accounts.token_program.to_account_info();
@@ -168,171 +907,11517 @@ pub mod session_wallet {
             token::transfer(cpi_ctx, remaining_balance)?;
         }
 
-        session_wallet.is_active = false;
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        session_wallet.set_active(false);
         session_wallet.current_balance = 0;
 
         emit!(SessionClosed {
-            session_id: session_wallet.session_id.clone(),
+            session_id: session_wallet.session_id().to_string(),
             refunded_amount: remaining_balance,
-            total_spent: session_wallet.initial_balance - remaining_balance,
+            total_spent: session_wallet.total_spent,
             timestamp: Clock::get()?.unix_timestamp,
         });
+        emit_session_report(&session_wallet, remaining_balance)?;
+
+        session_wallet.record_event()?;
 
         Ok(())
     }
-}
 
-// ============================================================================
-// Accounts
-// ============================================================================
-
-#[derive(Accounts)]
-#[instruction(session_id: String)]
-pub struct InitializeSession<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + SessionWallet::SIZE,
-        seeds = [b"session", session_id.as_bytes()],
-        bump
-    )]
-    pub session_wallet: Account<'info, SessionWallet>,
+    /// Withdraw the session's durable nonce account back to the authority
+    pub fn close_session_nonce(ctx: Context<CloseSessionNonce>) -> Result<()> {
+        let nonce_account = &ctx.accounts.session_nonce_account;
 
-    #[account(mut)]
-    pub treasury_token_account: Account<'info, TokenAccount>,
+        let ix = system_instruction::withdraw_nonce_account(
+            &nonce_account.key(),
+            &ctx.accounts.authority.key(),
+            &ctx.accounts.authority.key(),
+            nonce_account.lamports(),
+        );
+        invoke(
+            &ix,
+            &[
+                nonce_account.to_account_info(),
+                ctx.accounts.authority.to_account_info(),
+                ctx.accounts.recent_blockhashes.to_account_info(),
+                ctx.accounts.rent.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
 
-    /// CHECK: Session token account will be created externally
-    #[account(mut)]
-    pub session_token_account: AccountInfo<'info>,
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        session_wallet.set_nonce_account(None);
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+        emit!(SessionNonceClosed {
+            session_id: session_wallet.session_id().to_string(),
+            nonce_account: nonce_account.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
 
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
-}
+        session_wallet.record_event()?;
 
-#[derive(Accounts)]
-pub struct ExecutePurchase<'info> {
-    #[account(
-        mut,
-        seeds = [b"session", session_wallet.session_id.as_bytes()],
-        bump = session_wallet.bump
-    )]
-    pub session_wallet: Account<'info, SessionWallet>,
+        Ok(())
+    }
 
-    #[account(mut)]
-    pub session_token_account: Account<'info, TokenAccount>,
+    /// Record an immutable point-in-time snapshot of session balances and counters
+    pub fn snapshot_session(ctx: Context<SnapshotSession>) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        let snapshot_index = session_wallet.snapshot_count;
+        let clock = Clock::get()?;
 
-    #[account(mut)]
-    pub service_provider_token_account: Account<'info, TokenAccount>,
+        let snapshot = &mut ctx.accounts.snapshot;
+        snapshot.session = ctx.accounts.session_wallet.key();
+        snapshot.snapshot_index = snapshot_index;
+        snapshot.initial_balance = session_wallet.initial_balance;
+        snapshot.current_balance = session_wallet.current_balance;
+        snapshot.total_funded = session_wallet.total_funded;
+        snapshot.total_spent = session_wallet.total_spent;
+        snapshot.purchase_count = session_wallet.purchase_count;
+        snapshot.is_active = session_wallet.is_active();
+        snapshot.slot = clock.slot;
+        snapshot.timestamp = clock.unix_timestamp;
+        snapshot.bump = ctx.bumps.snapshot;
 
-    pub token_program: Program<'info, Token>,
-}
+        session_wallet.snapshot_count = session_wallet
+            .snapshot_count
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
 
-#[derive(Accounts)]
-pub struct FundSession<'info> {
-    #[account(
-        mut,
-        seeds = [b"session", session_wallet.session_id.as_bytes()],
-        bump = session_wallet.bump
-    )]
-    pub session_wallet: Account<'info, SessionWallet>,
+        emit!(SnapshotTaken {
+            session_id: session_wallet.session_id().to_string(),
+            snapshot: snapshot.key(),
+            snapshot_index,
+            current_balance: snapshot.current_balance,
+            total_funded: snapshot.total_funded,
+            total_spent: snapshot.total_spent,
+            purchase_count: snapshot.purchase_count,
+            slot: snapshot.slot,
+            timestamp: snapshot.timestamp,
+        });
 
-    #[account(mut)]
-    pub funder_token_account: Account<'info, TokenAccount>,
+        session_wallet.record_event()?;
 
+        Ok(())
+    }
+
+    /// Create a spending policy owned by the caller
+    pub fn create_policy(
+        ctx: Context<CreatePolicy>,
+        policy_id: String,
+        rules: Vec<PolicyRule>,
+    ) -> Result<()> {
+        require!(
+            policy_id.len() <= Policy::MAX_POLICY_ID_LEN,
+            ErrorCode::PolicyIdTooLong
+        );
+        validate_policy_rules(&rules)?;
+
+        let policy = &mut ctx.accounts.policy;
+        policy.authority = ctx.accounts.authority.key();
+        policy.policy_id = policy_id;
+        policy.rules = rules;
+        policy.bump = ctx.bumps.policy;
+
+        emit!(PolicyUpdated {
+            policy: policy.key(),
+            authority: policy.authority,
+            rule_count: policy.rules.len() as u8,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Replace the rules of an existing policy
+    pub fn update_policy(ctx: Context<UpdatePolicy>, rules: Vec<PolicyRule>) -> Result<()> {
+        validate_policy_rules(&rules)?;
+
+        let policy = &mut ctx.accounts.policy;
+        policy.rules = rules;
+
+        emit!(PolicyUpdated {
+            policy: policy.key(),
+            authority: policy.authority,
+            rule_count: policy.rules.len() as u8,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Attach a policy to a session; every purchase is then evaluated against it
+    pub fn attach_policy(ctx: Context<AttachPolicy>) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+
+        session_wallet.set_policy(Some(ctx.accounts.policy.key()));
+        session_wallet.last_activity = Clock::get()?.unix_timestamp;
+
+        emit!(PolicyAttached {
+            session_id: session_wallet.session_id().to_string(),
+            policy: session_wallet.policy(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Detach the session's policy
+    pub fn detach_policy(ctx: Context<DetachPolicy>) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+
+        session_wallet.set_policy(None);
+        session_wallet.last_activity = Clock::get()?.unix_timestamp;
+
+        emit!(PolicyAttached {
+            session_id: session_wallet.session_id().to_string(),
+            policy: None,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Register an agent account for the signing agent key
+    pub fn register_agent(ctx: Context<RegisterAgent>) -> Result<()> {
+        let agent_account = &mut ctx.accounts.agent_account;
+
+        agent_account.agent = ctx.accounts.agent.key();
+        agent_account.credit_limit = 0;
+        agent_account.outstanding_debt = 0;
+        agent_account.session_count = 0;
+        agent_account.bump = ctx.bumps.agent_account;
+
+        emit!(AgentRegistered {
+            agent: agent_account.agent,
+            agent_account: agent_account.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Set an agent's credit limit from its reputation (admin only)
+    pub fn set_credit_limit(ctx: Context<SetCreditLimit>, credit_limit: u64) -> Result<()> {
+        let agent_account = &mut ctx.accounts.agent_account;
+
+        agent_account.credit_limit = credit_limit;
+
+        emit!(CreditLimitSet {
+            agent: agent_account.agent,
+            credit_limit,
+            outstanding_debt: agent_account.outstanding_debt,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Open the agent's debt ledger for a mint so it can draw credit (admin only)
+    pub fn open_credit_line(ctx: Context<OpenCreditLine>) -> Result<()> {
+        let debt = &mut ctx.accounts.debt;
+
+        debt.agent_account = ctx.accounts.agent_account.key();
+        debt.mint = ctx.accounts.mint.key();
+        debt.outstanding = 0;
+        debt.total_borrowed = 0;
+        debt.total_repaid = 0;
+        debt.bump = ctx.bumps.debt;
+
+        Ok(())
+    }
+
+    /// Execute a purchase, fronting any shortfall from the treasury against the agent's credit line
+    ///
+    /// The agent must sign alongside the Operator, so an Owner repointing
+    /// `agent_pubkey` cannot borrow against another agent's line.
+    pub fn execute_purchase_on_credit(
+        ctx: Context<ExecutePurchaseOnCredit>,
+        amount: u64,
+        service_id: String,
+    ) -> Result<()> {
+        let session_wallet = &ctx.accounts.session_wallet;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
+        check_composition(&*session_wallet.load()?, &ctx.accounts.instructions)?;
+        let agent_account = &mut ctx.accounts.agent_account;
+        let debt = &mut ctx.accounts.debt;
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        let credit_available = agent_account
+            .credit_limit
+            .saturating_sub(agent_account.outstanding_debt);
+
+        let amount = apply_trial_discount(provider_account.as_ref(), &*session_wallet.load()?, &service_id, amount)?;
+
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider: ctx.accounts.service_provider_token_account.owner,
+            service_id: &service_id,
+            amount,
+            timestamp,
+            credit_available,
+            currency_balance: None,
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: ctx.accounts.service_provider_token_account.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        check_purchase(&*session_wallet.load()?, ctx.accounts.policy.as_ref(), &purchase)?;
+
+        track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+
+        charge_operator(
+            &*session_wallet.load()?,
+            ctx.accounts.authority.key(),
+            ctx.accounts.role_assignment.as_mut(),
+            amount,
+        )?;
+
+        let fee = collect_fee(
+            &*session_wallet.load()?,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &mut ctx.accounts.treasury,
+            &purchase,
+        )?;
+
+        let (session_id, from_balance) = {
+            let mut session = session_wallet.load_mut()?;
+            let from_balance = amount.min(session.current_balance);
+
+            session.current_balance -= from_balance;
+            record_purchase(
+                &mut session,
+                ctx.accounts.service_provider_token_account.owner,
+                &service_id,
+                amount,
+                timestamp,
+            )?;
+            (session.session_id().to_string(), from_balance)
+        };
+        let shortfall = amount - from_balance;
+
+        // The fee is paid from the session's part first; the rest of it stays
+        // in the treasury out of the credit draw
+        let fee_from_balance = fee.min(from_balance);
+        let fee_from_credit = fee - fee_from_balance;
+
+        if fee_from_balance > 0 {
+            transfer_from_session(
+                session_wallet,
+                &ctx.accounts.session_token_account,
+                &ctx.accounts.treasury_vault.to_account_info(),
+                &ctx.accounts.token_program,
+                fee_from_balance,
+            )?;
+        }
+
+        if from_balance > fee_from_balance {
+            transfer_from_session(
+                session_wallet,
+                &ctx.accounts.session_token_account,
+                &ctx.accounts.service_provider_token_account.to_account_info(),
+                &ctx.accounts.token_program,
+                from_balance - fee_from_balance,
+            )?;
+        }
+
+        if shortfall > 0 {
+            debt.outstanding = debt
+                .outstanding
+                .checked_add(shortfall)
+                .ok_or(ErrorCode::Overflow)?;
+            debt.total_borrowed = debt
+                .total_borrowed
+                .checked_add(shortfall)
+                .ok_or(ErrorCode::Overflow)?;
+            agent_account.outstanding_debt = agent_account
+                .outstanding_debt
+                .checked_add(shortfall)
+                .ok_or(ErrorCode::Overflow)?;
+
+            let treasury = &mut ctx.accounts.treasury;
+            treasury.total_disbursed = treasury
+                .total_disbursed
+                .checked_add(shortfall)
+                .ok_or(ErrorCode::Overflow)?;
+
+            transfer_from_treasury(
+                treasury,
+                &ctx.accounts.treasury_vault,
+                &ctx.accounts.service_provider_token_account.to_account_info(),
+                &ctx.accounts.token_program,
+                shortfall - fee_from_credit,
+            )?;
+
+            emit!(CreditDrawn {
+                session_id: session_id.clone(),
+                agent: agent_account.agent,
+                mint: debt.mint,
+                amount: shortfall,
+                outstanding: debt.outstanding,
+                timestamp,
+            });
+        }
+
+        emit!(PurchaseExecuted {
+            session_id,
+            service_id,
+            category_code,
+            amount,
+            remaining_balance: session_wallet.load()?.current_balance,
+            timestamp,
+        });
+
+        session_wallet.load_mut()?.record_event()?;
+
+        Ok(())
+    }
+
+    /// Open a netting channel that accumulates obligations between a session and a provider
+    pub fn open_netting_channel(ctx: Context<OpenNettingChannel>) -> Result<()> {
+        let session_wallet = ctx.accounts.session_wallet.load()?;
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+
+        let channel = &mut ctx.accounts.channel;
+        channel.session = ctx.accounts.session_wallet.key();
+        channel.provider = ctx.accounts.provider.key();
+        channel.provider_token_account = ctx.accounts.provider_token_account.key();
+        channel.session_owes = 0;
+        channel.provider_owes = 0;
+        channel.total_purchases = 0;
+        channel.total_refunds = 0;
+        channel.bump = ctx.bumps.channel;
+
+        emit!(NettingChannelOpened {
+            session_id: session_wallet.session_id().to_string(),
+            channel: channel.key(),
+            provider: channel.provider,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Record a purchase owed to the channel's provider, reserving it from the session balance
+    pub fn channel_purchase(
+        ctx: Context<ChannelPurchase>,
+        amount: u64,
+        service_id: String,
+    ) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        check_composition(&session_wallet, &ctx.accounts.instructions)?;
+        let channel = &mut ctx.accounts.channel;
+        let timestamp = Clock::get()?.unix_timestamp;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
+
+        let amount = apply_trial_discount(provider_account.as_ref(), &session_wallet, &service_id, amount)?;
+
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider: channel.provider,
+            service_id: &service_id,
+            amount,
+            timestamp,
+            credit_available: 0,
+            currency_balance: None,
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: session_wallet.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        check_purchase(&session_wallet, ctx.accounts.policy.as_ref(), &purchase)?;
+
+        track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+
+        charge_operator(
+            &session_wallet,
+            ctx.accounts.authority.key(),
+            ctx.accounts.role_assignment.as_mut(),
+            amount,
+        )?;
+
+        session_wallet.current_balance -= amount;
+        record_purchase(&mut session_wallet, channel.provider, &service_id, amount, timestamp)?;
+
+        let fee = collect_fee(
+            &session_wallet,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &mut ctx.accounts.treasury,
+            &purchase,
+        )?;
+
+        // The fee is paid now, so the channel only owes the provider's share
+        channel.session_owes = channel
+            .session_owes
+            .checked_add(amount - fee)
+            .ok_or(ErrorCode::Overflow)?;
+        channel.total_purchases = channel
+            .total_purchases
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(ChannelObligationRecorded {
+            channel: channel.key(),
+            service_id,
+            category_code,
+            session_owes: channel.session_owes,
+            provider_owes: channel.provider_owes,
+            amount,
+            is_refund: false,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+        drop(session_wallet);
+
+        if fee > 0 {
+            transfer_from_session(
+                &ctx.accounts.session_wallet,
+                &ctx.accounts.session_token_account,
+                &ctx.accounts.treasury_vault.to_account_info(),
+                &ctx.accounts.token_program,
+                fee,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Record a refund the channel's provider owes back to the session
+    pub fn channel_refund(ctx: Context<ChannelRefund>, amount: u64) -> Result<()> {
+        let channel = &mut ctx.accounts.channel;
+
+        channel.provider_owes = channel
+            .provider_owes
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        channel.total_refunds = channel
+            .total_refunds
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(ChannelObligationRecorded {
+            channel: channel.key(),
+            service_id: String::new(),
+            category_code: None,
+            session_owes: channel.session_owes,
+            provider_owes: channel.provider_owes,
+            amount,
+            is_refund: true,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Transfer only the net difference of the channel's obligations and reset them
+    pub fn settle_channel(ctx: Context<SettleChannel>) -> Result<()> {
+        let channel = &mut ctx.accounts.channel;
+
+        let session_owes = channel.session_owes;
+        let provider_owes = channel.provider_owes;
+
+        if session_owes >= provider_owes {
+            // Purchases were already reserved from the balance; only the net leaves the vault
+            let net_amount = session_owes - provider_owes;
+            if net_amount > 0 {
+                transfer_from_session(
+                    &ctx.accounts.session_wallet,
+                    &ctx.accounts.session_token_account,
+                    &ctx.accounts.provider_token_account.to_account_info(),
+                    &ctx.accounts.token_program,
+                    net_amount,
+                )?;
+            }
+        } else {
+            let net_amount = provider_owes - session_owes;
+            require_keys_eq!(
+                ctx.accounts.settler.key(),
+                channel.provider,
+                ErrorCode::Unauthorized
+            );
+
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.provider_token_account.to_account_info(),
+                to: ctx.accounts.session_token_account.to_account_info(),
+                authority: ctx.accounts.settler.to_account_info(),
+            };
+
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+            token::transfer(cpi_ctx, net_amount)?;
+        }
+
+        // Either way the session ends up `provider_owes` better off than its reserved balance.
+        // Refunds return money already spent, so they stay out of total_funded and the cap.
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        session_wallet.current_balance = session_wallet
+            .current_balance
+            .checked_add(provider_owes)
+            .ok_or(ErrorCode::Overflow)?;
+        session_wallet.total_channel_refunds = session_wallet
+            .total_channel_refunds
+            .checked_add(provider_owes)
+            .ok_or(ErrorCode::Overflow)?;
+        session_wallet.last_activity = Clock::get()?.unix_timestamp;
+
+        channel.session_owes = 0;
+        channel.provider_owes = 0;
+
+        emit!(ChannelSettled {
+            session_id: session_wallet.session_id().to_string(),
+            channel: channel.key(),
+            session_owed: session_owes,
+            provider_owed: provider_owes,
+            net_amount: session_owes.abs_diff(provider_owes),
+            provider_paid: provider_owes > session_owes,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Attach a concurrent Merkle tree (spl-account-compression) for compressed receipts
+    ///
+    /// The tree account must already be allocated for `max_depth` and
+    /// `max_buffer_size` and owned by the compression program.
+    pub fn init_receipt_tree(
+        ctx: Context<InitReceiptTree>,
+        max_depth: u32,
+        max_buffer_size: u32,
+    ) -> Result<()> {
+        {
+            let session_wallet = ctx.accounts.session_wallet.load()?;
+            require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+            require!(
+                session_wallet.receipt_tree().is_none(),
+                ErrorCode::ReceiptTreeAlreadySet
+            );
+        }
+
+        let mut data = INIT_EMPTY_MERKLE_TREE_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&max_depth.to_le_bytes());
+        data.extend_from_slice(&max_buffer_size.to_le_bytes());
+
+        invoke_compression(
+            &ctx.accounts.session_wallet,
+            &ctx.accounts.merkle_tree,
+            &ctx.accounts.noop_program,
+            &ctx.accounts.compression_program,
+            data,
+        )?;
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        session_wallet.set_receipt_tree(Some(ctx.accounts.merkle_tree.key()));
+        session_wallet.compressed_receipt_count = 0;
+
+        emit!(ReceiptTreeInitialized {
+            session_id: session_wallet.session_id().to_string(),
+            merkle_tree: ctx.accounts.merkle_tree.key(),
+            max_depth,
+            max_buffer_size,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Execute a service purchase and append its receipt to the session's Merkle tree
+    pub fn execute_purchase_compressed(
+        ctx: Context<ExecutePurchaseCompressed>,
+        amount: u64,
+        service_id: String,
+    ) -> Result<()> {
+        let session_wallet = &ctx.accounts.session_wallet;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
+        check_composition(&*session_wallet.load()?, &ctx.accounts.instructions)?;
+        let timestamp = Clock::get()?.unix_timestamp;
+        let provider = ctx.accounts.service_provider_token_account.owner;
+        let purchase_index = session_wallet.load()?.purchase_count;
+
+        let amount = apply_trial_discount(provider_account.as_ref(), &*session_wallet.load()?, &service_id, amount)?;
+
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider,
+            service_id: &service_id,
+            amount,
+            timestamp,
+            credit_available: 0,
+            currency_balance: None,
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: ctx.accounts.service_provider_token_account.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        check_purchase(&*session_wallet.load()?, ctx.accounts.policy.as_ref(), &purchase)?;
+
+        track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+
+        charge_operator(
+            &*session_wallet.load()?,
+            ctx.accounts.authority.key(),
+            ctx.accounts.role_assignment.as_mut(),
+            amount,
+        )?;
+
+        let fee = collect_fee(
+            &*session_wallet.load()?,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &mut ctx.accounts.treasury,
+            &purchase,
+        )?;
+
+        settle_purchase(
+            session_wallet,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.service_provider_token_account,
+            &ctx.accounts.token_program,
+            &service_id,
+            amount,
+            (&ctx.accounts.treasury_vault, fee),
+        )?;
+
+        let leaf = compressed_receipt_leaf(
+            &session_wallet.key(),
+            &provider,
+            &service_id,
+            amount,
+            purchase_index,
+            timestamp,
+        );
+
+        let mut data = APPEND_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&leaf);
+
+        invoke_compression(
+            session_wallet,
+            &ctx.accounts.merkle_tree,
+            &ctx.accounts.noop_program,
+            &ctx.accounts.compression_program,
+            data,
+        )?;
+
+        // Appends land in order, so the count is the new leaf's index
+        let mut session_wallet = session_wallet.load_mut()?;
+        let leaf_index = session_wallet.compressed_receipt_count;
+        session_wallet.compressed_receipt_count = leaf_index
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(PurchaseExecuted {
+            session_id: session_wallet.session_id().to_string(),
+            service_id: service_id.clone(),
+            category_code,
+            amount,
+            remaining_balance: session_wallet.current_balance,
+            timestamp,
+        });
+
+        emit!(CompressedReceiptAppended {
+            session_id: session_wallet.session_id().to_string(),
+            merkle_tree: ctx.accounts.merkle_tree.key(),
+            leaf,
+            leaf_index,
+            provider,
+            service_id,
+            category_code,
+            amount,
+            purchase_index,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Create a tree holding compressed sessions in one mint (admin only)
+    ///
+    /// A compressed session keeps its state in a leaf of this tree and its
+    /// balance in the tree's pooled vault, so it needs no session or token
+    /// account and costs no rent. This is the same state-tree model Light
+    /// Protocol uses, on the spl-account-compression program the receipt
+    /// trees already use. The tree account must already be allocated for
+    /// `max_depth` and `max_buffer_size` and owned by the compression program.
+    /// Requires the `compressed-sessions` feature.
+    pub fn init_compressed_session_tree(
+        ctx: Context<InitCompressedSessionTree>,
+        max_depth: u32,
+        max_buffer_size: u32,
+    ) -> Result<()> {
+        require!(cfg!(feature = "compressed-sessions"), ErrorCode::CompressedSessionsDisabled);
+
+        let session_tree = &mut ctx.accounts.session_tree;
+        session_tree.merkle_tree = ctx.accounts.merkle_tree.key();
+        session_tree.mint = ctx.accounts.mint.key();
+        session_tree.vault = ctx.accounts.vault.key();
+        session_tree.session_count = 0;
+        session_tree.bump = ctx.bumps.session_tree;
+
+        let mut data = INIT_EMPTY_MERKLE_TREE_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&max_depth.to_le_bytes());
+        data.extend_from_slice(&max_buffer_size.to_le_bytes());
+
+        invoke_session_tree(
+            session_tree,
+            &ctx.accounts.merkle_tree,
+            &ctx.accounts.noop_program,
+            &ctx.accounts.compression_program,
+            &[],
+            data,
+        )?;
+
+        emit!(CompressedSessionTreeInitialized {
+            merkle_tree: session_tree.merkle_tree,
+            mint: session_tree.mint,
+            vault: session_tree.vault,
+            max_depth,
+            max_buffer_size,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Open a compressed session funded from the treasury, as `initialize_session` does
+    ///
+    /// The new state is appended to the tree; its leaf index and state are in
+    /// the `CompressedSessionUpdated` event, and later instructions take both
+    /// back with a proof of the leaf.
+    pub fn initialize_compressed_session(
+        ctx: Context<InitializeCompressedSession>,
+        session_id: String,
+        initial_funding: u64,
+    ) -> Result<()> {
+        require!(cfg!(feature = "compressed-sessions"), ErrorCode::CompressedSessionsDisabled);
+        require!(
+            session_id.len() <= SessionWallet::MAX_SESSION_ID_LEN,
+            ErrorCode::SessionIdTooLong
+        );
+
+        disburse_from_treasury(
+            &mut ctx.accounts.treasury,
+            &ctx.accounts.treasury_vault,
+            &ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.token_program,
+            initial_funding,
+        )?;
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        let session_tree = &mut ctx.accounts.session_tree;
+        let leaf_index = u32::try_from(session_tree.session_count).map_err(|_| error!(ErrorCode::Overflow))?;
+        let session = CompressedSession {
+            authority: ctx.accounts.authority.key(),
+            session_id,
+            created_at: timestamp,
+            initial_balance: initial_funding,
+            current_balance: initial_funding,
+            total_funded: initial_funding,
+            total_spent: 0,
+            purchase_count: 0,
+            is_active: true,
+        };
+
+        let leaf = session.leaf(&session_tree.merkle_tree, leaf_index);
+        let mut data = APPEND_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&leaf);
+
+        invoke_session_tree(
+            session_tree,
+            &ctx.accounts.merkle_tree,
+            &ctx.accounts.noop_program,
+            &ctx.accounts.compression_program,
+            &[],
+            data,
+        )?;
+
+        // Only the tree PDA appends, so the count is the new leaf's index
+        session_tree.session_count = session_tree
+            .session_count
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(CompressedSessionUpdated {
+            merkle_tree: session_tree.merkle_tree,
+            leaf_index,
+            leaf,
+            session,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Add funds to a compressed session
+    ///
+    /// `session` is the state the leaf at `leaf_index` currently commits to
+    /// and `root` a recent root of the tree; the proof nodes follow as
+    /// remaining accounts.
+    pub fn fund_compressed_session<'info>(
+        ctx: Context<'_, '_, 'info, 'info, FundCompressedSession<'info>>,
+        session: CompressedSession,
+        root: [u8; 32],
+        leaf_index: u32,
+        amount: u64,
+    ) -> Result<()> {
+        require!(cfg!(feature = "compressed-sessions"), ErrorCode::CompressedSessionsDisabled);
+        require!(session.is_active, ErrorCode::SessionClosed);
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.funder.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        let mut next = session.clone();
+        next.current_balance = next
+            .current_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        next.total_funded = next
+            .total_funded
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        let session_tree = &ctx.accounts.session_tree;
+        let (data, leaf) = replace_compressed_session(&session_tree.merkle_tree, root, leaf_index, &session, &next);
+        invoke_session_tree(
+            session_tree,
+            &ctx.accounts.merkle_tree,
+            &ctx.accounts.noop_program,
+            &ctx.accounts.compression_program,
+            ctx.remaining_accounts,
+            data,
+        )?;
+
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        emit!(FundsAdded {
+            session_id: next.session_id.clone(),
+            amount,
+            new_balance: next.current_balance,
+            timestamp,
+        });
+
+        emit!(CompressedSessionUpdated {
+            merkle_tree: session_tree.merkle_tree,
+            leaf_index,
+            leaf,
+            session: next,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Execute a service purchase from a compressed session
+    ///
+    /// Mirrors `execute_purchase` for the session's authority: the circuit
+    /// breaker and platform fee apply. Compressed sessions carry no policy,
+    /// roles, budgets or per-provider history, so those checks and trial
+    /// discounts do not. State and proof are passed as for
+    /// `fund_compressed_session`.
+    pub fn execute_purchase_compressed_session<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecutePurchaseCompressedSession<'info>>,
+        session: CompressedSession,
+        root: [u8; 32],
+        leaf_index: u32,
+        amount: u64,
+        service_id: String,
+    ) -> Result<()> {
+        require!(cfg!(feature = "compressed-sessions"), ErrorCode::CompressedSessionsDisabled);
+        require_keys_eq!(ctx.accounts.authority.key(), session.authority, ErrorCode::Unauthorized);
+        require!(session.is_active, ErrorCode::SessionClosed);
+        require!(session.current_balance >= amount, ErrorCode::InsufficientBalance);
+
+        track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+
+        let provider = ctx.accounts.service_provider_token_account.owner;
+        let fee_bps = effective_fee_bps(
+            &ctx.accounts.fee_config,
+            None,
+            ctx.accounts.provider_fee_tier.as_deref(),
+            None,
+            provider,
+        )?;
+        let fee = (amount as u128 * fee_bps as u128 / BPS_DENOMINATOR as u128) as u64;
+        record_fee(&session.session_id, &mut ctx.accounts.treasury, &service_id, provider, fee, fee_bps)?;
+
+        let session_tree = &ctx.accounts.session_tree;
+        if fee > 0 {
+            transfer_from_session_tree(
+                session_tree,
+                &ctx.accounts.vault,
+                &ctx.accounts.treasury_vault.to_account_info(),
+                &ctx.accounts.token_program,
+                fee,
+            )?;
+        }
+        transfer_from_session_tree(
+            session_tree,
+            &ctx.accounts.vault,
+            &ctx.accounts.service_provider_token_account.to_account_info(),
+            &ctx.accounts.token_program,
+            amount - fee,
+        )?;
+
+        let mut next = session.clone();
+        next.current_balance -= amount;
+        next.total_spent = next
+            .total_spent
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        next.purchase_count = next
+            .purchase_count
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
+
+        let (data, leaf) = replace_compressed_session(&session_tree.merkle_tree, root, leaf_index, &session, &next);
+        invoke_session_tree(
+            session_tree,
+            &ctx.accounts.merkle_tree,
+            &ctx.accounts.noop_program,
+            &ctx.accounts.compression_program,
+            ctx.remaining_accounts,
+            data,
+        )?;
+
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        emit!(PurchaseExecuted {
+            session_id: next.session_id.clone(),
+            service_id,
+            category_code: None,
+            amount,
+            remaining_balance: next.current_balance,
+            timestamp,
+        });
+
+        emit!(CompressedSessionUpdated {
+            merkle_tree: session_tree.merkle_tree,
+            leaf_index,
+            leaf,
+            session: next,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Close a compressed session and refund its balance to the treasury
+    ///
+    /// State and proof are passed as for `fund_compressed_session`. The leaf
+    /// keeps the closed state, so the session cannot be used again.
+    pub fn close_compressed_session<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CloseCompressedSession<'info>>,
+        session: CompressedSession,
+        root: [u8; 32],
+        leaf_index: u32,
+    ) -> Result<()> {
+        require!(cfg!(feature = "compressed-sessions"), ErrorCode::CompressedSessionsDisabled);
+        require_keys_eq!(ctx.accounts.authority.key(), session.authority, ErrorCode::Unauthorized);
+        require!(session.is_active, ErrorCode::SessionClosed);
+
+        let remaining_balance = session.current_balance;
+        let session_tree = &ctx.accounts.session_tree;
+        if remaining_balance > 0 {
+            let treasury = &mut ctx.accounts.treasury;
+            treasury.total_refunded = treasury
+                .total_refunded
+                .checked_add(remaining_balance)
+                .ok_or(ErrorCode::Overflow)?;
+
+            transfer_from_session_tree(
+                session_tree,
+                &ctx.accounts.vault,
+                &ctx.accounts.treasury_vault.to_account_info(),
+                &ctx.accounts.token_program,
+                remaining_balance,
+            )?;
+        }
+
+        let mut next = session.clone();
+        next.current_balance = 0;
+        next.is_active = false;
+
+        let (data, leaf) = replace_compressed_session(&session_tree.merkle_tree, root, leaf_index, &session, &next);
+        invoke_session_tree(
+            session_tree,
+            &ctx.accounts.merkle_tree,
+            &ctx.accounts.noop_program,
+            &ctx.accounts.compression_program,
+            ctx.remaining_accounts,
+            data,
+        )?;
+
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        emit!(SessionClosed {
+            session_id: next.session_id.clone(),
+            refunded_amount: remaining_balance,
+            total_spent: next.total_spent,
+            timestamp,
+        });
+
+        emit!(CompressedSessionUpdated {
+            merkle_tree: session_tree.merkle_tree,
+            leaf_index,
+            leaf,
+            session: next,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Create a reusable session configuration owned by the caller
+    pub fn create_session_template(
+        ctx: Context<CreateSessionTemplate>,
+        template_id: String,
+        expiry_duration: i64,
+        expiry_grace_period: i64,
+        category_budgets: Vec<CategoryBudget>,
+    ) -> Result<()> {
+        require!(
+            template_id.len() <= SessionTemplate::MAX_TEMPLATE_ID_LEN,
+            ErrorCode::TemplateIdTooLong
+        );
+        require!(
+            expiry_duration >= 0 && expiry_grace_period >= 0,
+            ErrorCode::InvalidExpiry
+        );
+        validate_category_budgets(&category_budgets)?;
+
+        let template = &mut ctx.accounts.template;
+        template.authority = ctx.accounts.authority.key();
+        template.template_id = template_id;
+        template.policy = ctx.accounts.policy.as_ref().map(|policy| policy.key());
+        template.expiry_duration = expiry_duration;
+        template.expiry_grace_period = expiry_grace_period;
+        template.category_budgets = category_budgets
+            .into_iter()
+            .map(|budget| CategoryBudget { spent: 0, ..budget })
+            .collect();
+        template.bump = ctx.bumps.template;
+
+        emit!(SessionTemplateCreated {
+            template: template.key(),
+            authority: template.authority,
+            policy: template.policy,
+            expiry_duration,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Initialize a new session wallet configured from a template
+    pub fn initialize_session_from_template(
+        ctx: Context<InitializeSessionFromTemplate>,
+        session_id: String,
+        initial_funding: u64,
+    ) -> Result<()> {
+        // Sessions opened for a registered agent are refused while it owes credit
+        let agent_pubkey = match &ctx.accounts.agent_account {
+            Some(agent_account) => {
+                require!(
+                    agent_account.outstanding_debt == 0,
+                    ErrorCode::DebtOutstanding
+                );
+                Some(agent_account.agent)
+            }
+            None => None,
+        };
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_init()?;
+        let template = &ctx.accounts.template;
+
+        open_session(
+            &mut session_wallet,
+            ctx.accounts.authority.key(),
+            &session_id,
+            ctx.accounts.treasury.mint,
+            initial_funding,
+            ctx.bumps.session_wallet,
+        )?;
+
+        session_wallet.set_agent_pubkey(agent_pubkey);
+        session_wallet.set_policy(template.policy);
+        session_wallet.set_category_budgets(&template.category_budgets);
+        session_wallet.expiry_grace_period = template.expiry_grace_period;
+        if template.expiry_duration > 0 {
+            session_wallet.expires_at = session_wallet
+                .created_at
+                .checked_add(template.expiry_duration)
+                .ok_or(ErrorCode::Overflow)?;
+        }
+
+        // Transfer initial funding from treasury vault to session wallet
+        disburse_from_treasury(
+            &mut ctx.accounts.treasury,
+            &ctx.accounts.treasury_vault,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.token_program,
+            initial_funding,
+        )?;
+
+        emit!(SessionCreated {
+            session_id: session_id.clone(),
+            pda: ctx.accounts.session_wallet.key(),
+            initial_funding,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        emit!(SessionCreatedFromTemplate {
+            session_id,
+            template: template.key(),
+            expires_at: session_wallet.expires_at,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Push back a session's expiry, optionally topping it up from the authority
+    pub fn extend_session(
+        ctx: Context<ExtendSession>,
+        new_expiry: i64,
+        grace_period: i64,
+        additional_funding: u64,
+    ) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+        require!(
+            session_wallet.expires_at != 0
+                && new_expiry > session_wallet.expires_at
+                && new_expiry > timestamp,
+            ErrorCode::InvalidExpiry
+        );
+        require!(grace_period >= 0, ErrorCode::InvalidExpiry);
+
+        let previous_expiry = session_wallet.expires_at;
+        session_wallet.expires_at = new_expiry;
+        session_wallet.expiry_grace_period = grace_period;
+        session_wallet.last_activity = timestamp;
+
+        if additional_funding > 0 {
+            let (Some(authority_token_account), Some(session_token_account)) = (
+                ctx.accounts.authority_token_account.as_ref(),
+                ctx.accounts.session_token_account.as_ref(),
+            ) else {
+                return err!(ErrorCode::MissingFundingAccounts);
+            };
+            if session_wallet.exceeds_funding_cap(additional_funding) {
+                return reject_funding(
+                    &session_wallet,
+                    ctx.accounts.authority.key(),
+                    additional_funding,
+                    RejectionReason::FundingCapExceeded,
+                );
+            }
+
+            session_wallet.current_balance = session_wallet
+                .current_balance
+                .checked_add(additional_funding)
+                .ok_or(ErrorCode::Overflow)?;
+
+            session_wallet.total_funded = session_wallet
+                .total_funded
+                .checked_add(additional_funding)
+                .ok_or(ErrorCode::Overflow)?;
+
+            let cpi_accounts = Transfer {
+                from: authority_token_account.to_account_info(),
+                to: session_token_account.to_account_info(),
+                authority: ctx.accounts.authority.to_account_info(),
+            };
+
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+            token::transfer(cpi_ctx, additional_funding)?;
+
+            emit!(FundsAdded {
+                session_id: session_wallet.session_id().to_string(),
+                amount: additional_funding,
+                new_balance: session_wallet.current_balance,
+                timestamp,
+            });
+        }
+
+        emit!(SessionExtended {
+            session_id: session_wallet.session_id().to_string(),
+            previous_expiry,
+            new_expiry,
+            grace_period,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Enable or disable the velocity anomaly lock; a window of 0 disables it
+    pub fn configure_anomaly_lock(
+        ctx: Context<ConfigureAnomalyLock>,
+        velocity_window: i64,
+        velocity_multiple: u32,
+    ) -> Result<()> {
+        require!(
+            velocity_window >= 0 && (velocity_window == 0 || velocity_multiple > 0),
+            ErrorCode::InvalidAnomalyConfig
+        );
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        session_wallet.velocity_window = velocity_window;
+        session_wallet.velocity_multiple = velocity_multiple;
+        session_wallet.window_start = 0;
+        session_wallet.window_spent = 0;
+        session_wallet.average_window_spend = 0;
+
+        emit!(AnomalyLockConfigured {
+            session_id: session_wallet.session_id().to_string(),
+            velocity_window,
+            velocity_multiple,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Lift an anomaly suspension after the authority has reviewed it
+    pub fn resume_session(ctx: Context<ConfigureAnomalyLock>) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        require!(session_wallet.is_suspended(), ErrorCode::SessionNotSuspended);
+
+        // Start a fresh window so the spend that tripped the lock does not trip it again
+        session_wallet.set_suspended(false);
+        session_wallet.window_start = timestamp;
+        session_wallet.window_spent = 0;
+        session_wallet.last_activity = timestamp;
+
+        emit!(SessionResumed {
+            session_id: session_wallet.session_id().to_string(),
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Let the session hold a balance in an additional mint
+    pub fn add_session_currency(ctx: Context<AddSessionCurrency>) -> Result<()> {
+        let session_wallet = ctx.accounts.session_wallet.load()?;
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+        require_keys_neq!(
+            ctx.accounts.mint.key(),
+            session_wallet.mint,
+            ErrorCode::InvalidCurrencyBalance
+        );
+
+        let mut currency_balance = ctx.accounts.currency_balance.load_init()?;
+        currency_balance.session = ctx.accounts.session_wallet.key();
+        currency_balance.mint = ctx.accounts.mint.key();
+        currency_balance.vault = ctx.accounts.vault.key();
+        currency_balance.bump = ctx.bumps.currency_balance;
+
+        emit!(SessionCurrencyAdded {
+            session_id: session_wallet.session_id().to_string(),
+            mint: currency_balance.mint,
+            vault: currency_balance.vault,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Add funds to one of the session's secondary mint balances
+    pub fn fund_session_currency(ctx: Context<FundSessionCurrency>, amount: u64) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+
+        if !session_wallet.is_active() {
+            return reject_funding(
+                &session_wallet,
+                ctx.accounts.funder.key(),
+                amount,
+                RejectionReason::SessionClosed,
+            );
+        }
+
+        let mut currency_balance = ctx.accounts.currency_balance.load_mut()?;
+        currency_balance.current_balance = currency_balance
+            .current_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        currency_balance.total_funded = currency_balance
+            .total_funded
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        session_wallet.last_activity = Clock::get()?.unix_timestamp;
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.funder.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        token::transfer(cpi_ctx, amount)?;
+
+        emit!(SessionCurrencyFunded {
+            session_id: session_wallet.session_id().to_string(),
+            mint: currency_balance.mint,
+            amount,
+            new_balance: currency_balance.current_balance,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Return a secondary mint balance to the authority
+    pub fn withdraw_session_currency(
+        ctx: Context<WithdrawSessionCurrency>,
+        amount: u64,
+    ) -> Result<()> {
+        let (mint, new_balance) = {
+            let mut currency_balance = ctx.accounts.currency_balance.load_mut()?;
+            require!(
+                currency_balance.current_balance >= amount,
+                ErrorCode::InsufficientBalance
+            );
+            currency_balance.current_balance -= amount;
+            (currency_balance.mint, currency_balance.current_balance)
+        };
+
+        transfer_from_session(
+            &ctx.accounts.session_wallet,
+            &ctx.accounts.vault,
+            &ctx.accounts.destination.to_account_info(),
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        emit!(SessionCurrencyWithdrawn {
+            session_id: ctx.accounts.session_wallet.load()?.session_id().to_string(),
+            mint,
+            amount,
+            new_balance,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Create the swap configuration naming the program used for conversions
+    pub fn initialize_swap_config(
+        ctx: Context<InitializeSwapConfig>,
+        swap_program: Pubkey,
+    ) -> Result<()> {
+        let swap_config = &mut ctx.accounts.swap_config;
+        swap_config.swap_program = swap_program;
+        swap_config.bump = ctx.bumps.swap_config;
+
+        emit!(SwapProgramSet {
+            swap_program,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Point conversions at a different swap program
+    pub fn set_swap_program(ctx: Context<SetSwapProgram>, swap_program: Pubkey) -> Result<()> {
+        ctx.accounts.swap_config.swap_program = swap_program;
+
+        emit!(SwapProgramSet {
+            swap_program,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Pay a provider that wants a different mint by swapping session funds
+    /// through the configured swap program first.
+    ///
+    /// `quoted_amount_in` is the session-mint input the caller was quoted for
+    /// `amount` of the provider's mint; the swap may use at most the policy's
+    /// `MaxSlippage` on top of it. `swap_data` and the remaining accounts are
+    /// passed to the swap program as is; the only session-owned token
+    /// accounts they may mark writable are `session_token_account` and
+    /// `swap_destination`. Any output above `amount` stays in
+    /// `swap_destination`. A trial discount lowers `amount`, so quote for the
+    /// discounted price.
+    pub fn execute_purchase_with_swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecutePurchaseWithSwap<'info>>,
+        amount: u64,
+        service_id: String,
+        quoted_amount_in: u64,
+        swap_data: Vec<u8>,
+    ) -> Result<()> {
+        require!(
+            service_id.len() <= PurchaseReceipt::MAX_SERVICE_ID_LEN,
+            ErrorCode::ServiceIdTooLong
+        );
+
+        check_composition(&*ctx.accounts.session_wallet.load()?, &ctx.accounts.instructions)?;
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        let slippage_bps = ctx
+            .accounts
+            .policy
+            .max_slippage_bps()
+            .ok_or(ErrorCode::SwapNotAllowed)?;
+        let max_amount_in = (quoted_amount_in as u128)
+            .checked_mul((BPS_DENOMINATOR + slippage_bps) as u128)
+            .map(|scaled| scaled / BPS_DENOMINATOR as u128)
+            .and_then(|max| u64::try_from(max).ok())
+            .ok_or(ErrorCode::Overflow)?;
+
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
+        let amount = apply_trial_discount(
+            provider_account.as_ref(),
+            &*ctx.accounts.session_wallet.load()?,
+            &service_id,
+            amount,
+        )?;
+
+        // The policy sees the worst-case input in session mint units
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider: ctx.accounts.service_provider_token_account.owner,
+            service_id: &service_id,
+            amount: max_amount_in,
+            timestamp,
+            credit_available: 0,
+            currency_balance: None,
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: ctx.accounts.service_provider_token_account.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        check_purchase(
+            &*ctx.accounts.session_wallet.load()?,
+            Some(&ctx.accounts.policy),
+            &purchase,
+        )?;
+
+        track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, max_amount_in)?;
+
+        let source_before = ctx.accounts.session_token_account.amount;
+        let destination_before = ctx.accounts.swap_destination.amount;
+
+        // The session signs the swap, so no other account of its may be writable in it
+        check_forwarded_accounts(
+            ctx.accounts.session_wallet.key(),
+            ctx.remaining_accounts,
+            &[ctx.accounts.session_token_account.key(), ctx.accounts.swap_destination.key()],
+        )?;
+        invoke_as_session(
+            &ctx.accounts.session_wallet,
+            &ctx.accounts.swap_program,
+            ctx.remaining_accounts,
+            swap_data,
+        )?;
+
+        ctx.accounts.session_token_account.reload()?;
+        ctx.accounts.swap_destination.reload()?;
+
+        let amount_in = source_before.saturating_sub(ctx.accounts.session_token_account.amount);
+        let amount_out = ctx
+            .accounts
+            .swap_destination
+            .amount
+            .saturating_sub(destination_before);
+        require!(amount_in <= max_amount_in, ErrorCode::SlippageExceeded);
+        require!(amount_out >= amount, ErrorCode::SlippageExceeded);
+
+        charge_operator(
+            &*ctx.accounts.session_wallet.load()?,
+            ctx.accounts.authority.key(),
+            ctx.accounts.role_assignment.as_mut(),
+            amount_in,
+        )?;
+
+        {
+            let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+            session_wallet.current_balance = session_wallet
+                .current_balance
+                .checked_sub(amount_in)
+                .ok_or(ErrorCode::InsufficientBalance)?;
+            record_purchase(
+                &mut session_wallet,
+                ctx.accounts.service_provider_token_account.owner,
+                &service_id,
+                amount_in,
+                timestamp,
+            )?;
+        }
+
+        // The fee is cut from the output, in the provider's mint
+        let fee = collect_fee(
+            &*ctx.accounts.session_wallet.load()?,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &mut ctx.accounts.treasury,
+            &PurchaseContext { amount, ..purchase },
+        )?;
+        if fee > 0 {
+            transfer_from_session(
+                &ctx.accounts.session_wallet,
+                &ctx.accounts.swap_destination,
+                &ctx.accounts.treasury_vault.to_account_info(),
+                &ctx.accounts.token_program,
+                fee,
+            )?;
+        }
+
+        transfer_from_session(
+            &ctx.accounts.session_wallet,
+            &ctx.accounts.swap_destination,
+            &ctx.accounts.service_provider_token_account.to_account_info(),
+            &ctx.accounts.token_program,
+            amount - fee,
+        )?;
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+
+        emit!(PurchaseSwapped {
+            session_id: session_wallet.session_id().to_string(),
+            service_id: service_id.clone(),
+            input_mint: session_wallet.mint,
+            output_mint: ctx.accounts.swap_destination.mint,
+            amount_in,
+            amount_out,
+            timestamp,
+        });
+
+        emit!(PurchaseExecuted {
+            session_id: session_wallet.session_id().to_string(),
+            service_id,
+            category_code,
+            amount: amount_in,
+            remaining_balance: session_wallet.current_balance,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Reserve funds for a purchase that is only paid once `verifier` attests
+    /// to a successful call whose output hashes to `output_hash`. Escrowed
+    /// purchases pay list price; trial discounts apply to immediate payments.
+    pub fn open_conditional_escrow(
+        ctx: Context<OpenConditionalEscrow>,
+        escrow_id: u64,
+        amount: u64,
+        service_id: String,
+        verifier: Pubkey,
+        output_hash: [u8; 32],
+        expires_at: i64,
+    ) -> Result<()> {
+        require!(
+            service_id.len() <= PurchaseReceipt::MAX_SERVICE_ID_LEN,
+            ErrorCode::ServiceIdTooLong
+        );
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        require!(expires_at > timestamp, ErrorCode::InvalidExpiry);
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
+        check_composition(&session_wallet, &ctx.accounts.instructions)?;
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider: ctx.accounts.service_provider_token_account.owner,
+            service_id: &service_id,
+            amount,
+            timestamp,
+            credit_available: 0,
+            currency_balance: None,
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: ctx.accounts.service_provider_token_account.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        check_purchase(&session_wallet, ctx.accounts.policy.as_ref(), &purchase)?;
+
+        track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+
+        charge_operator(
+            &session_wallet,
+            ctx.accounts.authority.key(),
+            ctx.accounts.role_assignment.as_mut(),
+            amount,
+        )?;
+
+        let (fee, fee_bps) = purchase_fee(
+            &session_wallet,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &purchase,
+        )?;
+
+        // Funds stay in the session token account, only the accounting moves
+        session_wallet.current_balance -= amount;
+        session_wallet.escrowed_balance = session_wallet
+            .escrowed_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        session_wallet.last_activity = timestamp;
+
+        let escrow = &mut ctx.accounts.escrow;
+        escrow.session = ctx.accounts.session_wallet.key();
+        escrow.escrow_id = escrow_id;
+        escrow.provider_token_account = ctx.accounts.service_provider_token_account.key();
+        escrow.verifier = verifier;
+        escrow.amount = amount;
+        escrow.service_id = service_id;
+        escrow.category_code = category_code;
+        escrow.fee = fee;
+        escrow.fee_bps = fee_bps;
+        escrow.output_hash = output_hash;
+        escrow.created_at = timestamp;
+        escrow.expires_at = expires_at;
+        escrow.bump = ctx.bumps.escrow;
+
+        emit!(EscrowOpened {
+            session_id: session_wallet.session_id().to_string(),
+            escrow: escrow.key(),
+            verifier,
+            amount,
+            expires_at,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Pay an escrow to its provider. The preceding instruction must be an
+    /// ed25519 verification of `attestation_message` by the escrow's verifier
+    /// with a 2xx status; anyone holding the attestation may submit it.
+    pub fn conditional_capture(ctx: Context<ConditionalCapture>, status_code: u16) -> Result<()> {
+        let timestamp = Clock::get()?.unix_timestamp;
+        let escrow = &ctx.accounts.escrow;
+
+        require!(timestamp <= escrow.expires_at, ErrorCode::EscrowExpired);
+        require!((200..300).contains(&status_code), ErrorCode::AttestationRejected);
+
+        let message = attestation_message(&escrow.key(), status_code, &escrow.output_hash);
+        verify_ed25519_instruction(&ctx.accounts.instructions, &escrow.verifier, &message)?;
+
+        {
+            let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+            session_wallet.escrowed_balance = session_wallet
+                .escrowed_balance
+                .checked_sub(escrow.amount)
+                .ok_or(ErrorCode::Overflow)?;
+            record_purchase(
+                &mut session_wallet,
+                ctx.accounts.service_provider_token_account.owner,
+                &escrow.service_id,
+                escrow.amount,
+                timestamp,
+            )?;
+        }
+
+        record_fee(
+            ctx.accounts.session_wallet.load()?.session_id(),
+            &mut ctx.accounts.treasury,
+            &escrow.service_id,
+            ctx.accounts.service_provider_token_account.owner,
+            escrow.fee,
+            escrow.fee_bps,
+        )?;
+        if escrow.fee > 0 {
+            transfer_from_session(
+                &ctx.accounts.session_wallet,
+                &ctx.accounts.session_token_account,
+                &ctx.accounts.treasury_vault.to_account_info(),
+                &ctx.accounts.token_program,
+                escrow.fee,
+            )?;
+        }
+
+        transfer_from_session(
+            &ctx.accounts.session_wallet,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.service_provider_token_account.to_account_info(),
+            &ctx.accounts.token_program,
+            escrow.amount - escrow.fee,
+        )?;
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+
+        emit!(EscrowCaptured {
+            session_id: session_wallet.session_id().to_string(),
+            escrow: escrow.key(),
+            status_code,
+            amount: escrow.amount,
+            timestamp,
+        });
+
+        emit!(PurchaseExecuted {
+            session_id: session_wallet.session_id().to_string(),
+            service_id: escrow.service_id.clone(),
+            category_code: escrow.category_code,
+            amount: escrow.amount,
+            remaining_balance: session_wallet.current_balance,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Return an expired escrow's funds to the session balance
+    pub fn refund_escrow(ctx: Context<RefundEscrow>) -> Result<()> {
+        let timestamp = Clock::get()?.unix_timestamp;
+        let escrow = &ctx.accounts.escrow;
+
+        require!(timestamp > escrow.expires_at, ErrorCode::EscrowNotExpired);
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        session_wallet.escrowed_balance = session_wallet
+            .escrowed_balance
+            .checked_sub(escrow.amount)
+            .ok_or(ErrorCode::Overflow)?;
+        session_wallet.current_balance = session_wallet
+            .current_balance
+            .checked_add(escrow.amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(EscrowRefunded {
+            session_id: session_wallet.session_id().to_string(),
+            escrow: escrow.key(),
+            amount: escrow.amount,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Open the ledger a provider's accrued earnings for a mint collect in
+    pub fn open_payout_ledger(ctx: Context<OpenPayoutLedger>) -> Result<()> {
+        let mut ledger = ctx.accounts.ledger.load_init()?;
+        ledger.provider = ctx.accounts.provider.key();
+        ledger.mint = ctx.accounts.mint.key();
+        ledger.vault = ctx.accounts.vault.key();
+        ledger.last_claimed_at = Clock::get()?.unix_timestamp;
+        ledger.bump = ctx.bumps.ledger;
+
+        emit!(PayoutLedgerOpened {
+            provider: ledger.provider,
+            mint: ledger.mint,
+            ledger: ctx.accounts.ledger.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Execute a service purchase, accruing the payment to the provider's
+    /// ledger instead of writing to the provider's token account
+    pub fn execute_purchase_accrued(
+        ctx: Context<ExecutePurchaseAccrued>,
+        amount: u64,
+        service_id: String,
+    ) -> Result<()> {
+        let session_wallet = &ctx.accounts.session_wallet;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
+        check_composition(&*session_wallet.load()?, &ctx.accounts.instructions)?;
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        let amount = apply_trial_discount(provider_account.as_ref(), &*session_wallet.load()?, &service_id, amount)?;
+
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider: ctx.accounts.ledger.load()?.provider,
+            service_id: &service_id,
+            amount,
+            timestamp,
+            credit_available: 0,
+            currency_balance: None,
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: ctx.accounts.ledger.load()?.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        check_purchase(&*session_wallet.load()?, ctx.accounts.policy.as_ref(), &purchase)?;
+
+        track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+
+        charge_operator(
+            &*session_wallet.load()?,
+            ctx.accounts.authority.key(),
+            ctx.accounts.role_assignment.as_mut(),
+            amount,
+        )?;
+
+        let fee = collect_fee(
+            &*session_wallet.load()?,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &mut ctx.accounts.treasury,
+            &purchase,
+        )?;
+
+        settle_purchase(
+            session_wallet,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.vault,
+            &ctx.accounts.token_program,
+            &service_id,
+            amount,
+            (&ctx.accounts.treasury_vault, fee),
+        )?;
+
+        let mut ledger = ctx.accounts.ledger.load_mut()?;
+        ledger.accrued = ledger
+            .accrued
+            .checked_add(amount - fee)
+            .ok_or(ErrorCode::Overflow)?;
+        ledger.total_accrued = ledger
+            .total_accrued
+            .checked_add(amount - fee)
+            .ok_or(ErrorCode::Overflow)?;
+        ledger.purchase_count = ledger
+            .purchase_count
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
+
+        let mut session_wallet = session_wallet.load_mut()?;
+
+        emit!(PurchaseExecuted {
+            session_id: session_wallet.session_id().to_string(),
+            service_id,
+            category_code,
+            amount,
+            remaining_balance: session_wallet.current_balance,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Pay out everything accrued on a provider's ledger
+    pub fn claim_payout(ctx: Context<ClaimPayout>) -> Result<()> {
+        let amount = {
+            let mut ledger = ctx.accounts.ledger.load_mut()?;
+            let amount = ledger.accrued;
+            require!(amount > 0, ErrorCode::NothingToClaim);
+
+            ledger.accrued = 0;
+            ledger.total_claimed = ledger
+                .total_claimed
+                .checked_add(amount)
+                .ok_or(ErrorCode::Overflow)?;
+            ledger.last_claimed_at = Clock::get()?.unix_timestamp;
+            amount
+        };
+
+        transfer_from_ledger(
+            &ctx.accounts.ledger,
+            &ctx.accounts.vault,
+            &ctx.accounts.destination.to_account_info(),
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        let ledger = ctx.accounts.ledger.load()?;
+
+        emit!(PayoutClaimed {
+            provider: ledger.provider,
+            mint: ledger.mint,
+            amount,
+            total_claimed: ledger.total_claimed,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Create the next vault shard. Shards are session-owned token accounts in
+    /// the session mint, so any of them can be passed as `session_token_account`
+    /// to spread parallel purchases across separate write locks.
+    pub fn create_vault_shard(ctx: Context<CreateVaultShard>, index: u8) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+        require!(
+            index == session_wallet.shard_count && (index as usize) < MAX_VAULT_SHARDS,
+            ErrorCode::InvalidShard
+        );
+
+        session_wallet.shard_count += 1;
+
+        emit!(VaultShardCreated {
+            session_id: session_wallet.session_id().to_string(),
+            shard: ctx.accounts.shard.key(),
+            index,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Even out the session's funds across the vaults passed as remaining
+    /// accounts, or move everything into the first one when `consolidate` is
+    /// set (e.g. before closing). The vaults must be the session's associated
+    /// token account or its created shards; funds never leave the session.
+    pub fn rebalance_shards<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RebalanceShards<'info>>,
+        consolidate: bool,
+    ) -> Result<()> {
+        let session_wallet = &ctx.accounts.session_wallet;
+        let (session_id, mint, shard_count) = {
+            let session = session_wallet.load()?;
+            (session.session_id().to_string(), session.mint, session.shard_count)
+        };
+        require!(ctx.remaining_accounts.len() >= 2, ErrorCode::InvalidShard);
+
+        let mut session_vaults = vec![get_associated_token_address(&session_wallet.key(), &mint)];
+        session_vaults.extend((0..shard_count).map(|index| {
+            Pubkey::find_program_address(&[SHARD_SEED, session_wallet.key().as_ref(), &[index]], &crate::ID).0
+        }));
+
+        let mut vaults = Vec::with_capacity(ctx.remaining_accounts.len());
+        for account in ctx.remaining_accounts.iter() {
+            let vault = Account::<TokenAccount>::try_from(account)?;
+            require!(
+                session_vaults.contains(&vault.key())
+                    && vault.owner == session_wallet.key()
+                    && vault.mint == mint,
+                ErrorCode::InvalidShard
+            );
+            require!(
+                vaults.iter().all(|other: &Account<TokenAccount>| other.key() != vault.key()),
+                ErrorCode::InvalidShard
+            );
+            vaults.push(vault);
+        }
+
+        let total = vaults
+            .iter()
+            .try_fold(0u64, |total, vault| total.checked_add(vault.amount))
+            .ok_or(ErrorCode::Overflow)?;
+        let targets: Vec<u64> = (0..vaults.len())
+            .map(|i| {
+                if consolidate {
+                    if i == 0 { total } else { 0 }
+                } else {
+                    // The first vault absorbs the rounding remainder
+                    let share = total / vaults.len() as u64;
+                    if i == 0 { share + total % vaults.len() as u64 } else { share }
+                }
+            })
+            .collect();
+
+        // Pair every surplus with the next deficit until balanced
+        let mut moved = 0u64;
+        let mut amounts: Vec<u64> = vaults.iter().map(|vault| vault.amount).collect();
+        let mut to = 0;
+        for from in 0..vaults.len() {
+            while amounts[from] > targets[from] {
+                while amounts[to] >= targets[to] {
+                    to += 1;
+                }
+                let amount = (amounts[from] - targets[from]).min(targets[to] - amounts[to]);
+                transfer_from_session(
+                    session_wallet,
+                    &vaults[from],
+                    &vaults[to].to_account_info(),
+                    &ctx.accounts.token_program,
+                    amount,
+                )?;
+                amounts[from] -= amount;
+                amounts[to] += amount;
+                moved += amount;
+            }
+        }
+
+        emit!(ShardsRebalanced {
+            session_id,
+            vaults: vaults.len() as u8,
+            total,
+            moved,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Record a batch of purchases to be executed across as many
+    /// transactions as compute allows
+    pub fn open_batch(
+        ctx: Context<OpenBatch>,
+        batch_id: u64,
+        entries_hash: [u8; 32],
+        entry_count: u16,
+    ) -> Result<()> {
+        require!(entry_count > 0, ErrorCode::InvalidBatch);
+
+        let batch_state = &mut ctx.accounts.batch_state;
+        batch_state.session = ctx.accounts.session_wallet.key();
+        batch_state.batch_id = batch_id;
+        batch_state.entries_hash = entries_hash;
+        batch_state.entry_count = entry_count;
+        batch_state.cursor = 0;
+        batch_state.total_amount = 0;
+        batch_state.bump = ctx.bumps.batch_state;
+
+        emit!(BatchOpened {
+            session_id: ctx.accounts.session_wallet.load()?.session_id().to_string(),
+            batch: batch_state.key(),
+            entry_count,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Execute a list of purchases. The remaining accounts are
+    /// `LEG_ACCOUNT_COUNT` accounts for each entry still to run, in order:
+    /// its provider token account, the provider's registry PDA, the
+    /// provider's listing PDA for the entry's service id, then the provider's
+    /// fee tier PDA.
+    ///
+    /// Without a `batch_state` every entry runs or the transaction fails.
+    /// With one, `entries` must hash to the batch's `entries_hash`; entries
+    /// run from the stored cursor until compute runs low, and the cursor is
+    /// saved so a follow-up transaction can resume. The batch state is closed
+    /// to the authority once every entry has run.
+    pub fn execute_purchases_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecutePurchasesBatch<'info>>,
+        entries: Vec<BatchPurchase>,
+    ) -> Result<()> {
+        check_composition(&*ctx.accounts.session_wallet.load()?, &ctx.accounts.instructions)?;
+
+        let start = match &ctx.accounts.batch_state {
+            Some(batch_state) => {
+                require!(
+                    batch_entries_hash(&entries) == batch_state.entries_hash
+                        && entries.len() == batch_state.entry_count as usize,
+                    ErrorCode::InvalidBatch
+                );
+                batch_state.cursor as usize
+            }
+            None => 0,
+        };
+        let pending = &entries[start..];
+        require!(
+            ctx.remaining_accounts.len() == pending.len() * LEG_ACCOUNT_COUNT,
+            ErrorCode::InvalidBatch
+        );
+
+        let resumable = ctx.accounts.batch_state.is_some();
+        let mut processed = 0usize;
+        let mut amount_processed = 0u64;
+        for (entry, accounts) in pending.iter().zip(ctx.remaining_accounts.chunks(LEG_ACCOUNT_COUNT)) {
+            if resumable && sol_remaining_compute_units() < BATCH_ENTRY_COMPUTE_UNITS {
+                break;
+            }
+
+            let leg_accounts = load_leg_accounts(accounts, entry, ErrorCode::InvalidBatch)?;
+            let service_provider_token_account = &leg_accounts.provider_token_account;
+            let amount = apply_trial_discount(
+                leg_accounts.provider_account.as_ref(),
+                &*ctx.accounts.session_wallet.load()?,
+                &entry.service_id,
+                entry.amount,
+            )?;
+
+            let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &entry.service_id);
+            let purchase = PurchaseContext {
+                provider: service_provider_token_account.owner,
+                service_id: &entry.service_id,
+                amount,
+                timestamp: Clock::get()?.unix_timestamp,
+                credit_available: 0,
+                currency_balance: None,
+                category_code,
+                service_listing: leg_accounts.service_listing.as_ref(),
+                payout_mint: service_provider_token_account.mint,
+                provider_account: leg_accounts.provider_account.as_ref(),
+            };
+            check_purchase(
+                &*ctx.accounts.session_wallet.load()?,
+                ctx.accounts.policy.as_ref(),
+                &purchase,
+            )?;
+
+            track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+
+            charge_operator(
+                &*ctx.accounts.session_wallet.load()?,
+                ctx.accounts.authority.key(),
+                ctx.accounts.role_assignment.as_mut(),
+                amount,
+            )?;
+
+            let fee = collect_fee(
+                &*ctx.accounts.session_wallet.load()?,
+                &ctx.accounts.fee_config,
+                ctx.accounts.agent_fee_tier.as_deref(),
+                leg_accounts.provider_fee_tier.as_ref(),
+                &mut ctx.accounts.treasury,
+                &purchase,
+            )?;
+
+            settle_purchase(
+                &ctx.accounts.session_wallet,
+                &ctx.accounts.session_token_account,
+                service_provider_token_account,
+                &ctx.accounts.token_program,
+                &entry.service_id,
+                amount,
+                (&ctx.accounts.treasury_vault, fee),
+            )?;
+
+            processed += 1;
+            amount_processed = amount_processed
+                .checked_add(amount)
+                .ok_or(ErrorCode::Overflow)?;
+        }
+
+        let cursor = start + processed;
+        let complete = cursor == entries.len();
+        let total_amount = match ctx.accounts.batch_state.as_mut() {
+            Some(batch_state) => {
+                batch_state.cursor = cursor as u16;
+                batch_state.total_amount = batch_state
+                    .total_amount
+                    .checked_add(amount_processed)
+                    .ok_or(ErrorCode::Overflow)?;
+                batch_state.total_amount
+            }
+            None => amount_processed,
+        };
+
+        emit!(BatchProgress {
+            session_id: ctx.accounts.session_wallet.load()?.session_id().to_string(),
+            batch: ctx.accounts.batch_state.as_ref().map(|batch_state| batch_state.key()),
+            processed: processed as u16,
+            cursor: cursor as u16,
+            entry_count: entries.len() as u16,
+            amount: amount_processed,
+            total_amount,
+            remaining_balance: ctx.accounts.session_wallet.load()?.current_balance,
+            complete,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        if complete {
+            if let Some(batch_state) = &ctx.accounts.batch_state {
+                batch_state.close(ctx.accounts.authority.to_account_info())?;
+            }
+        }
+
+        ctx.accounts.session_wallet.load_mut()?.record_event()?;
+
+        Ok(())
+    }
+
+    /// Name a beneficiary that receives the session balance once the session
+    /// has been inactive for `inactivity_sweep_secs`; 0 disables the sweep
+    pub fn configure_inactivity_sweep(
+        ctx: Context<ConfigureInactivitySweep>,
+        inactivity_sweep_secs: i64,
+        beneficiary: Pubkey,
+    ) -> Result<()> {
+        require!(inactivity_sweep_secs >= 0, ErrorCode::InvalidSweepConfig);
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        session_wallet.inactivity_sweep_secs = inactivity_sweep_secs;
+        session_wallet.set_beneficiary((inactivity_sweep_secs > 0).then_some(beneficiary));
+        session_wallet.last_activity = timestamp;
+
+        emit!(InactivitySweepConfigured {
+            session_id: session_wallet.session_id().to_string(),
+            beneficiary: session_wallet.beneficiary(),
+            inactivity_sweep_secs,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Send the balance of a session that has been inactive past its
+    /// threshold to its beneficiary. Callable by anyone.
+    pub fn sweep_inactive_session(ctx: Context<SweepInactiveSession>) -> Result<()> {
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        let amount = {
+            let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+            require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+            require!(
+                session_wallet.inactivity_sweep_secs > 0,
+                ErrorCode::InvalidSweepConfig
+            );
+            require!(
+                timestamp.saturating_sub(session_wallet.last_activity)
+                    >= session_wallet.inactivity_sweep_secs,
+                ErrorCode::SessionNotInactive
+            );
+
+            let amount = session_wallet.current_balance;
+            require!(amount > 0, ErrorCode::InsufficientBalance);
+
+            session_wallet.current_balance = 0;
+            amount
+        };
+
+        transfer_from_session(
+            &ctx.accounts.session_wallet,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.beneficiary_token_account.to_account_info(),
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+
+        emit!(InactiveSessionSwept {
+            session_id: session_wallet.session_id().to_string(),
+            beneficiary: session_wallet.beneficiary,
+            amount,
+            last_activity: session_wallet.last_activity,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Grant `member` a role on the session. Operator limits are per purchase
+    /// and cumulative; 0 leaves either unlimited.
+    pub fn assign_role(
+        ctx: Context<AssignRole>,
+        member: Pubkey,
+        role: Role,
+        purchase_limit: u64,
+        spend_limit: u64,
+    ) -> Result<()> {
+        let role_assignment = &mut ctx.accounts.role_assignment;
+        role_assignment.session = ctx.accounts.session_wallet.key();
+        role_assignment.member = member;
+        role_assignment.role = role;
+        role_assignment.purchase_limit = purchase_limit;
+        role_assignment.spend_limit = spend_limit;
+        role_assignment.spent = 0;
+        role_assignment.bump = ctx.bumps.role_assignment;
+
+        emit!(RoleAssigned {
+            session_id: ctx.accounts.session_wallet.load()?.session_id().to_string(),
+            member,
+            role,
+            purchase_limit,
+            spend_limit,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Remove a member's role from the session
+    pub fn revoke_role(ctx: Context<RevokeRole>) -> Result<()> {
+        emit!(RoleRevoked {
+            session_id: ctx.accounts.session_wallet.load()?.session_id().to_string(),
+            member: ctx.accounts.role_assignment.member,
+            role: ctx.accounts.role_assignment.role,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Create the global table mapping service id prefixes to reporting codes (admin only)
+    pub fn initialize_category_codes(
+        ctx: Context<InitializeCategoryCodes>,
+        codes: Vec<CategoryCode>,
+    ) -> Result<()> {
+        validate_category_codes(&codes)?;
+
+        let table = &mut ctx.accounts.category_codes;
+        table.codes = codes;
+        table.bump = ctx.bumps.category_codes;
+
+        emit!(CategoryCodesUpdated {
+            code_count: table.codes.len() as u8,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Replace the reporting code table (admin only)
+    pub fn set_category_codes(ctx: Context<SetCategoryCodes>, codes: Vec<CategoryCode>) -> Result<()> {
+        validate_category_codes(&codes)?;
+
+        let table = &mut ctx.accounts.category_codes;
+        table.codes = codes;
+
+        emit!(CategoryCodesUpdated {
+            code_count: table.codes.len() as u8,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Require every purchase from the session to map to a reporting code
+    pub fn set_category_codes_required(
+        ctx: Context<SetCategoryCodesRequired>,
+        required: bool,
+    ) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+
+        session_wallet.set_category_codes_required(required);
+
+        emit!(CategoryCodesRequiredSet {
+            session_id: session_wallet.session_id().to_string(),
+            required,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Create the automation configuration naming the thread program used
+    /// for scheduled session operations
+    pub fn initialize_automation_config(
+        ctx: Context<InitializeAutomationConfig>,
+        automation_program: Pubkey,
+    ) -> Result<()> {
+        let automation_config = &mut ctx.accounts.automation_config;
+        automation_config.automation_program = automation_program;
+        automation_config.bump = ctx.bumps.automation_config;
+
+        emit!(AutomationProgramSet {
+            automation_program,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Point scheduled operations at a different thread program
+    pub fn set_automation_program(
+        ctx: Context<SetAutomationProgram>,
+        automation_program: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.automation_config.automation_program = automation_program;
+
+        emit!(AutomationProgramSet {
+            automation_program,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Create a thread on the automation program with the session PDA as its
+    /// authority, and record it on the session.
+    ///
+    /// `thread_data` and the remaining accounts are passed to the automation
+    /// program as is; they carry the schedule and the instruction the thread
+    /// runs. A `CloseAtExpiry` thread may call `close_session` once the session
+    /// has expired. Subscription processing and refills go through
+    /// `execute_purchase` and `fund_session`, which need no extra rights.
+    pub fn register_automation_thread<'info>(
+        ctx: Context<'_, '_, '_, 'info, RegisterAutomationThread<'info>>,
+        task: AutomationTask,
+        thread_data: Vec<u8>,
+    ) -> Result<()> {
+        {
+            let session_wallet = ctx.accounts.session_wallet.load()?;
+            require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+            require!(
+                session_wallet.automation_thread().is_none(),
+                ErrorCode::AutomationThreadRegistered
+            );
+        }
+
+        invoke_as_session(
+            &ctx.accounts.session_wallet,
+            &ctx.accounts.automation_program,
+            ctx.remaining_accounts,
+            thread_data,
+        )?;
+
+        let thread = ctx.accounts.thread.key();
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        session_wallet.set_automation(Some((thread, task)));
+
+        emit!(AutomationThreadRegistered {
+            session_id: session_wallet.session_id().to_string(),
+            thread,
+            task,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Delete the session's automation thread and forget it. `thread_data`
+    /// and the remaining accounts are passed to the automation program as is.
+    pub fn unregister_automation_thread<'info>(
+        ctx: Context<'_, '_, '_, 'info, UnregisterAutomationThread<'info>>,
+        thread_data: Vec<u8>,
+    ) -> Result<()> {
+        invoke_as_session(
+            &ctx.accounts.session_wallet,
+            &ctx.accounts.automation_program,
+            ctx.remaining_accounts,
+            thread_data,
+        )?;
+
+        let thread = ctx.accounts.thread.key();
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        session_wallet.set_automation(None);
+
+        emit!(AutomationThreadUnregistered {
+            session_id: session_wallet.session_id().to_string(),
+            thread,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Create the bridge configuration naming the program that redeems
+    /// cross-chain USDC transfers (CCTP, Wormhole)
+    pub fn initialize_bridge_config(
+        ctx: Context<InitializeBridgeConfig>,
+        bridge_program: Pubkey,
+    ) -> Result<()> {
+        let bridge_config = &mut ctx.accounts.bridge_config;
+        bridge_config.bridge_program = bridge_program;
+        bridge_config.bump = ctx.bumps.bridge_config;
+
+        emit!(BridgeProgramSet {
+            bridge_program,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Point bridged funding at a different redemption program
+    pub fn set_bridge_program(ctx: Context<SetBridgeProgram>, bridge_program: Pubkey) -> Result<()> {
+        ctx.accounts.bridge_config.bridge_program = bridge_program;
+
+        emit!(BridgeProgramSet {
+            bridge_program,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Redeem a bridged USDC transfer straight into the session vault.
+    ///
+    /// `redeem_data` and the remaining accounts are passed to the bridge
+    /// program as is. That program verifies the message and attestation and
+    /// mints to the recipient named in the message. Whatever lands in the
+    /// session token account is credited to the session. Nothing is signed as
+    /// the session.
+    pub fn fund_session_from_bridge<'info>(
+        ctx: Context<'_, '_, '_, 'info, FundSessionFromBridge<'info>>,
+        redeem_data: Vec<u8>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.session_wallet.load()?.is_active(),
+            ErrorCode::SessionClosed
+        );
+
+        let balance_before = ctx.accounts.session_token_account.amount;
+
+        let ix = Instruction {
+            program_id: ctx.accounts.bridge_program.key(),
+            accounts: ctx
+                .remaining_accounts
+                .iter()
+                .map(|account| AccountMeta {
+                    pubkey: account.key(),
+                    is_signer: account.is_signer,
+                    is_writable: account.is_writable,
+                })
+                .collect(),
+            data: redeem_data,
+        };
+        let mut account_infos = ctx.remaining_accounts.to_vec();
+        account_infos.push(ctx.accounts.bridge_program.clone());
+        invoke(&ix, &account_infos)?;
+
+        ctx.accounts.session_token_account.reload()?;
+        let amount = ctx
+            .accounts
+            .session_token_account
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(ErrorCode::InvalidBridgeTransfer)?;
+        require!(amount > 0, ErrorCode::InvalidBridgeTransfer);
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        if session_wallet.exceeds_funding_cap(amount) {
+            return reject_funding(
+                &session_wallet,
+                ctx.accounts.relayer.key(),
+                amount,
+                RejectionReason::FundingCapExceeded,
+            );
+        }
+
+        session_wallet.current_balance = session_wallet
+            .current_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        session_wallet.total_funded = session_wallet
+            .total_funded
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        session_wallet.last_activity = timestamp;
+
+        emit!(FundsAdded {
+            session_id: session_wallet.session_id().to_string(),
+            amount,
+            new_balance: session_wallet.current_balance,
+            timestamp,
+        });
+        emit!(BridgeFundsRedeemed {
+            session_id: session_wallet.session_id().to_string(),
+            bridge_program: ctx.accounts.bridge_program.key(),
+            amount,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Initialize a session for a registered agent under an id derived from
+    /// the agent key and its session counter, see `derived_session_id`
+    pub fn initialize_session_auto(
+        ctx: Context<InitializeSessionAuto>,
+        initial_funding: u64,
+    ) -> Result<()> {
+        let agent_account = &mut ctx.accounts.agent_account;
+        require!(agent_account.outstanding_debt == 0, ErrorCode::DebtOutstanding);
+
+        let session_id = derived_session_id(&agent_account.agent, agent_account.session_count);
+        agent_account.session_count = agent_account
+            .session_count
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
+
+        disburse_from_treasury(
+            &mut ctx.accounts.treasury,
+            &ctx.accounts.treasury_vault,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.token_program,
+            initial_funding,
+        )?;
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_init()?;
+
+        open_session(
+            &mut session_wallet,
+            ctx.accounts.authority.key(),
+            &session_id,
+            ctx.accounts.treasury.mint,
+            initial_funding,
+            ctx.bumps.session_wallet,
+        )?;
+        session_wallet.set_agent_pubkey(Some(agent_account.agent));
+
+        emit!(SessionCreated {
+            session_id,
+            pda: ctx.accounts.session_wallet.key(),
+            initial_funding,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Write the session's full state to a checkpoint PDA and close the
+    /// session account. The PDA address, and with it every account the
+    /// session owns, is kept for `import_session_state`.
+    pub fn export_session_state(ctx: Context<ExportSessionState>) -> Result<()> {
+        let session_wallet = ctx.accounts.session_wallet.load()?;
+        let exported_at = Clock::get()?.unix_timestamp;
+
+        let checkpoint = &mut ctx.accounts.checkpoint;
+        checkpoint.session = ctx.accounts.session_wallet.key();
+        checkpoint.version = SessionCheckpoint::VERSION;
+        checkpoint.state = SessionState::capture(&session_wallet);
+        checkpoint.exported_at = exported_at;
+        checkpoint.bump = ctx.bumps.checkpoint;
+
+        emit!(SessionExported {
+            session_id: session_wallet.session_id().to_string(),
+            checkpoint: checkpoint.key(),
+            version: checkpoint.version,
+            current_balance: session_wallet.current_balance,
+            timestamp: exported_at,
+        });
+
+        Ok(())
+    }
+
+    /// Recreate a session at its original address from a checkpoint written
+    /// by `export_session_state`, and close the checkpoint
+    pub fn import_session_state(ctx: Context<ImportSessionState>) -> Result<()> {
+        let checkpoint = &ctx.accounts.checkpoint;
+        let mut session_wallet = ctx.accounts.session_wallet.load_init()?;
+
+        checkpoint
+            .state
+            .restore(&mut session_wallet, ctx.bumps.session_wallet)?;
+
+        emit!(SessionImported {
+            session_id: checkpoint.state.session_id.clone(),
+            checkpoint: checkpoint.key(),
+            version: checkpoint.version,
+            current_balance: session_wallet.current_balance,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Register a service provider so it can pay rebates into sessions (admin only)
+    pub fn register_provider(ctx: Context<RegisterProvider>, provider: Pubkey) -> Result<()> {
+        let provider_account = &mut ctx.accounts.provider_account;
+
+        provider_account.provider = provider;
+        provider_account.total_rebated = 0;
+        provider_account.rating_count = 0;
+        provider_account.rating_total = 0;
+        provider_account.payout_mint = Pubkey::default();
+        provider_account.trial_discount_bps = 0;
+        provider_account.bump = ctx.bumps.provider_account;
+
+        emit!(ProviderRegistered {
+            provider,
+            provider_account: provider_account.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Pay a reward or rebate from a registered provider into a session.
+    /// It adds to the balance but is kept out of `total_funded`.
+    pub fn pay_rebate(ctx: Context<PayRebate>, amount: u64) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        session_wallet.current_balance = session_wallet
+            .current_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        session_wallet.total_rebated = session_wallet
+            .total_rebated
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        session_wallet.last_activity = timestamp;
+
+        let provider_account = &mut ctx.accounts.provider_account;
+        provider_account.total_rebated = provider_account
+            .total_rebated
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.provider_token_account.to_account_info(),
+            to: ctx.accounts.session_token_account.to_account_info(),
+            authority: ctx.accounts.provider.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        token::transfer(cpi_ctx, amount)?;
+
+        emit!(RebateReceived {
+            session_id: session_wallet.session_id().to_string(),
+            provider: ctx.accounts.provider.key(),
+            amount,
+            new_balance: session_wallet.current_balance,
+            total_rebated: session_wallet.total_rebated,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Hand a narrowly scoped spending right to an ephemeral key. The holder
+    /// may spend up to `max_amount` before `expiry` on service ids starting
+    /// with the scope whose hash is `scope_hash`, see `capability_scope_hash`.
+    pub fn issue_capability(
+        ctx: Context<IssueCapability>,
+        scope_hash: [u8; 32],
+        max_amount: u64,
+        expiry: i64,
+    ) -> Result<()> {
+        let timestamp = Clock::get()?.unix_timestamp;
+        require!(expiry > timestamp, ErrorCode::InvalidCapability);
+
+        let capability = &mut ctx.accounts.capability;
+        capability.session = ctx.accounts.session_wallet.key();
+        capability.key = ctx.accounts.capability_key.key();
+        capability.issuer = ctx.accounts.authority.key();
+        capability.scope_hash = scope_hash;
+        capability.max_amount = max_amount;
+        capability.spent = 0;
+        capability.expires_at = expiry;
+        capability.bump = ctx.bumps.capability;
+
+        emit!(CapabilityIssued {
+            session_id: ctx.accounts.session_wallet.load()?.session_id().to_string(),
+            capability: capability.key(),
+            key: capability.key,
+            scope_hash,
+            max_amount,
+            expires_at: expiry,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw a capability before it expires
+    pub fn revoke_capability(ctx: Context<RevokeCapability>) -> Result<()> {
+        emit!(CapabilityRevoked {
+            session_id: ctx.accounts.session_wallet.load()?.session_id().to_string(),
+            capability: ctx.accounts.capability.key(),
+            spent: ctx.accounts.capability.spent,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Execute a purchase signed by a capability's ephemeral key. `scope` must
+    /// hash to the capability's scope and prefix `service_id`. The purchase is
+    /// checked as the issuer's, so revoking the issuer's Owner role also
+    /// retires its capabilities.
+    pub fn execute_purchase_with_capability(
+        ctx: Context<ExecutePurchaseWithCapability>,
+        amount: u64,
+        service_id: String,
+        scope: String,
+    ) -> Result<()> {
+        check_composition(&*ctx.accounts.session_wallet.load()?, &ctx.accounts.instructions)?;
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
+        let amount = apply_trial_discount(
+            provider_account.as_ref(),
+            &*ctx.accounts.session_wallet.load()?,
+            &service_id,
+            amount,
+        )?;
+        let capability = &mut ctx.accounts.capability;
+
+        require!(timestamp <= capability.expires_at, ErrorCode::CapabilityExpired);
+        require!(
+            capability_scope_hash(&scope) == capability.scope_hash && service_id.starts_with(&scope),
+            ErrorCode::CapabilityScopeMismatch
+        );
+        capability.spent = capability
+            .spent
+            .checked_add(amount)
+            .filter(|spent| *spent <= capability.max_amount)
+            .ok_or(ErrorCode::CapabilityLimitExceeded)?;
+
+        let issuer = capability.issuer;
+        let session_wallet = &ctx.accounts.session_wallet;
+        require!(
+            has_role(session_wallet, issuer, ctx.accounts.role_assignment.as_ref(), Role::Owner)?,
+            ErrorCode::Unauthorized
+        );
+
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider: ctx.accounts.service_provider_token_account.owner,
+            service_id: &service_id,
+            amount,
+            timestamp,
+            credit_available: 0,
+            currency_balance: None,
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: ctx.accounts.service_provider_token_account.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        preflight_purchase(
+            &*session_wallet.load()?,
+            ctx.accounts.policy.as_ref(),
+            &purchase,
+            &ctx.accounts.config,
+            &ctx.accounts.global_stats,
+            issuer,
+            ctx.accounts.role_assignment.as_deref(),
+        )?;
+
+        track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+
+        charge_operator(
+            &*session_wallet.load()?,
+            issuer,
+            ctx.accounts.role_assignment.as_mut(),
+            amount,
+        )?;
+
+        let fee = collect_fee(
+            &*session_wallet.load()?,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &mut ctx.accounts.treasury,
+            &purchase,
+        )?;
+
+        settle_purchase(
+            session_wallet,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.service_provider_token_account,
+            &ctx.accounts.token_program,
+            &service_id,
+            amount,
+            (&ctx.accounts.treasury_vault, fee),
+        )?;
+
+        emit!(PurchaseExecuted {
+            session_id: session_wallet.load()?.session_id().to_string(),
+            service_id,
+            category_code,
+            amount,
+            remaining_balance: session_wallet.load()?.current_balance,
+            timestamp,
+        });
+
+        session_wallet.load_mut()?.record_event()?;
+
+        Ok(())
+    }
+
+    /// Create the fee configuration with the default platform fee on
+    /// purchases, in basis points (admin only)
+    pub fn initialize_fee_config(ctx: Context<InitializeFeeConfig>, fee_bps: u16) -> Result<()> {
+        require!(fee_bps <= BPS_DENOMINATOR, ErrorCode::InvalidFeeBps);
+
+        let fee_config = &mut ctx.accounts.fee_config;
+        fee_config.fee_bps = fee_bps;
+        fee_config.fee_destination = Pubkey::default();
+        fee_config.bump = ctx.bumps.fee_config;
+
+        emit!(FeeBpsSet {
+            fee_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Change the default platform fee (admin only)
+    pub fn set_fee_bps(ctx: Context<SetFeeConfig>, fee_bps: u16) -> Result<()> {
+        require!(fee_bps <= BPS_DENOMINATOR, ErrorCode::InvalidFeeBps);
+
+        ctx.accounts.fee_config.fee_bps = fee_bps;
+
+        emit!(FeeBpsSet {
+            fee_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Give an agent or provider key a negotiated fee in basis points, 0 for
+    /// an exemption (admin only)
+    pub fn assign_fee_tier(
+        ctx: Context<AssignFeeTier>,
+        subject: Pubkey,
+        fee_bps: u16,
+    ) -> Result<()> {
+        require!(fee_bps <= BPS_DENOMINATOR, ErrorCode::InvalidFeeBps);
+
+        let fee_tier = &mut ctx.accounts.fee_tier;
+        fee_tier.subject = subject;
+        fee_tier.fee_bps = fee_bps;
+        fee_tier.bump = ctx.bumps.fee_tier;
+
+        emit!(FeeTierAssigned {
+            subject,
+            fee_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Return an agent or provider key to the default fee (admin only)
+    pub fn remove_fee_tier(ctx: Context<RemoveFeeTier>) -> Result<()> {
+        emit!(FeeTierRemoved {
+            subject: ctx.accounts.fee_tier.subject,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Pay a provider at once against a bond it posts alongside the claim.
+    /// Until `challenge_window` seconds have passed, an attestation from
+    /// `arbiter` forfeits the bond to the session; afterwards the provider
+    /// can take it back. Both the buyer and the provider sign.
+    pub fn execute_bonded_purchase(
+        ctx: Context<ExecuteBondedPurchase>,
+        claim_id: u64,
+        amount: u64,
+        service_id: String,
+        bond: u64,
+        arbiter: Pubkey,
+        challenge_window: i64,
+    ) -> Result<()> {
+        require!(
+            service_id.len() <= PurchaseReceipt::MAX_SERVICE_ID_LEN,
+            ErrorCode::ServiceIdTooLong
+        );
+        require!(bond > 0 && challenge_window > 0, ErrorCode::InvalidBond);
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        let session_wallet = &ctx.accounts.session_wallet;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
+        check_composition(&*session_wallet.load()?, &ctx.accounts.instructions)?;
+
+        let amount = apply_trial_discount(provider_account.as_ref(), &*session_wallet.load()?, &service_id, amount)?;
+
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider: ctx.accounts.service_provider_token_account.owner,
+            service_id: &service_id,
+            amount,
+            timestamp,
+            credit_available: 0,
+            currency_balance: None,
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: ctx.accounts.service_provider_token_account.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        {
+            let session = session_wallet.load()?;
+            check_purchase(&session, ctx.accounts.policy.as_ref(), &purchase)?;
+
+            track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+            charge_operator(
+                &session,
+                ctx.accounts.authority.key(),
+                ctx.accounts.role_assignment.as_mut(),
+                amount,
+            )?;
+        }
+
+        let fee = collect_fee(
+            &*session_wallet.load()?,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &mut ctx.accounts.treasury,
+            &purchase,
+        )?;
+
+        settle_purchase(
+            session_wallet,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.service_provider_token_account,
+            &ctx.accounts.token_program,
+            &service_id,
+            amount,
+            (&ctx.accounts.treasury_vault, fee),
+        )?;
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.provider_bond_account.to_account_info(),
+            to: ctx.accounts.bond_vault.to_account_info(),
+            authority: ctx.accounts.provider.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::transfer(CpiContext::new(cpi_program, cpi_accounts), bond)?;
+
+        let claim = &mut ctx.accounts.claim;
+        claim.session = session_wallet.key();
+        claim.claim_id = claim_id;
+        claim.provider = ctx.accounts.provider.key();
+        claim.arbiter = arbiter;
+        claim.amount = amount;
+        claim.bond = bond;
+        claim.challenge_deadline = timestamp
+            .checked_add(challenge_window)
+            .ok_or(ErrorCode::Overflow)?;
+        claim.bump = ctx.bumps.claim;
+
+        let session_id = session_wallet.load()?.session_id().to_string();
+        emit!(BondedPurchaseOpened {
+            session_id: session_id.clone(),
+            claim: claim.key(),
+            provider: claim.provider,
+            amount,
+            bond,
+            challenge_deadline: claim.challenge_deadline,
+            timestamp,
+        });
+        emit!(PurchaseExecuted {
+            session_id,
+            service_id,
+            category_code,
+            amount,
+            remaining_balance: session_wallet.load()?.current_balance,
+            timestamp,
+        });
+
+        session_wallet.load_mut()?.record_event()?;
+
+        Ok(())
+    }
+
+    /// Forfeit a claim's bond to the session. The preceding instruction must
+    /// be an ed25519 verification of `bond_challenge_message` by the claim's
+    /// arbiter, submitted before the challenge deadline.
+    pub fn challenge_bonded_purchase(ctx: Context<ChallengeBondedPurchase>) -> Result<()> {
+        let timestamp = Clock::get()?.unix_timestamp;
+        let claim = &ctx.accounts.claim;
+
+        require!(timestamp <= claim.challenge_deadline, ErrorCode::ChallengeWindowClosed);
+        verify_ed25519_instruction(
+            &ctx.accounts.instructions,
+            &claim.arbiter,
+            &bond_challenge_message(&claim.key()),
+        )?;
+
+        let bond = claim.bond;
+        drain_bond_vault(
+            claim,
+            &ctx.accounts.bond_vault,
+            &ctx.accounts.session_token_account.to_account_info(),
+            &ctx.accounts.provider.to_account_info(),
+            &ctx.accounts.token_program,
+        )?;
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        session_wallet.current_balance = session_wallet
+            .current_balance
+            .checked_add(bond)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(BondForfeited {
+            session_id: session_wallet.session_id().to_string(),
+            claim: claim.key(),
+            provider: claim.provider,
+            bond,
+            new_balance: session_wallet.current_balance,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Return an unchallenged bond to its provider once the window has closed.
+    /// Callable by anyone.
+    pub fn release_bond(ctx: Context<ReleaseBond>) -> Result<()> {
+        let timestamp = Clock::get()?.unix_timestamp;
+        let claim = &ctx.accounts.claim;
+
+        require!(timestamp > claim.challenge_deadline, ErrorCode::ChallengeWindowOpen);
+
+        drain_bond_vault(
+            claim,
+            &ctx.accounts.bond_vault,
+            &ctx.accounts.provider_bond_account.to_account_info(),
+            &ctx.accounts.provider.to_account_info(),
+            &ctx.accounts.token_program,
+        )?;
+
+        emit!(BondReleased {
+            claim: claim.key(),
+            provider: claim.provider,
+            bond: claim.bond,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Open a new session with an existing session's policy, budgets, expiry,
+    /// velocity and sweep settings, but fresh counters and its own funding
+    pub fn clone_session(
+        ctx: Context<CloneSession>,
+        new_session_id: String,
+        funding: u64,
+    ) -> Result<()> {
+        // The clone inherits the agent key, so it inherits the debt gate too
+        require_agent_clear(&ctx.accounts.agent_account)?;
+
+        let source = ctx.accounts.source_session.load()?;
+        let mut session_wallet = ctx.accounts.session_wallet.load_init()?;
+
+        open_session(
+            &mut session_wallet,
+            ctx.accounts.authority.key(),
+            &new_session_id,
+            ctx.accounts.treasury.mint,
+            funding,
+            ctx.bumps.session_wallet,
+        )?;
+
+        session_wallet.set_agent_pubkey(source.agent_pubkey());
+        session_wallet.set_policy(source.policy());
+        let category_budgets: Vec<CategoryBudget> = source
+            .category_budgets()
+            .iter()
+            .map(|entry| CategoryBudget {
+                prefix: entry.prefix().to_string(),
+                limit: entry.limit,
+                spent: 0,
+            })
+            .collect();
+        session_wallet.set_category_budgets(&category_budgets);
+        session_wallet.expiry_grace_period = source.expiry_grace_period;
+        if source.expires_at != 0 {
+            session_wallet.expires_at = session_wallet
+                .created_at
+                .checked_add(source.expires_at.saturating_sub(source.created_at))
+                .ok_or(ErrorCode::Overflow)?;
+        }
+        session_wallet.velocity_window = source.velocity_window;
+        session_wallet.velocity_multiple = source.velocity_multiple;
+        session_wallet.window_start = session_wallet.created_at;
+        session_wallet.inactivity_sweep_secs = source.inactivity_sweep_secs;
+        session_wallet.set_beneficiary(source.beneficiary());
+        session_wallet.set_category_codes_required(source.category_codes_required());
+        session_wallet.set_allow_cpi(source.allow_cpi());
+        session_wallet.max_total_funding = source.max_total_funding;
+        require!(!session_wallet.exceeds_funding_cap(0), ErrorCode::FundingCapExceeded);
+
+        disburse_from_treasury(
+            &mut ctx.accounts.treasury,
+            &ctx.accounts.treasury_vault,
+            &ctx.accounts.session_token_account,
+            &ctx.accounts.token_program,
+            funding,
+        )?;
+
+        emit!(SessionCreated {
+            session_id: new_session_id.clone(),
+            pda: ctx.accounts.session_wallet.key(),
+            initial_funding: funding,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        emit!(SessionCloned {
+            session_id: new_session_id,
+            source_session_id: source.session_id().to_string(),
+            expires_at: session_wallet.expires_at,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Rate the provider of a receipted purchase, once per receipt. The score
+    /// feeds the provider's aggregate rating; the comment lives off-chain
+    /// under `comment_hash`.
+    pub fn rate_purchase(
+        ctx: Context<RatePurchase>,
+        score: u8,
+        comment_hash: [u8; 32],
+    ) -> Result<()> {
+        require!(
+            (PurchaseRating::MIN_SCORE..=PurchaseRating::MAX_SCORE).contains(&score),
+            ErrorCode::InvalidRating
+        );
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        let rating = &mut ctx.accounts.rating;
+        rating.receipt = ctx.accounts.receipt.key();
+        rating.provider = ctx.accounts.receipt.provider;
+        rating.score = score;
+        rating.comment_hash = comment_hash;
+        rating.rated_at = timestamp;
+        rating.bump = ctx.bumps.rating;
+
+        let provider_account = &mut ctx.accounts.provider_account;
+        provider_account.rating_count = provider_account
+            .rating_count
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
+        provider_account.rating_total = provider_account
+            .rating_total
+            .checked_add(score as u64)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(PurchaseRated {
+            session_id: ctx.accounts.session_wallet.load()?.session_id().to_string(),
+            receipt: rating.receipt,
+            provider: rating.provider,
+            score,
+            comment_hash,
+            rating_count: provider_account.rating_count,
+            rating_total: provider_account.rating_total,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Commit the session to a ceiling on lifetime funding. A cap can be set
+    /// once and then only lowered; raising it takes the two-step
+    /// `propose_funding_cap_raise` / `raise_funding_cap`.
+    pub fn set_funding_cap(ctx: Context<ConfigureFundingCap>, max_total_funding: u64) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+        require!(
+            max_total_funding != 0
+                && (session_wallet.max_total_funding == 0
+                    || max_total_funding <= session_wallet.max_total_funding),
+            ErrorCode::InvalidFundingCap
+        );
+
+        // Tightening the cap drops any raise still waiting
+        session_wallet.max_total_funding = max_total_funding;
+        session_wallet.pending_funding_cap = 0;
+        session_wallet.funding_cap_raise_at = 0;
+
+        emit!(FundingCapSet {
+            session_id: session_wallet.session_id().to_string(),
+            max_total_funding,
+            total_funded: session_wallet.total_funded,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Propose a higher funding cap, applicable with `raise_funding_cap` once
+    /// `FUNDING_CAP_RAISE_DELAY` has passed
+    pub fn propose_funding_cap_raise(
+        ctx: Context<ConfigureFundingCap>,
+        max_total_funding: u64,
+    ) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+        require!(
+            session_wallet.max_total_funding != 0
+                && max_total_funding > session_wallet.max_total_funding,
+            ErrorCode::InvalidFundingCap
+        );
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        session_wallet.pending_funding_cap = max_total_funding;
+        session_wallet.funding_cap_raise_at = timestamp
+            .checked_add(FUNDING_CAP_RAISE_DELAY)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(FundingCapRaiseProposed {
+            session_id: session_wallet.session_id().to_string(),
+            max_total_funding,
+            raise_at: session_wallet.funding_cap_raise_at,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Apply a proposed funding cap raise whose delay has passed
+    pub fn raise_funding_cap(ctx: Context<ConfigureFundingCap>) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        require!(
+            session_wallet.pending_funding_cap != 0
+                && timestamp >= session_wallet.funding_cap_raise_at,
+            ErrorCode::FundingCapRaisePending
+        );
+
+        session_wallet.max_total_funding = session_wallet.pending_funding_cap;
+        session_wallet.pending_funding_cap = 0;
+        session_wallet.funding_cap_raise_at = 0;
+
+        emit!(FundingCapSet {
+            session_id: session_wallet.session_id().to_string(),
+            max_total_funding: session_wallet.max_total_funding,
+            total_funded: session_wallet.total_funded,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Run every check `execute_purchase` would for `amount` to the provider
+    /// on behalf of `authority` without moving funds, emitting
+    /// `PurchaseValidated` with the trial-discounted amount and the fee when
+    /// it would pass. Failures return the same typed error, so agents can
+    /// pre-flight plans.
+    pub fn validate_purchase(
+        ctx: Context<ValidatePurchase>,
+        amount: u64,
+        service_id: String,
+    ) -> Result<()> {
+        let session_wallet = ctx.accounts.session_wallet.load()?;
+        let provider = ctx.accounts.service_provider_token_account.owner;
+        let timestamp = Clock::get()?.unix_timestamp;
+        let provider_account = load_if_created::<ProviderAccount>(&ctx.accounts.provider_account)?;
+        let service_listing = load_if_created::<ServiceListing>(&ctx.accounts.service_listing)?;
+
+        // Priced as execute_purchase would, after any trial discount
+        let amount = amount - trial_discount(provider_account.as_ref(), &session_wallet, amount);
+
+        let currency_balance = match &ctx.accounts.currency_balance {
+            Some(currency_balance) => Some(currency_balance.load()?.current_balance),
+            None => None,
+        };
+
+        let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &service_id);
+        let purchase = PurchaseContext {
+            provider,
+            service_id: &service_id,
+            amount,
+            timestamp,
+            credit_available: 0,
+            currency_balance,
+            category_code,
+            service_listing: service_listing.as_ref(),
+            payout_mint: ctx.accounts.service_provider_token_account.mint,
+            provider_account: provider_account.as_ref(),
+        };
+        preflight_purchase(
+            &session_wallet,
+            ctx.accounts.policy.as_ref(),
+            &purchase,
+            &ctx.accounts.config,
+            &ctx.accounts.global_stats,
+            ctx.accounts.authority.key(),
+            ctx.accounts.role_assignment.as_deref(),
+        )?;
+
+        let (fee, _) = purchase_fee(
+            &session_wallet,
+            &ctx.accounts.fee_config,
+            ctx.accounts.agent_fee_tier.as_deref(),
+            ctx.accounts.provider_fee_tier.as_deref(),
+            &purchase,
+        )?;
+
+        emit!(PurchaseValidated {
+            session_id: session_wallet.session_id().to_string(),
+            service_id,
+            provider,
+            category_code,
+            amount,
+            fee,
+            remaining_balance: currency_balance
+                .unwrap_or(session_wallet.current_balance)
+                .saturating_sub(amount),
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Name the owner whose token accounts receive swept platform fees (admin only)
+    pub fn set_fee_destination(ctx: Context<SetFeeConfig>, fee_destination: Pubkey) -> Result<()> {
+        ctx.accounts.fee_config.fee_destination = fee_destination;
+
+        emit!(FeeDestinationSet {
+            fee_destination,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Create the fee sweep totals for a mint (admin only)
+    pub fn initialize_global_stats(ctx: Context<InitializeGlobalStats>, mint: Pubkey) -> Result<()> {
+        let global_stats = &mut ctx.accounts.global_stats;
+        global_stats.mint = mint;
+        global_stats.total_fees_swept = 0;
+        global_stats.sweep_count = 0;
+        global_stats.last_swept_at = 0;
+        global_stats.window_start_slot = Clock::get()?.slot;
+        global_stats.window_volume = 0;
+        global_stats.bump = ctx.bumps.global_stats;
+
+        Ok(())
+    }
+
+    /// Move the platform fees accumulated in a mint's treasury since the last
+    /// sweep to the configured fee destination. Callable by anyone.
+    pub fn sweep_fees(ctx: Context<SweepFees>, _mint: Pubkey) -> Result<()> {
+        let global_stats = &mut ctx.accounts.global_stats;
+        let amount = ctx
+            .accounts
+            .treasury
+            .total_fees
+            .checked_sub(global_stats.total_fees_swept)
+            .ok_or(ErrorCode::Overflow)?;
+        require!(amount > 0, ErrorCode::NoFeesToSweep);
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        global_stats.total_fees_swept = global_stats
+            .total_fees_swept
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        global_stats.sweep_count = global_stats
+            .sweep_count
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
+        global_stats.last_swept_at = timestamp;
+
+        transfer_from_treasury(
+            &ctx.accounts.treasury,
+            &ctx.accounts.treasury_vault,
+            &ctx.accounts.destination_token_account.to_account_info(),
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        emit!(FeesSwept {
+            mint: global_stats.mint,
+            destination: ctx.accounts.destination_token_account.key(),
+            amount,
+            total_fees_swept: global_stats.total_fees_swept,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Move the earnings of a payout ledger whose provider has not claimed for
+    /// `PAYOUT_ESCHEAT_PERIOD` into the treasury for its mint, which backs
+    /// refunds and losses. Callable by anyone.
+    pub fn escheat_payout(ctx: Context<EscheatPayout>) -> Result<()> {
+        let timestamp = Clock::get()?.unix_timestamp;
+        let (amount, last_claimed_at) = {
+            let mut ledger = ctx.accounts.ledger.load_mut()?;
+            let amount = ledger.accrued;
+            require!(amount > 0, ErrorCode::NothingToClaim);
+            require!(
+                timestamp.saturating_sub(ledger.last_claimed_at) >= PAYOUT_ESCHEAT_PERIOD,
+                ErrorCode::PayoutNotAbandoned
+            );
+
+            ledger.accrued = 0;
+            ledger.total_escheated = ledger
+                .total_escheated
+                .checked_add(amount)
+                .ok_or(ErrorCode::Overflow)?;
+            (amount, ledger.last_claimed_at)
+        };
+
+        let treasury = &mut ctx.accounts.treasury;
+        treasury.total_escheated = treasury
+            .total_escheated
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        transfer_from_ledger(
+            &ctx.accounts.ledger,
+            &ctx.accounts.vault,
+            &ctx.accounts.treasury_vault.to_account_info(),
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        let ledger = ctx.accounts.ledger.load()?;
+
+        emit!(PayoutEscheated {
+            provider: ledger.provider,
+            mint: ledger.mint,
+            ledger: ctx.accounts.ledger.key(),
+            amount,
+            last_claimed_at,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// List a service under the calling provider so buyers can check its status
+    pub fn list_service(ctx: Context<ListService>, service_id: String) -> Result<()> {
+        require!(
+            service_id.len() <= ServiceListing::MAX_SERVICE_ID_LEN,
+            ErrorCode::ServiceIdTooLong
+        );
+
+        let listing = &mut ctx.accounts.service_listing;
+        listing.provider = ctx.accounts.provider.key();
+        listing.service_id = service_id;
+        listing.listed_at = Clock::get()?.unix_timestamp;
+        listing.deprecated_after = 0;
+        listing.bump = ctx.bumps.service_listing;
+
+        emit!(ServiceListed {
+            provider: listing.provider,
+            service_id: listing.service_id.clone(),
+            listing: listing.key(),
+            timestamp: listing.listed_at,
+        });
+
+        Ok(())
+    }
+
+    /// Schedule when a listed service stops accepting payments; purchases
+    /// passing the listing fail from then on. 0 withdraws the deprecation.
+    pub fn deprecate_service(ctx: Context<DeprecateService>, deprecated_after: i64) -> Result<()> {
+        let listing = &mut ctx.accounts.service_listing;
+        listing.deprecated_after = deprecated_after;
+
+        emit!(ServiceDeprecationScheduled {
+            provider: listing.provider,
+            service_id: listing.service_id.clone(),
+            deprecated_after,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Let the session's purchases run inside transactions composed with
+    /// other programs, or be invoked by them through CPI
+    pub fn set_allow_cpi(ctx: Context<SetAllowCpi>, allow_cpi: bool) -> Result<()> {
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+        require!(session_wallet.is_active(), ErrorCode::SessionClosed);
+
+        session_wallet.set_allow_cpi(allow_cpi);
+
+        emit!(AllowCpiSet {
+            session_id: session_wallet.session_id().to_string(),
+            allow_cpi,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Pull `amount` into the session from a token account whose owner signed
+    /// a permit off-chain. The owner approves the session PDA as delegate on
+    /// the account beforehand, and the transaction must carry an ed25519
+    /// program instruction immediately before this one verifying the owner's
+    /// signature over `funding_permit_message(session, amount, deadline, nonce)`.
+    /// Anyone holding the permit may submit it, once.
+    pub fn fund_with_permit(
+        ctx: Context<FundWithPermit>,
+        amount: u64,
+        deadline: i64,
+        nonce: u64,
+    ) -> Result<()> {
+        let funder = ctx.accounts.funder_token_account.owner;
+        let timestamp = Clock::get()?.unix_timestamp;
+
+        require!(timestamp <= deadline, ErrorCode::PermitExpired);
+
+        let message = funding_permit_message(&ctx.accounts.session_wallet.key(), amount, deadline, nonce);
+        verify_ed25519_instruction(&ctx.accounts.instructions, &funder, &message)?;
+
+        {
+            let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+            if !session_wallet.is_active() {
+                return reject_funding(&session_wallet, funder, amount, RejectionReason::SessionClosed);
+            }
+            if session_wallet.exceeds_funding_cap(amount) {
+                return reject_funding(
+                    &session_wallet,
+                    funder,
+                    amount,
+                    RejectionReason::FundingCapExceeded,
+                );
+            }
+
+            session_wallet.current_balance = session_wallet
+                .current_balance
+                .checked_add(amount)
+                .ok_or(ErrorCode::Overflow)?;
+            session_wallet.total_funded = session_wallet
+                .total_funded
+                .checked_add(amount)
+                .ok_or(ErrorCode::Overflow)?;
+            session_wallet.last_activity = timestamp;
+        }
+
+        // The permit's PDA is keyed on funder and nonce, so a second use fails at init
+        let permit = &mut ctx.accounts.permit;
+        permit.session = ctx.accounts.session_wallet.key();
+        permit.funder = funder;
+        permit.nonce = nonce;
+        permit.amount = amount;
+        permit.redeemed_at = timestamp;
+        permit.bump = ctx.bumps.permit;
+
+        // The session signs as the delegate the funder approved
+        transfer_from_session(
+            &ctx.accounts.session_wallet,
+            &ctx.accounts.funder_token_account,
+            &ctx.accounts.session_token_account.to_account_info(),
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        let mut session_wallet = ctx.accounts.session_wallet.load_mut()?;
+
+        emit!(FundsAdded {
+            session_id: session_wallet.session_id().to_string(),
+            amount,
+            new_balance: session_wallet.current_balance,
+            timestamp,
+        });
+
+        emit!(PermitRedeemed {
+            session_id: session_wallet.session_id().to_string(),
+            funder,
+            nonce,
+            amount,
+            deadline,
+            timestamp,
+        });
+
+        session_wallet.record_event()?;
+
+        Ok(())
+    }
+
+    /// Choose the mint the provider is paid in; `None` accepts any. Purchases
+    /// passing the provider account must then pay in that mint, converting
+    /// through `execute_purchase_with_swap` when the session holds another.
+    pub fn set_payout_mint(ctx: Context<SetProviderConfig>, payout_mint: Option<Pubkey>) -> Result<()> {
+        let provider_account = &mut ctx.accounts.provider_account;
+        provider_account.payout_mint = payout_mint.unwrap_or_default();
+
+        emit!(PayoutMintSet {
+            provider: provider_account.provider,
+            payout_mint,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Discount each session's first purchase from the provider by
+    /// `trial_discount_bps`; 10000 makes it free and 0 ends the trial.
+    /// Applied by `execute_purchase` when the provider account is passed.
+    pub fn set_trial_discount(ctx: Context<SetProviderConfig>, trial_discount_bps: u16) -> Result<()> {
+        require!(trial_discount_bps <= BPS_DENOMINATOR, ErrorCode::InvalidFeeBps);
+
+        let provider_account = &mut ctx.accounts.provider_account;
+        provider_account.trial_discount_bps = trial_discount_bps;
+
+        emit!(TrialDiscountSet {
+            provider: provider_account.provider,
+            trial_discount_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Cap purchase volume per mint over windows of `volume_window_slots`;
+    /// a `max_window_volume` of 0 turns the breaker off (admin only)
+    pub fn set_circuit_breaker(
+        ctx: Context<SetCircuitBreaker>,
+        volume_window_slots: u64,
+        max_window_volume: u64,
+    ) -> Result<()> {
+        require!(
+            max_window_volume == 0 || volume_window_slots > 0,
+            ErrorCode::InvalidCircuitBreaker
+        );
+
+        let config = &mut ctx.accounts.config;
+        config.volume_window_slots = volume_window_slots;
+        config.max_window_volume = max_window_volume;
+
+        emit!(CircuitBreakerSet {
+            volume_window_slots,
+            max_window_volume,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Open a fresh circuit breaker window for a mint, clearing its volume (admin only)
+    pub fn reset_circuit_breaker(ctx: Context<ResetCircuitBreaker>, _mint: Pubkey) -> Result<()> {
+        let global_stats = &mut ctx.accounts.global_stats;
+        let cleared_volume = global_stats.window_volume;
+        global_stats.window_start_slot = Clock::get()?.slot;
+        global_stats.window_volume = 0;
+
+        emit!(CircuitBreakerReset {
+            mint: global_stats.mint,
+            cleared_volume,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Replace a closed session with a SessionTombstone at the same address,
+    /// returning the rent above the tombstone's minimum to the session authority.
+    /// The PDA stays allocated, so the session id can never be initialized again.
+    pub fn archive_session(ctx: Context<ArchiveSession>) -> Result<()> {
+        let info = ctx.accounts.session_wallet.to_account_info();
+        let signer = ctx.accounts.authority.key();
+
+        let tombstone = {
+            let data = info.try_borrow_data()?;
+            let session_wallet = session_wallet_from_bytes(&data)?;
+
+            let is_owner = session_wallet.authority == signer
+                || ctx.accounts.role_assignment.as_ref().is_some_and(|assignment| {
+                    assignment.session == info.key()
+                        && assignment.member == signer
+                        && assignment.role.permits(Role::Owner)
+                });
+            require!(is_owner, ErrorCode::Unauthorized);
+            require!(!session_wallet.is_active(), ErrorCode::SessionStillOpen);
+            require!(session_wallet.escrowed_balance == 0, ErrorCode::EscrowOutstanding);
+            require_keys_eq!(
+                ctx.accounts.session_authority.key(),
+                session_wallet.authority,
+                ErrorCode::Unauthorized
+            );
+
+            SessionTombstone {
+                authority: session_wallet.authority,
+                mint: session_wallet.mint,
+                session_id: session_wallet.session_id().to_string(),
+                created_at: session_wallet.created_at,
+                archived_at: Clock::get()?.unix_timestamp,
+                total_funded: session_wallet.total_funded,
+                total_spent: session_wallet.total_spent,
+                total_rebated: session_wallet.total_rebated,
+                purchase_count: session_wallet.purchase_count,
+                last_event_seq: session_wallet.last_event_seq,
+                state_hash: hash(bytemuck::bytes_of(session_wallet)).to_bytes(),
+                bump: session_wallet.bump,
+            }
+        };
+
+        // Shrink in place and overwrite the discriminator, so loaders expecting a
+        // SessionWallet reject the account from here on
+        info.realloc(8 + SessionTombstone::SIZE, false)?;
+        {
+            let mut data = info.try_borrow_mut_data()?;
+            let mut writer: &mut [u8] = &mut data[..];
+            tombstone.try_serialize(&mut writer)?;
+        }
+
+        let rent_exempt = Rent::get()?.minimum_balance(info.data_len());
+        let reclaimed = info.lamports().saturating_sub(rent_exempt);
+        **info.try_borrow_mut_lamports()? -= reclaimed;
+        **ctx.accounts.session_authority.try_borrow_mut_lamports()? += reclaimed;
+
+        emit!(SessionArchived {
+            session_id: tombstone.session_id,
+            tombstone: info.key(),
+            state_hash: tombstone.state_hash,
+            reclaimed_lamports: reclaimed,
+            timestamp: tombstone.archived_at,
+        });
+
+        Ok(())
+    }
+
+    /// Pay each leg of a multi-provider workflow in order and record them in
+    /// one WorkflowReceipt. The remaining accounts are `LEG_ACCOUNT_COUNT`
+    /// accounts for each leg, in order: its provider token account, the
+    /// provider's registry PDA, the provider's listing PDA for the leg's
+    /// service id, then the provider's fee tier PDA. Any leg failing its
+    /// checks fails the whole transaction, so no leg is paid.
+    pub fn execute_workflow_purchase<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteWorkflowPurchase<'info>>,
+        workflow_id: String,
+        legs: Vec<BatchPurchase>,
+    ) -> Result<()> {
+        require!(
+            workflow_id.len() <= WorkflowReceipt::MAX_WORKFLOW_ID_LEN,
+            ErrorCode::WorkflowIdTooLong
+        );
+        check_composition(&*ctx.accounts.session_wallet.load()?, &ctx.accounts.instructions)?;
+        require!(
+            !legs.is_empty()
+                && legs.len() <= WorkflowReceipt::MAX_LEGS
+                && ctx.remaining_accounts.len() == legs.len() * LEG_ACCOUNT_COUNT,
+            ErrorCode::InvalidWorkflow
+        );
+
+        // Match every leg to its account and the total to the balance before
+        // paying anything
+        let mut leg_accounts = Vec::with_capacity(legs.len());
+        let mut total_amount = 0u64;
+        for (leg, accounts) in legs.iter().zip(ctx.remaining_accounts.chunks(LEG_ACCOUNT_COUNT)) {
+            require!(
+                leg.service_id.len() <= PurchaseReceipt::MAX_SERVICE_ID_LEN,
+                ErrorCode::ServiceIdTooLong
+            );
+            leg_accounts.push(load_leg_accounts(accounts, leg, ErrorCode::InvalidWorkflow)?);
+            total_amount = total_amount.checked_add(leg.amount).ok_or(ErrorCode::Overflow)?;
+        }
+        require!(
+            ctx.accounts.session_wallet.load()?.current_balance >= total_amount,
+            ErrorCode::InsufficientBalance
+        );
+
+        let first_purchase_index = ctx.accounts.session_wallet.load()?.purchase_count;
+        let mut receipt_legs = Vec::with_capacity(legs.len());
+        for (leg, leg_accounts) in legs.iter().zip(leg_accounts.iter()) {
+            let service_provider_token_account = &leg_accounts.provider_token_account;
+            let amount = apply_trial_discount(
+                leg_accounts.provider_account.as_ref(),
+                &*ctx.accounts.session_wallet.load()?,
+                &leg.service_id,
+                leg.amount,
+            )?;
+            let category_code = lookup_category_code(ctx.accounts.category_codes.as_deref(), &leg.service_id);
+            let purchase = PurchaseContext {
+                provider: service_provider_token_account.owner,
+                service_id: &leg.service_id,
+                amount,
+                timestamp: Clock::get()?.unix_timestamp,
+                credit_available: 0,
+                currency_balance: None,
+                category_code,
+                service_listing: leg_accounts.service_listing.as_ref(),
+                payout_mint: service_provider_token_account.mint,
+                provider_account: leg_accounts.provider_account.as_ref(),
+            };
+            check_purchase(
+                &*ctx.accounts.session_wallet.load()?,
+                ctx.accounts.policy.as_ref(),
+                &purchase,
+            )?;
+
+            track_purchase_volume(&ctx.accounts.config, &mut ctx.accounts.global_stats, amount)?;
+
+            charge_operator(
+                &*ctx.accounts.session_wallet.load()?,
+                ctx.accounts.authority.key(),
+                ctx.accounts.role_assignment.as_mut(),
+                amount,
+            )?;
+
+            let fee = collect_fee(
+                &*ctx.accounts.session_wallet.load()?,
+                &ctx.accounts.fee_config,
+                ctx.accounts.agent_fee_tier.as_deref(),
+                leg_accounts.provider_fee_tier.as_ref(),
+                &mut ctx.accounts.treasury,
+                &purchase,
+            )?;
+
+            settle_purchase(
+                &ctx.accounts.session_wallet,
+                &ctx.accounts.session_token_account,
+                service_provider_token_account,
+                &ctx.accounts.token_program,
+                &leg.service_id,
+                amount,
+                (&ctx.accounts.treasury_vault, fee),
+            )?;
+
+            receipt_legs.push(WorkflowLeg {
+                provider: service_provider_token_account.owner,
+                service_id: leg.service_id.clone(),
+                amount,
+            });
+        }
+
+        // Trial discounts can leave the amount paid below the legs' total
+        let total_amount = receipt_legs.iter().map(|leg| leg.amount).sum();
+        let timestamp = Clock::get()?.unix_timestamp;
+        let receipt = &mut ctx.accounts.workflow_receipt;
+        receipt.session = ctx.accounts.session_wallet.key();
+        receipt.workflow_id = workflow_id.clone();
+        receipt.legs = receipt_legs;
+        receipt.total_amount = total_amount;
+        receipt.first_purchase_index = first_purchase_index;
+        receipt.timestamp = timestamp;
+        receipt.bump = ctx.bumps.workflow_receipt;
+
+        emit!(WorkflowPurchaseExecuted {
+            session_id: ctx.accounts.session_wallet.load()?.session_id().to_string(),
+            workflow_id,
+            receipt: receipt.key(),
+            leg_count: legs.len() as u8,
+            total_amount,
+            remaining_balance: ctx.accounts.session_wallet.load()?.current_balance,
+            timestamp,
+        });
+
+        ctx.accounts.session_wallet.load_mut()?.record_event()?;
+
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Helpers
+// ============================================================================
+
+/// Seed for the durable nonce account derived from the session PDA
+pub const SESSION_NONCE_SEED: &str = "nonce";
+
+/// PDA seed of `SessionWallet`, keyed on the session id
+pub const SESSION_SEED: &[u8] = b"session";
+
+/// PDA seed of the program `Config`
+pub const CONFIG_SEED: &[u8] = b"config";
+
+/// PDA seed of `GlobalStats`, one per session mint
+pub const GLOBAL_STATS_SEED: &[u8] = b"global_stats";
+
+/// PDA seed of `Treasury`, one per session mint
+pub const TREASURY_SEED: &[u8] = b"treasury";
+
+/// PDA seed of a treasury's token account
+pub const TREASURY_VAULT_SEED: &[u8] = b"treasury_vault";
+
+/// PDA seed of the platform `FeeConfig`
+pub const FEE_CONFIG_SEED: &[u8] = b"fee_config";
+
+/// PDA seed of `FeeTier`, keyed on the agent or provider it discounts
+pub const FEE_TIER_SEED: &[u8] = b"fee_tier";
+
+/// PDA seed of the `CategoryCodeTable`
+pub const CATEGORY_CODES_SEED: &[u8] = b"category_codes";
+
+/// PDA seed of `Policy`, keyed on the authority and policy id
+pub const POLICY_SEED: &[u8] = b"policy";
+
+/// PDA seed of `AgentAccount`, keyed on the agent authority
+pub const AGENT_SEED: &[u8] = b"agent";
+
+/// PDA seed of `ProviderAccount`, keyed on the provider's wallet
+pub const PROVIDER_SEED: &[u8] = b"provider";
+
+/// PDA seed of `ServiceListing`, keyed on the provider and `service_listing_seed`
+pub const SERVICE_SEED: &[u8] = b"service";
+
+/// PDA seed of `RoleAssignment`, keyed on the session and member
+pub const ROLE_SEED: &[u8] = b"role";
+
+/// PDA seed of `PurchaseReceipt`, keyed on the session and purchase index
+pub const RECEIPT_SEED: &[u8] = b"receipt";
+
+/// PDA seed of a receipt token's mint, keyed on the receipt
+pub const RECEIPT_MINT_SEED: &[u8] = b"receipt_mint";
+
+/// PDA seed of `ReceiptMetadata`, keyed on the receipt mint
+pub const RECEIPT_METADATA_SEED: &[u8] = b"receipt_metadata";
+
+/// PDA seed of `RedeemedVoucher`, keyed on the session and voucher hash
+pub const VOUCHER_SEED: &[u8] = b"voucher";
+
+/// PDA seed of `FundingPermit`, keyed on the session, funder and nonce
+pub const PERMIT_SEED: &[u8] = b"permit";
+
+/// PDA seed of `SessionSnapshot`, keyed on the session and snapshot index
+pub const SNAPSHOT_SEED: &[u8] = b"snapshot";
+
+/// PDA seed of `SessionCheckpoint`, keyed on the session
+pub const CHECKPOINT_SEED: &[u8] = b"checkpoint";
+
+/// PDA seed of `SessionTemplate`, keyed on the authority and template id
+pub const TEMPLATE_SEED: &[u8] = b"template";
+
+/// PDA seed of `Debt`, keyed on the agent account and mint
+pub const DEBT_SEED: &[u8] = b"debt";
+
+/// PDA seed of `NettingChannel` between a session and a provider
+pub const CHANNEL_SEED: &[u8] = b"channel";
+
+/// PDA seed of `ConditionalEscrow`, keyed on the session and escrow id
+pub const ESCROW_SEED: &[u8] = b"escrow";
+
+/// PDA seed of `PayoutLedger`, keyed on the provider and mint
+pub const PAYOUT_SEED: &[u8] = b"payout";
+
+/// PDA seed of a payout ledger's token account
+pub const PAYOUT_VAULT_SEED: &[u8] = b"payout_vault";
+
+/// PDA seed of `CurrencyBalance`, one per session and secondary mint
+pub const BALANCE_SEED: &[u8] = b"balance";
+
+/// PDA seed of a currency balance's token account
+pub const BALANCE_VAULT_SEED: &[u8] = b"balance_vault";
+
+/// PDA seed of a session's vault shard, keyed on the shard index
+pub const SHARD_SEED: &[u8] = b"shard";
+
+/// PDA seed of `BatchState`, keyed on the session and batch id
+pub const BATCH_SEED: &[u8] = b"batch";
+
+/// PDA seed of `WorkflowReceipt`, keyed on the session and workflow id
+pub const WORKFLOW_SEED: &[u8] = b"workflow";
+
+/// PDA seed of `Capability`, keyed on the session and ephemeral key
+pub const CAPABILITY_SEED: &[u8] = b"capability";
+
+/// PDA seed of `PurchaseRating`, keyed on the receipt
+pub const RATING_SEED: &[u8] = b"rating";
+
+/// PDA seed of a bond claim's token account
+pub const BOND_VAULT_SEED: &[u8] = b"bond_vault";
+
+/// PDA seed of `BondClaim`
+pub const BOND_CLAIM_SEED: &[u8] = b"bond_claim";
+
+/// PDA seed of the `SwapConfig`
+pub const SWAP_CONFIG_SEED: &[u8] = b"swap_config";
+
+/// PDA seed of the `BridgeConfig`
+pub const BRIDGE_CONFIG_SEED: &[u8] = b"bridge_config";
+
+/// PDA seed of the `AutomationConfig`
+pub const AUTOMATION_CONFIG_SEED: &[u8] = b"automation_config";
+
+/// PDA seed of `CompressedSessionTree`, keyed on the merkle tree
+pub const SESSION_TREE_SEED: &[u8] = b"session_tree";
+
+/// PDA seed of a compressed session tree's pooled vault
+pub const SESSION_TREE_VAULT_SEED: &[u8] = b"session_tree_vault";
+
+/// Set up the state of a freshly initialized session wallet
+fn open_session(
+    session_wallet: &mut SessionWallet,
+    authority: Pubkey,
+    session_id: &str,
+    mint: Pubkey,
+    initial_funding: u64,
+    bump: u8,
+) -> Result<()> {
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    session_wallet.authority = authority;
+    session_wallet.set_session_id(session_id)?;
+    session_wallet.created_at = timestamp;
+    session_wallet.last_activity = timestamp;
+    session_wallet.mint = mint;
+    session_wallet.initial_balance = initial_funding;
+    session_wallet.current_balance = initial_funding;
+    session_wallet.total_funded = initial_funding;
+    session_wallet.set_active(true);
+    session_wallet.bump = bump;
+
+    Ok(())
+}
+
+/// Fund a session from the treasury vault, recording the disbursement
+fn disburse_from_treasury<'info>(
+    treasury: &mut Account<'info, Treasury>,
+    treasury_vault: &Account<'info, TokenAccount>,
+    destination: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    treasury.total_disbursed = treasury
+        .total_disbursed
+        .checked_add(amount)
+        .ok_or(ErrorCode::Overflow)?;
+
+    transfer_from_treasury(treasury, treasury_vault, destination, token_program, amount)
+}
+
+/// Move tokens out of a treasury vault, signing as the treasury PDA
+fn transfer_from_treasury<'info>(
+    treasury: &Account<'info, Treasury>,
+    treasury_vault: &Account<'info, TokenAccount>,
+    destination: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    let mint = treasury.mint;
+    let seeds = &[TREASURY_SEED, mint.as_ref(), &[treasury.bump]];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: treasury_vault.to_account_info(),
+        to: destination.clone(),
+        authority: treasury.to_account_info(),
+    };
+
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+    token::transfer(cpi_ctx, amount)
+}
+
+/// Debit the session balance and pay the provider, signing as the session
+/// PDA. `fee` names the fee vault and the part of `amount` it is sent; the
+/// rest goes to the provider.
+fn settle_purchase<'info>(
+    session_wallet: &AccountLoader<'info, SessionWallet>,
+    session_token_account: &Account<'info, TokenAccount>,
+    service_provider_token_account: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    service_id: &str,
+    amount: u64,
+    fee: (&Account<'info, TokenAccount>, u64),
+) -> Result<()> {
+    {
+        let mut session = session_wallet.load_mut()?;
+        require!(session.is_active(), ErrorCode::SessionClosed);
+        require!(session.current_balance >= amount, ErrorCode::InsufficientBalance);
+
+        // Update balance
+        session.current_balance = session
+            .current_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        record_purchase(
+            &mut session,
+            service_provider_token_account.owner,
+            service_id,
+            amount,
+            Clock::get()?.unix_timestamp,
+        )?;
+    }
+
+    let (fee_vault, fee_amount) = fee;
+    if fee_amount > 0 {
+        transfer_from_session(
+            session_wallet,
+            session_token_account,
+            &fee_vault.to_account_info(),
+            token_program,
+            fee_amount,
+        )?;
+    }
+
+    // Transfer USDC from session wallet to service provider
+    transfer_from_session(
+        session_wallet,
+        session_token_account,
+        &service_provider_token_account.to_account_info(),
+        token_program,
+        amount - fee_amount,
+    )
+}
+
+/// Settle a purchase paid from one of the session's secondary mint vaults,
+/// sending `fee` to the fee vault as `settle_purchase` does
+fn settle_currency_purchase<'info>(
+    session_wallet: &AccountLoader<'info, SessionWallet>,
+    currency_balance: &AccountLoader<'info, CurrencyBalance>,
+    vault: &Account<'info, TokenAccount>,
+    service_provider_token_account: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+    fee: (&Account<'info, TokenAccount>, u64),
+) -> Result<()> {
+    {
+        let mut currency_balance = currency_balance.load_mut()?;
+        require_keys_eq!(vault.key(), currency_balance.vault, ErrorCode::InvalidCurrencyBalance);
+        require!(
+            currency_balance.current_balance >= amount,
+            ErrorCode::InsufficientBalance
+        );
+
+        currency_balance.current_balance -= amount;
+        currency_balance.total_spent = currency_balance
+            .total_spent
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        // Spend totals, budgets and velocity stay in session mint units
+        let mut session = session_wallet.load_mut()?;
+        session.purchase_count = session
+            .purchase_count
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
+        session.last_activity = Clock::get()?.unix_timestamp;
+    }
+
+    let (fee_vault, fee_amount) = fee;
+    if fee_amount > 0 {
+        transfer_from_session(session_wallet, vault, &fee_vault.to_account_info(), token_program, fee_amount)?;
+    }
+
+    transfer_from_session(
+        session_wallet,
+        vault,
+        &service_provider_token_account.to_account_info(),
+        token_program,
+        amount - fee_amount,
+    )
+}
+
+/// Update spend counters and category budgets for a purchase the caller has already debited
+fn record_purchase(
+    session_wallet: &mut SessionWallet,
+    provider: Pubkey,
+    service_id: &str,
+    amount: u64,
+    timestamp: i64,
+) -> Result<()> {
+    session_wallet.total_spent = session_wallet
+        .total_spent
+        .checked_add(amount)
+        .ok_or(ErrorCode::Overflow)?;
+
+    session_wallet.purchase_count = session_wallet
+        .purchase_count
+        .checked_add(1)
+        .ok_or(ErrorCode::Overflow)?;
+
+    if let Some(budget) = session_wallet
+        .category_budgets_mut()
+        .iter_mut()
+        .find(|budget| service_id.starts_with(budget.prefix()))
+    {
+        budget.spent = budget.spent.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+    }
+
+    session_wallet.record_provider_spend(provider, amount)?;
+
+    session_wallet.last_activity = timestamp;
+
+    track_velocity(session_wallet, amount, timestamp)?;
+
+    if session_wallet.expires_at != 0
+        && session_wallet.expiry_grace_period > 0
+        && timestamp >= session_wallet.expires_at.saturating_sub(session_wallet.expiry_grace_period)
+    {
+        emit!(SessionExpiringSoon {
+            session_id: session_wallet.session_id().to_string(),
+            expires_at: session_wallet.expires_at,
+            seconds_remaining: session_wallet.expires_at.saturating_sub(timestamp),
+            timestamp,
+        });
+    }
+
+    Ok(())
+}
+
+/// Fold a purchase into the velocity window, suspending the session when the
+/// window's spend runs past `velocity_multiple` times the moving average
+fn track_velocity(session_wallet: &mut SessionWallet, amount: u64, timestamp: i64) -> Result<()> {
+    if session_wallet.velocity_window == 0 {
+        return Ok(());
+    }
+
+    let elapsed = timestamp.saturating_sub(session_wallet.window_start);
+    if elapsed >= session_wallet.velocity_window {
+        // Windows with no purchases count as zero spend
+        let mut average = session_wallet.average_window_spend;
+        let closed_windows = (elapsed / session_wallet.velocity_window).min(VELOCITY_AVERAGE_WEIGHT as i64);
+        for window in 0..closed_windows {
+            let spent = if window == 0 { session_wallet.window_spent } else { 0 };
+            // The first window with spend seeds the average outright
+            average = if average == 0 {
+                spent
+            } else {
+                average
+                    .saturating_mul(VELOCITY_AVERAGE_WEIGHT - 1)
+                    .saturating_add(spent)
+                    / VELOCITY_AVERAGE_WEIGHT
+            };
+        }
+        session_wallet.average_window_spend = average;
+        session_wallet.window_start = timestamp;
+        session_wallet.window_spent = 0;
+    }
+
+    session_wallet.window_spent = session_wallet
+        .window_spent
+        .checked_add(amount)
+        .ok_or(ErrorCode::Overflow)?;
+
+    // Without a history there is nothing to compare against
+    let threshold = session_wallet
+        .average_window_spend
+        .saturating_mul(session_wallet.velocity_multiple as u64);
+    if session_wallet.average_window_spend > 0 && session_wallet.window_spent > threshold {
+        session_wallet.set_suspended(true);
+
+        emit!(AnomalyDetected {
+            session_id: session_wallet.session_id().to_string(),
+            window_spent: session_wallet.window_spent,
+            average_window_spend: session_wallet.average_window_spend,
+            velocity_multiple: session_wallet.velocity_multiple,
+            timestamp,
+        });
+    }
+
+    Ok(())
+}
+
+/// Move tokens out of the session token account, signing as the session PDA
+fn transfer_from_session<'info>(
+    session_wallet: &AccountLoader<'info, SessionWallet>,
+    session_token_account: &Account<'info, TokenAccount>,
+    destination: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    let session_seeds = session_wallet.load()?.signer_seeds();
+    let seeds = session_seeds.seeds();
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: session_token_account.to_account_info(),
+        to: destination.clone(),
+        authority: session_wallet.to_account_info(),
+    };
+
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+    token::transfer(cpi_ctx, amount)
+}
+
+/// Domain separator prefixed to every signed purchase intent
+pub const PURCHASE_INTENT_DOMAIN: &[u8] = b"session-wallet:purchase-intent:v1";
+
+/// Canonical bytes an agent signs to authorize a purchase:
+/// domain || session || pay_to || amount (LE) || nonce (LE) || service_id,
+/// where `pay_to` is the provider token account the purchase pays
+pub fn purchase_intent_message(
+    session: &Pubkey,
+    pay_to: &Pubkey,
+    service_id: &str,
+    amount: u64,
+    nonce: u64,
+) -> Vec<u8> {
+    let mut message =
+        Vec::with_capacity(PURCHASE_INTENT_DOMAIN.len() + 32 + 32 + 8 + 8 + service_id.len());
+    message.extend_from_slice(PURCHASE_INTENT_DOMAIN);
+    message.extend_from_slice(session.as_ref());
+    message.extend_from_slice(pay_to.as_ref());
+    message.extend_from_slice(&amount.to_le_bytes());
+    message.extend_from_slice(&nonce.to_le_bytes());
+    message.extend_from_slice(service_id.as_bytes());
+    message
+}
+
+/// Domain separator prefixed to every funding permit
+pub const FUNDING_PERMIT_DOMAIN: &[u8] = b"session-wallet:funding-permit:v1";
+
+/// Canonical bytes a funder signs to let a session pull a top-up:
+/// domain || session || amount (LE) || deadline (LE) || nonce (LE)
+pub fn funding_permit_message(session: &Pubkey, amount: u64, deadline: i64, nonce: u64) -> Vec<u8> {
+    let mut message = Vec::with_capacity(FUNDING_PERMIT_DOMAIN.len() + 32 + 8 + 8 + 8);
+    message.extend_from_slice(FUNDING_PERMIT_DOMAIN);
+    message.extend_from_slice(session.as_ref());
+    message.extend_from_slice(&amount.to_le_bytes());
+    message.extend_from_slice(&deadline.to_le_bytes());
+    message.extend_from_slice(&nonce.to_le_bytes());
+    message
+}
+
+/// Domain separator prefixed to every verifier attestation
+pub const ATTESTATION_DOMAIN: &[u8] = b"session-wallet:attestation:v1";
+
+/// Canonical bytes a verifier signs to release an escrow:
+/// domain || escrow || status_code (LE) || output_hash
+pub fn attestation_message(escrow: &Pubkey, status_code: u16, output_hash: &[u8; 32]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(ATTESTATION_DOMAIN.len() + 32 + 2 + 32);
+    message.extend_from_slice(ATTESTATION_DOMAIN);
+    message.extend_from_slice(escrow.as_ref());
+    message.extend_from_slice(&status_code.to_le_bytes());
+    message.extend_from_slice(output_hash);
+    message
+}
+
+/// Domain separator prefixed to every signed HTTP 402 voucher
+pub const HTTP402_VOUCHER_DOMAIN: &[u8] = b"session-wallet:http402-voucher:v1";
+
+/// Canonical bytes an agent signs for an HTTP 402 voucher:
+/// domain || session || pay_to || amount (LE) || resource_hash || expiry (LE)
+pub fn http402_voucher_message(
+    session: &Pubkey,
+    pay_to: &Pubkey,
+    voucher: &Http402Voucher,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HTTP402_VOUCHER_DOMAIN.len() + 32 + 32 + 8 + 32 + 8);
+    message.extend_from_slice(HTTP402_VOUCHER_DOMAIN);
+    message.extend_from_slice(session.as_ref());
+    message.extend_from_slice(pay_to.as_ref());
+    message.extend_from_slice(&voucher.amount.to_le_bytes());
+    message.extend_from_slice(&voucher.resource_hash);
+    message.extend_from_slice(&voucher.expiry.to_le_bytes());
+    message
+}
+
+/// Check that the previous instruction is an ed25519 program verification of
+/// exactly one signature by `signer` over `message`, with all data inline
+fn verify_ed25519_instruction(
+    instructions: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<()> {
+    // Layout of the single signature offsets entry, see ed25519_program
+    const OFFSETS_START: usize = 2;
+    const OFFSETS_SIZE: usize = 14;
+
+    let ix = get_instruction_relative(-1, instructions)
+        .map_err(|_| error!(ErrorCode::InvalidSignature))?;
+
+    require_keys_eq!(ix.program_id, ed25519_program::ID, ErrorCode::InvalidSignature);
+
+    let data = &ix.data;
+    require!(
+        data.len() >= OFFSETS_START + OFFSETS_SIZE && data[0] == 1,
+        ErrorCode::InvalidSignature
+    );
+
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let offsets = OFFSETS_START;
+    let signature_ix_index = read_u16(offsets + 2);
+    let public_key_offset = read_u16(offsets + 4) as usize;
+    let public_key_ix_index = read_u16(offsets + 6);
+    let message_offset = read_u16(offsets + 8) as usize;
+    let message_size = read_u16(offsets + 10) as usize;
+    let message_ix_index = read_u16(offsets + 12);
+
+    // Signature, key and message must all live in the ed25519 instruction itself
+    require!(
+        signature_ix_index == u16::MAX
+            && public_key_ix_index == u16::MAX
+            && message_ix_index == u16::MAX,
+        ErrorCode::InvalidSignature
+    );
+
+    let public_key = data
+        .get(public_key_offset..public_key_offset + 32)
+        .ok_or(ErrorCode::InvalidSignature)?;
+    let signed_message = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(ErrorCode::InvalidSignature)?;
+
+    require!(public_key == signer.as_ref(), ErrorCode::InvalidSignature);
+    require!(signed_message == message, ErrorCode::InvalidSignature);
+
+    Ok(())
+}
+
+/// Purchase attributes evaluated by the policy engine
+pub struct PurchaseContext<'a> {
+    pub provider: Pubkey,
+    pub service_id: &'a str,
+    pub amount: u64,
+    pub timestamp: i64,
+    pub credit_available: u64, // Credit line headroom on top of the session balance
+    pub currency_balance: Option<u64>, // Paying from a secondary mint instead of the session mint
+    pub category_code: Option<u32>, // Reporting code the service id maps to
+    pub service_listing: Option<&'a ServiceListing>, // Provider's listing, checked for deprecation
+    pub payout_mint: Pubkey,      // Mint the provider is paid in
+    pub provider_account: Option<&'a ProviderAccount>, // Provider's registry entry, checked for its payout mint
+}
+
+/// Run the session state, balance and policy checks for a purchase
+fn evaluate_purchase(
+    session_wallet: &SessionWallet,
+    policy: Option<&Account<Policy>>,
+    purchase: &PurchaseContext,
+) -> std::result::Result<(), RejectionReason> {
+    if !session_wallet.is_active() {
+        return Err(RejectionReason::SessionClosed);
+    }
+    if session_wallet.is_suspended() {
+        return Err(RejectionReason::SessionSuspended);
+    }
+    if session_wallet.expires_at != 0 && purchase.timestamp > session_wallet.expires_at {
+        return Err(RejectionReason::SessionExpired);
+    }
+    if service_deprecated_after(purchase).is_some_and(|after| purchase.timestamp >= after) {
+        return Err(RejectionReason::ServiceDeprecated);
+    }
+    // Paying a registered provider in another mint is execute_purchase_with_swap's job
+    if purchase
+        .provider_account
+        .and_then(ProviderAccount::payout_mint)
+        .is_some_and(|payout_mint| payout_mint != purchase.payout_mint)
+    {
+        return Err(RejectionReason::PayoutMintMismatch);
+    }
+    let balance = purchase.currency_balance.unwrap_or(session_wallet.current_balance);
+    if balance.saturating_add(purchase.credit_available) < purchase.amount {
+        return Err(RejectionReason::InsufficientBalance);
+    }
+    // Category budgets are denominated in the session mint
+    let budget = session_wallet
+        .category_budgets()
+        .iter()
+        .find(|budget| purchase.service_id.starts_with(budget.prefix()));
+    if let (Some(budget), None) = (budget, purchase.currency_balance) {
+        if budget.spent.saturating_add(purchase.amount) > budget.limit {
+            return Err(RejectionReason::CategoryBudgetExceeded);
+        }
+    }
+    if session_wallet.category_codes_required() && purchase.category_code.is_none() {
+        return Err(RejectionReason::UnknownCategoryCode);
+    }
+
+    check_policy(session_wallet, policy, purchase)
+}
+
+/// Evaluate the session's attached policy, if any, against a purchase
+fn check_policy(
+    session_wallet: &SessionWallet,
+    policy: Option<&Account<Policy>>,
+    purchase: &PurchaseContext,
+) -> std::result::Result<(), RejectionReason> {
+    let Some(policy_key) = session_wallet.policy() else {
+        return Ok(());
+    };
+
+    match policy {
+        Some(policy) if policy.key() == policy_key => policy.evaluate(purchase),
+        _ => Err(RejectionReason::PolicyAccountMismatch),
+    }
+}
+
+/// Check a purchase, emitting `PurchaseRejected` and failing with the error
+/// code its `RejectionReason` maps to. See `RejectionReason` for how
+/// indexers read events from the failed transaction.
+fn check_purchase(
+    session_wallet: &SessionWallet,
+    policy: Option<&Account<Policy>>,
+    purchase: &PurchaseContext,
+) -> Result<()> {
+    evaluate_purchase(session_wallet, policy, purchase).map_err(|reason| {
+        if let (RejectionReason::ServiceDeprecated, Some(deprecated_after)) =
+            (reason, service_deprecated_after(purchase))
+        {
+            emit!(ServiceDeprecated {
+                session_id: session_wallet.session_id().to_string(),
+                service_id: purchase.service_id.to_string(),
+                provider: purchase.provider,
+                deprecated_after,
+                timestamp: purchase.timestamp,
+            });
+        }
+        emit!(PurchaseRejected {
+            session_id: session_wallet.session_id().to_string(),
+            service_id: purchase.service_id.to_string(),
+            provider: purchase.provider,
+            amount: purchase.amount,
+            reason,
+            timestamp: purchase.timestamp,
+        });
+        error!(ErrorCode::from(reason))
+    })
+}
+
+/// Emit `FundingRejected` and fail with the matching error code
+fn reject_funding(
+    session_wallet: &SessionWallet,
+    funder: Pubkey,
+    amount: u64,
+    reason: RejectionReason,
+) -> Result<()> {
+    emit!(FundingRejected {
+        session_id: session_wallet.session_id().to_string(),
+        funder,
+        amount,
+        reason,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Err(error!(ErrorCode::from(reason)))
+}
+
+fn validate_policy_rules(rules: &[PolicyRule]) -> Result<()> {
+    require!(rules.len() <= Policy::MAX_RULES, ErrorCode::TooManyPolicyRules);
+
+    for rule in rules {
+        match rule {
+            PolicyRule::TimeWindow {
+                not_before,
+                not_after,
+            } => require!(not_before <= not_after, ErrorCode::InvalidPolicyRule),
+            PolicyRule::AllowCategory { prefix } => require!(
+                !prefix.is_empty() && prefix.len() <= PolicyRule::MAX_CATEGORY_LEN,
+                ErrorCode::InvalidPolicyRule
+            ),
+            PolicyRule::MaxSlippage { bps } => {
+                require!(*bps <= BPS_DENOMINATOR, ErrorCode::InvalidPolicyRule)
+            }
+            PolicyRule::MaxAmount { .. } | PolicyRule::AllowProvider { .. } => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// spl-account-compression program
+pub mod spl_account_compression {
+    anchor_lang::declare_id!("cmtDvXumGCrqC1Age74AVPhSRVXJMd8PJS91L8KbNCK");
+}
+
+/// Compute budget program
+pub mod compute_budget {
+    anchor_lang::declare_id!("ComputeBudget111111111111111111111111111111");
+}
+
+/// Instruction data of a system program AdvanceNonceAccount
+const ADVANCE_NONCE_DATA: [u8; 4] = 4u32.to_le_bytes();
+
+/// Refuse a purchase invoked by another program, or sharing its transaction
+/// with anything beyond this program's instructions, compute budget requests,
+/// ed25519 verifications and a leading durable nonce advance. Sessions that
+/// set `allow_cpi` skip the check.
+fn check_composition(session_wallet: &SessionWallet, instructions: &AccountInfo) -> Result<()> {
+    if session_wallet.allow_cpi() {
+        return Ok(());
+    }
+
+    // The instructions sysvar only lists top-level instructions, so a CPI
+    // caller shows up as the current instruction's program
+    let current = get_instruction_relative(0, instructions)
+        .map_err(|_| error!(ErrorCode::UnexpectedComposition))?;
+    require_keys_eq!(current.program_id, crate::ID, ErrorCode::UnexpectedComposition);
+
+    let mut index = 0;
+    while let Ok(ix) = load_instruction_at_checked(index, instructions) {
+        let allowed = ix.program_id == crate::ID
+            || ix.program_id == compute_budget::ID
+            || ix.program_id == ed25519_program::ID
+            || (index == 0
+                && ix.program_id == anchor_lang::system_program::ID
+                && ix.data == ADVANCE_NONCE_DATA);
+        require!(allowed, ErrorCode::UnexpectedComposition);
+        index += 1;
+    }
+
+    Ok(())
+}
+
+/// spl-noop program, used by compression to log changelogs
+pub mod spl_noop {
+    anchor_lang::declare_id!("noopb9bkMVfRPU8AsbpTUg8AQkHtKwMYZiFUjNRtMmV");
+}
+
+// Anchor sighashes of the compression instructions we call
+const INIT_EMPTY_MERKLE_TREE_DISCRIMINATOR: [u8; 8] = [191, 11, 119, 7, 180, 107, 220, 110];
+const APPEND_DISCRIMINATOR: [u8; 8] = [149, 120, 18, 222, 236, 225, 88, 203];
+const REPLACE_LEAF_DISCRIMINATOR: [u8; 8] = [204, 165, 76, 100, 73, 147, 0, 128];
+
+/// Leaf hash of a compressed receipt. Disputes recompute this from the
+/// `CompressedReceiptAppended` event and prove it with the tree's `verify_leaf`.
+pub fn compressed_receipt_leaf(
+    session: &Pubkey,
+    provider: &Pubkey,
+    service_id: &str,
+    amount: u64,
+    purchase_index: u64,
+    timestamp: i64,
+) -> [u8; 32] {
+    hashv(&[
+        session.as_ref(),
+        provider.as_ref(),
+        service_id.as_bytes(),
+        &amount.to_le_bytes(),
+        &purchase_index.to_le_bytes(),
+        &timestamp.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+/// Call a compression instruction whose accounts are (tree, authority, noop),
+/// with the session PDA as tree authority
+fn invoke_compression<'info>(
+    session_wallet: &AccountLoader<'info, SessionWallet>,
+    merkle_tree: &AccountInfo<'info>,
+    noop_program: &AccountInfo<'info>,
+    compression_program: &AccountInfo<'info>,
+    data: Vec<u8>,
+) -> Result<()> {
+    let ix = Instruction {
+        program_id: spl_account_compression::ID,
+        accounts: vec![
+            AccountMeta::new(merkle_tree.key(), false),
+            AccountMeta::new_readonly(session_wallet.key(), true),
+            AccountMeta::new_readonly(noop_program.key(), false),
+        ],
+        data,
+    };
+
+    let session_seeds = session_wallet.load()?.signer_seeds();
+    let seeds = session_seeds.seeds();
+
+    invoke_signed(
+        &ix,
+        &[
+            merkle_tree.clone(),
+            session_wallet.to_account_info(),
+            noop_program.clone(),
+            compression_program.clone(),
+        ],
+        &[&seeds[..]],
+    )?;
+
+    Ok(())
+}
+
+/// Call a compression instruction on a compressed session tree, with the
+/// tree's PDA as authority and `proof` appended as the leaf's proof nodes
+fn invoke_session_tree<'info>(
+    session_tree: &Account<'info, CompressedSessionTree>,
+    merkle_tree: &AccountInfo<'info>,
+    noop_program: &AccountInfo<'info>,
+    compression_program: &AccountInfo<'info>,
+    proof: &[AccountInfo<'info>],
+    data: Vec<u8>,
+) -> Result<()> {
+    let mut accounts = vec![
+        AccountMeta::new(merkle_tree.key(), false),
+        AccountMeta::new_readonly(session_tree.key(), true),
+        AccountMeta::new_readonly(noop_program.key(), false),
+    ];
+    accounts.extend(proof.iter().map(|node| AccountMeta::new_readonly(node.key(), false)));
+
+    let mut account_infos = vec![
+        merkle_tree.clone(),
+        session_tree.to_account_info(),
+        noop_program.clone(),
+        compression_program.clone(),
+    ];
+    account_infos.extend_from_slice(proof);
+
+    let ix = Instruction {
+        program_id: spl_account_compression::ID,
+        accounts,
+        data,
+    };
+
+    let merkle_tree_key = session_tree.merkle_tree;
+    let seeds = &[SESSION_TREE_SEED, merkle_tree_key.as_ref(), &[session_tree.bump]];
+
+    invoke_signed(&ix, &account_infos, &[&seeds[..]])?;
+
+    Ok(())
+}
+
+/// `replace_leaf` data moving the leaf at `leaf_index` from `previous` to
+/// `next`, and the new leaf. The compression program rejects it unless
+/// `previous` is the leaf proved under `root`.
+fn replace_compressed_session(
+    merkle_tree: &Pubkey,
+    root: [u8; 32],
+    leaf_index: u32,
+    previous: &CompressedSession,
+    next: &CompressedSession,
+) -> (Vec<u8>, [u8; 32]) {
+    let leaf = next.leaf(merkle_tree, leaf_index);
+
+    let mut data = REPLACE_LEAF_DISCRIMINATOR.to_vec();
+    data.extend_from_slice(&root);
+    data.extend_from_slice(&previous.leaf(merkle_tree, leaf_index));
+    data.extend_from_slice(&leaf);
+    data.extend_from_slice(&leaf_index.to_le_bytes());
+
+    (data, leaf)
+}
+
+/// Move tokens out of a compressed session tree's pooled vault, signing as the tree PDA
+fn transfer_from_session_tree<'info>(
+    session_tree: &Account<'info, CompressedSessionTree>,
+    vault: &Account<'info, TokenAccount>,
+    destination: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    let merkle_tree = session_tree.merkle_tree;
+    let seeds = &[SESSION_TREE_SEED, merkle_tree.as_ref(), &[session_tree.bump]];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: vault.to_account_info(),
+        to: destination.clone(),
+        authority: session_tree.to_account_info(),
+    };
+
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+    token::transfer(cpi_ctx, amount)
+}
+
+fn validate_category_budgets(category_budgets: &[CategoryBudget]) -> Result<()> {
+    require!(
+        category_budgets.len() <= MAX_CATEGORY_BUDGETS,
+        ErrorCode::TooManyCategoryBudgets
+    );
+
+    for budget in category_budgets {
+        require!(
+            !budget.prefix.is_empty() && budget.prefix.len() <= PolicyRule::MAX_CATEGORY_LEN,
+            ErrorCode::InvalidCategoryBudget
+        );
+    }
+
+    Ok(())
+}
+
+/// Closed windows folded into the velocity moving average, and its weight
+const VELOCITY_AVERAGE_WEIGHT: u64 = 8;
+
+/// Basis points in one whole
+const BPS_DENOMINATOR: u16 = 10_000;
+
+/// Invoke a configured external program (swap, automation), signing as the
+/// session PDA. Every remaining account is forwarded with its writable flag;
+/// the session PDA is marked as signer wherever it appears.
+fn invoke_as_session<'info>(
+    session_wallet: &AccountLoader<'info, SessionWallet>,
+    program: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    data: Vec<u8>,
+) -> Result<()> {
+    let session_key = session_wallet.key();
+    let ix = Instruction {
+        program_id: program.key(),
+        accounts: remaining_accounts
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: account.key(),
+                is_signer: account.is_signer || account.key() == session_key,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data,
+    };
+
+    let session_seeds = session_wallet.load()?.signer_seeds();
+    let seeds = session_seeds.seeds();
+
+    let mut account_infos = remaining_accounts.to_vec();
+    account_infos.push(program.clone());
+
+    invoke_signed(&ix, &account_infos, &[&seeds[..]])?;
+
+    Ok(())
+}
+
+/// Refuse to forward a writable token account owned by `session` other than
+/// those in `allowed` to a program the session signs for, so the call
+/// cannot move funds the instruction does not account for. Currency vaults
+/// and any other session token accounts stay out of reach.
+fn check_forwarded_accounts(session: Pubkey, remaining_accounts: &[AccountInfo], allowed: &[Pubkey]) -> Result<()> {
+    for account in remaining_accounts
+        .iter()
+        .filter(|account| account.is_writable && account.owner == &token::ID)
+        .filter(|account| !allowed.contains(&account.key()))
+    {
+        // An SPL token account stores its owner after the 32-byte mint
+        let data = account.try_borrow_data()?;
+        let owned_by_session = data.get(32..64).is_some_and(|owner| owner == session.as_ref());
+        require!(!owned_by_session, ErrorCode::InvalidSwapAccounts);
+    }
+
+    Ok(())
+}
+
+/// Move tokens out of a payout ledger vault, signing as the ledger PDA
+fn transfer_from_ledger<'info>(
+    ledger: &AccountLoader<'info, PayoutLedger>,
+    vault: &Account<'info, TokenAccount>,
+    destination: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    let (provider, mint, bump) = {
+        let ledger = ledger.load()?;
+        (ledger.provider, ledger.mint, ledger.bump)
+    };
+    let seeds = &[PAYOUT_SEED, provider.as_ref(), mint.as_ref(), &[bump]];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: vault.to_account_info(),
+        to: destination.clone(),
+        authority: ledger.to_account_info(),
+    };
+
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+    token::transfer(cpi_ctx, amount)
+}
+
+/// Most extra vault shards a session can have
+pub const MAX_VAULT_SHARDS: usize = 8;
+
+
+/// Compute each batch entry is budgeted; a resumable batch stops before an
+/// entry once less than this remains
+const BATCH_ENTRY_COMPUTE_UNITS: u64 = 30_000;
+
+/// Hash a batch's entries commit to in `BatchState::entries_hash`
+pub fn batch_entries_hash(entries: &[BatchPurchase]) -> [u8; 32] {
+    let mut data = Vec::new();
+    for entry in entries {
+        data.extend_from_slice(entry.provider_token_account.as_ref());
+        data.extend_from_slice(&entry.amount.to_le_bytes());
+        data.extend_from_slice(&(entry.service_id.len() as u32).to_le_bytes());
+        data.extend_from_slice(entry.service_id.as_bytes());
+    }
+    hash(&data).to_bytes()
+}
+
+/// Whether `signer` may act on the session in `role`. The session authority
+/// holds every role; other keys need a matching role assignment.
+fn has_role(
+    session_wallet: &AccountLoader<SessionWallet>,
+    signer: Pubkey,
+    role_assignment: Option<&Account<RoleAssignment>>,
+    role: Role,
+) -> Result<bool> {
+    if session_wallet.load()?.authority == signer {
+        return Ok(true);
+    }
+
+    Ok(role_assignment.is_some_and(|assignment| {
+        assignment.session == session_wallet.key()
+            && assignment.member == signer
+            && assignment.role.permits(role)
+    }))
+}
+
+/// Remaining accounts each batch entry or workflow leg passes, in order: the
+/// provider token account, the provider's registry PDA, the provider's
+/// listing PDA for the leg's service id, then the provider's fee tier PDA
+pub const LEG_ACCOUNT_COUNT: usize = 4;
+
+/// A batch entry's or workflow leg's remaining accounts, checked against it
+struct LegAccounts<'info> {
+    provider_token_account: Account<'info, TokenAccount>,
+    provider_account: Option<ProviderAccount>,
+    service_listing: Option<ServiceListing>,
+    provider_fee_tier: Option<FeeTier>,
+}
+
+/// Load the `LEG_ACCOUNT_COUNT` remaining accounts of `leg`, failing with
+/// `error` when they are not the leg's provider token account and the PDAs
+/// derived from it
+fn load_leg_accounts<'info>(
+    accounts: &'info [AccountInfo<'info>],
+    leg: &BatchPurchase,
+    error: ErrorCode,
+) -> Result<LegAccounts<'info>> {
+    let [provider_token_account, provider_account, service_listing, provider_fee_tier] = accounts else {
+        return Err(error.into());
+    };
+
+    let provider_token_account = Account::<TokenAccount>::try_from(provider_token_account)?;
+    require_keys_eq!(provider_token_account.key(), leg.provider_token_account, error);
+
+    let provider = provider_token_account.owner;
+    let (provider_pda, _) = Pubkey::find_program_address(&[PROVIDER_SEED, provider.as_ref()], &crate::ID);
+    require_keys_eq!(provider_account.key(), provider_pda, error);
+    let (listing_pda, _) = Pubkey::find_program_address(
+        &[SERVICE_SEED, provider.as_ref(), &service_listing_seed(&leg.service_id)],
+        &crate::ID,
+    );
+    require_keys_eq!(service_listing.key(), listing_pda, error);
+    let (fee_tier_pda, _) = Pubkey::find_program_address(&[FEE_TIER_SEED, provider.as_ref()], &crate::ID);
+    require_keys_eq!(provider_fee_tier.key(), fee_tier_pda, error);
+
+    Ok(LegAccounts {
+        provider_account: load_if_created(provider_account)?,
+        service_listing: load_if_created(service_listing)?,
+        provider_fee_tier: load_if_created(provider_fee_tier)?,
+        provider_token_account,
+    })
+}
+
+/// Deserialize a registry PDA the instruction derives, or `None` when it was
+/// never created. Deriving the address means a caller cannot leave out an
+/// entry that exists.
+fn load_if_created<T: AccountDeserialize>(account: &AccountInfo) -> Result<Option<T>> {
+    if account.owner != &crate::ID || account.data_is_empty() {
+        return Ok(None);
+    }
+    T::try_deserialize(&mut &account.data.borrow()[..]).map(Some)
+}
+
+/// Refuse an agent that owes credit. `agent_account` is the agent's PDA,
+/// which only has data once the agent registered.
+fn require_agent_clear(agent_account: &AccountInfo) -> Result<()> {
+    if let Some(agent_account) = load_if_created::<AgentAccount>(agent_account)? {
+        require!(agent_account.outstanding_debt == 0, ErrorCode::DebtOutstanding);
+    }
+    Ok(())
+}
+
+/// The signer's Operator spend after a purchase of `amount`, or `None` when
+/// the signer is the session authority or an Owner and is not limited
+fn check_operator_limits(
+    session_wallet: &SessionWallet,
+    signer: Pubkey,
+    role_assignment: Option<&RoleAssignment>,
+    amount: u64,
+) -> Result<Option<u64>> {
+    if session_wallet.authority == signer {
+        return Ok(None);
+    }
+
+    let assignment = role_assignment.ok_or(ErrorCode::Unauthorized)?;
+    if assignment.role != Role::Operator {
+        return Ok(None);
+    }
+
+    let spent = assignment.spent.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+    require!(
+        assignment.purchase_limit == 0 || amount <= assignment.purchase_limit,
+        ErrorCode::OperatorLimitExceeded
+    );
+    require!(
+        assignment.spend_limit == 0 || spent <= assignment.spend_limit,
+        ErrorCode::OperatorLimitExceeded
+    );
+
+    Ok(Some(spent))
+}
+
+/// Count a purchase against the signer's Operator limits. The session
+/// authority and Owners are not limited.
+fn charge_operator(
+    session_wallet: &SessionWallet,
+    signer: Pubkey,
+    role_assignment: Option<&mut Account<RoleAssignment>>,
+    amount: u64,
+) -> Result<()> {
+    let limits = role_assignment.as_deref().map(|assignment| &**assignment);
+    let spent = check_operator_limits(session_wallet, signer, limits, amount)?;
+    if let (Some(spent), Some(assignment)) = (spent, role_assignment) {
+        assignment.spent = spent;
+    }
+
+    Ok(())
+}
+
+/// Every check `execute_purchase` makes before moving funds, without writing
+/// anything: the session and policy checks, the circuit breaker window for
+/// session-mint purchases and the signer's Operator limits. `validate_purchase`
+/// runs the same function, so a validated purchase fails the same way.
+fn preflight_purchase(
+    session_wallet: &SessionWallet,
+    policy: Option<&Account<Policy>>,
+    purchase: &PurchaseContext,
+    config: &Config,
+    global_stats: &GlobalStats,
+    signer: Pubkey,
+    role_assignment: Option<&RoleAssignment>,
+) -> Result<()> {
+    check_purchase(session_wallet, policy, purchase)?;
+    if purchase.currency_balance.is_none() {
+        next_window_volume(config, global_stats, purchase.amount)?;
+    }
+    check_operator_limits(session_wallet, signer, role_assignment, purchase.amount)?;
+    Ok(())
+}
+
+/// Reporting code for a service id: the first table entry whose prefix matches
+fn lookup_category_code(table: Option<&CategoryCodeTable>, service_id: &str) -> Option<u32> {
+    table?
+        .codes
+        .iter()
+        .find(|entry| service_id.starts_with(entry.prefix.as_str()))
+        .map(|entry| entry.code)
+}
+
+fn validate_category_codes(codes: &[CategoryCode]) -> Result<()> {
+    require!(
+        codes.len() <= CategoryCodeTable::MAX_CATEGORY_CODES,
+        ErrorCode::InvalidCategoryCodes
+    );
+    require!(
+        codes.iter().all(|entry| entry.prefix.len() <= PolicyRule::MAX_CATEGORY_LEN),
+        ErrorCode::InvalidCategoryCodes
+    );
+    Ok(())
+}
+
+/// Whether `signer` is the session's registered automation thread for `task`
+fn runs_automation(
+    session_wallet: &AccountLoader<SessionWallet>,
+    signer: Pubkey,
+    task: AutomationTask,
+) -> Result<bool> {
+    let session_wallet = session_wallet.load()?;
+    Ok(session_wallet.automation_thread() == Some(signer)
+        && session_wallet.automation_task() == Some(task))
+}
+
+/// Session id for an agent's `index`th auto-initialized session: the hex of
+/// the first 16 bytes of a hash over the agent key and index
+pub fn derived_session_id(agent: &Pubkey, index: u64) -> String {
+    hashv(&[b"session", agent.as_ref(), &index.to_le_bytes()]).to_bytes()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// When the purchase's service stops accepting payments, if the listing
+/// passed belongs to its provider and service id and has a deprecation set
+fn service_deprecated_after(purchase: &PurchaseContext) -> Option<i64> {
+    purchase
+        .service_listing
+        .filter(|listing| {
+            listing.provider == purchase.provider && listing.service_id == purchase.service_id
+        })
+        .and_then(ServiceListing::deprecated_after)
+}
+
+/// Seed a service listing PDA is derived from; service ids may exceed the
+/// 32-byte seed limit
+pub fn service_listing_seed(service_id: &str) -> [u8; 32] {
+    hash(service_id.as_bytes()).to_bytes()
+}
+
+/// Hash a capability commits to for a service id prefix
+pub fn capability_scope_hash(scope: &str) -> [u8; 32] {
+    hashv(&[b"capability_scope", scope.as_bytes()]).to_bytes()
+}
+
+/// Fee for a purchase in basis points: the lowest of the default and any
+/// tier held by the session's agent or the provider
+fn effective_fee_bps(
+    fee_config: &FeeConfig,
+    agent_fee_tier: Option<&FeeTier>,
+    provider_fee_tier: Option<&FeeTier>,
+    agent: Option<Pubkey>,
+    provider: Pubkey,
+) -> Result<u16> {
+    let mut fee_bps = fee_config.fee_bps;
+    if let Some(tier) = agent_fee_tier {
+        require!(agent == Some(tier.subject), ErrorCode::InvalidFeeAccounts);
+        fee_bps = fee_bps.min(tier.fee_bps);
+    }
+    if let Some(tier) = provider_fee_tier {
+        require_keys_eq!(tier.subject, provider, ErrorCode::InvalidFeeAccounts);
+        fee_bps = fee_bps.min(tier.fee_bps);
+    }
+    Ok(fee_bps)
+}
+
+/// Platform fee on `purchase` and the basis points it was charged at
+fn purchase_fee(
+    session_wallet: &SessionWallet,
+    fee_config: &FeeConfig,
+    agent_fee_tier: Option<&FeeTier>,
+    provider_fee_tier: Option<&FeeTier>,
+    purchase: &PurchaseContext,
+) -> Result<(u64, u16)> {
+    let fee_bps = effective_fee_bps(
+        fee_config,
+        agent_fee_tier,
+        provider_fee_tier,
+        session_wallet.agent_pubkey(),
+        purchase.provider,
+    )?;
+    let fee = (purchase.amount as u128 * fee_bps as u128 / BPS_DENOMINATOR as u128) as u64;
+    Ok((fee, fee_bps))
+}
+
+/// Count a purchase's platform fee in the treasury's total
+fn record_fee(
+    session_id: &str,
+    treasury: &mut Treasury,
+    service_id: &str,
+    provider: Pubkey,
+    fee: u64,
+    fee_bps: u16,
+) -> Result<()> {
+    treasury.total_fees = treasury
+        .total_fees
+        .checked_add(fee)
+        .ok_or(ErrorCode::Overflow)?;
+
+    if fee > 0 {
+        emit!(PurchaseFeeCollected {
+            session_id: session_id.to_string(),
+            service_id: service_id.to_string(),
+            provider,
+            fee,
+            fee_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+    }
+
+    Ok(())
+}
+
+/// Platform fee on `purchase`, counted in the treasury's total. The caller
+/// cuts it from the provider's share and sends it to the treasury vault.
+fn collect_fee(
+    session_wallet: &SessionWallet,
+    fee_config: &FeeConfig,
+    agent_fee_tier: Option<&FeeTier>,
+    provider_fee_tier: Option<&FeeTier>,
+    treasury: &mut Treasury,
+    purchase: &PurchaseContext,
+) -> Result<u64> {
+    let (fee, fee_bps) = purchase_fee(session_wallet, fee_config, agent_fee_tier, provider_fee_tier, purchase)?;
+    record_fee(session_wallet.session_id(), treasury, purchase.service_id, purchase.provider, fee, fee_bps)?;
+    Ok(fee)
+}
+
+/// Emit the closing `SessionReport` with per-provider and per-category totals
+fn emit_session_report(session_wallet: &SessionWallet, refunded_amount: u64) -> Result<()> {
+    let categories: Vec<CategorySpend> = session_wallet
+        .category_budgets()
+        .iter()
+        .map(|budget| CategorySpend {
+            prefix: budget.prefix().to_string(),
+            amount: budget.spent,
+        })
+        .collect();
+    let categorized: u64 = categories.iter().map(|category| category.amount).sum();
+
+    emit!(SessionReport {
+        session_id: session_wallet.session_id().to_string(),
+        total_funded: session_wallet.total_funded,
+        total_rebated: session_wallet.total_rebated,
+        total_spent: session_wallet.total_spent,
+        refunded_amount,
+        purchase_count: session_wallet.purchase_count,
+        providers: session_wallet.provider_spend().iter().map(ProviderSpend::from).collect(),
+        untracked_provider_spend: session_wallet.untracked_provider_spend,
+        categories,
+        uncategorized_spend: session_wallet.total_spent.saturating_sub(categorized),
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/// Domain separator prefixed to every bond challenge attestation
+pub const BOND_CHALLENGE_DOMAIN: &[u8] = b"session-wallet:bond-challenge:v1";
+
+/// Canonical bytes an arbiter signs to uphold a challenge: domain || claim
+pub fn bond_challenge_message(claim: &Pubkey) -> Vec<u8> {
+    let mut message = Vec::with_capacity(BOND_CHALLENGE_DOMAIN.len() + 32);
+    message.extend_from_slice(BOND_CHALLENGE_DOMAIN);
+    message.extend_from_slice(claim.as_ref());
+    message
+}
+
+/// Move a claim's whole bond to `destination` and close the bond vault,
+/// signing as the claim PDA
+fn drain_bond_vault<'info>(
+    claim: &Account<'info, BondClaim>,
+    bond_vault: &Account<'info, TokenAccount>,
+    destination: &AccountInfo<'info>,
+    rent_destination: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+) -> Result<()> {
+    let claim_id = claim.claim_id.to_le_bytes();
+    let seeds = &[
+        BOND_CLAIM_SEED,
+        claim.session.as_ref(),
+        &claim_id,
+        &[claim.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: bond_vault.to_account_info(),
+        to: destination.clone(),
+        authority: claim.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token::transfer(cpi_ctx, bond_vault.amount)?;
+
+    let cpi_accounts = CloseAccount {
+        account: bond_vault.to_account_info(),
+        destination: rent_destination.clone(),
+        authority: claim.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token::close_account(cpi_ctx)
+}
+
+/// Seconds between proposing a higher funding cap and applying it
+pub const FUNDING_CAP_RAISE_DELAY: i64 = 86_400;
+
+/// Seconds a payout ledger may go without a claim before its accrued
+/// earnings can be escheated to the treasury (180 days)
+pub const PAYOUT_ESCHEAT_PERIOD: i64 = 180 * 86_400;
+
+/// Building blocks for programs that call session wallets through CPI, on top
+/// of the Anchor-generated `cpi` module: PDA derivation, instruction builders
+/// and invoke wrappers that pass every optional account as omitted.
+/// Purchases are refused when invoked through CPI unless the session owner
+/// has called `set_allow_cpi`.
+#[cfg(feature = "cpi")]
+pub mod interface {
+    use super::*;
+    use anchor_lang::InstructionData;
+    use anchor_spl::associated_token::get_associated_token_address;
+
+    /// Session PDA and bump for `session_id`
+    pub fn session_address(session_id: &str) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SESSION_SEED, session_id.as_bytes()], &crate::ID)
+    }
+
+    /// Associated token account the session holds `mint` in
+    pub fn session_token_account(session_id: &str, mint: &Pubkey) -> Pubkey {
+        get_associated_token_address(&session_address(session_id).0, mint)
+    }
+
+    /// `execute_purchase` paying `service_provider_token_account`, owned by
+    /// `provider`, from the session's associated token account, signed by the
+    /// session authority, with no optional accounts
+    pub fn execute_purchase_instruction(
+        session_id: &str,
+        mint: &Pubkey,
+        provider: &Pubkey,
+        service_provider_token_account: Pubkey,
+        authority: Pubkey,
+        amount: u64,
+        service_id: String,
+    ) -> Instruction {
+        let accounts = crate::accounts::ExecutePurchase {
+            session_wallet: session_address(session_id).0,
+            session_token_account: session_token_account(session_id, mint),
+            service_provider_token_account,
+            authority,
+            token_program: token::ID,
+            instructions: instructions_sysvar::ID,
+            config: Pubkey::find_program_address(&[CONFIG_SEED], &crate::ID).0,
+            global_stats: Pubkey::find_program_address(&[GLOBAL_STATS_SEED, mint.as_ref()], &crate::ID).0,
+            policy: None,
+            role_assignment: None,
+            currency_balance: None,
+            category_codes: None,
+            service_listing: Pubkey::find_program_address(
+                &[SERVICE_SEED, provider.as_ref(), &service_listing_seed(&service_id)],
+                &crate::ID,
+            )
+            .0,
+            fee_config: Pubkey::find_program_address(&[FEE_CONFIG_SEED], &crate::ID).0,
+            treasury: Pubkey::find_program_address(&[TREASURY_SEED, mint.as_ref()], &crate::ID).0,
+            treasury_vault: Pubkey::find_program_address(&[TREASURY_VAULT_SEED, mint.as_ref()], &crate::ID).0,
+            agent_fee_tier: None,
+            provider_fee_tier: None,
+            provider_account: Pubkey::find_program_address(&[PROVIDER_SEED, provider.as_ref()], &crate::ID).0,
+        };
+
+        Instruction {
+            program_id: crate::ID,
+            accounts: accounts.to_account_metas(None),
+            data: crate::instruction::ExecutePurchase { amount, service_id }.data(),
+        }
+    }
+
+    /// `fund_session` moving `amount` from `funder_token_account` into the
+    /// session's associated token account, with no optional accounts
+    pub fn fund_session_instruction(
+        session_id: &str,
+        mint: &Pubkey,
+        funder: Pubkey,
+        funder_token_account: Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        let accounts = crate::accounts::FundSession {
+            session_wallet: session_address(session_id).0,
+            funder_token_account,
+            session_token_account: session_token_account(session_id, mint),
+            funder,
+            token_program: token::ID,
+            agent_account: None,
+            debt: None,
+            treasury: None,
+            treasury_vault: None,
+        };
+
+        Instruction {
+            program_id: crate::ID,
+            accounts: accounts.to_account_metas(None),
+            data: crate::instruction::FundSession { amount }.data(),
+        }
+    }
+
+    /// Accounts `execute_purchase` requires
+    pub struct ExecutePurchaseAccounts<'info> {
+        pub session_wallet: AccountInfo<'info>,
+        pub session_token_account: AccountInfo<'info>,
+        pub service_provider_token_account: AccountInfo<'info>,
+        pub authority: AccountInfo<'info>,
+        pub token_program: AccountInfo<'info>,
+        pub instructions: AccountInfo<'info>,
+        pub config: AccountInfo<'info>,
+        pub global_stats: AccountInfo<'info>,
+        pub service_listing: AccountInfo<'info>,
+        pub fee_config: AccountInfo<'info>,
+        pub treasury: AccountInfo<'info>,
+        pub treasury_vault: AccountInfo<'info>,
+        pub provider_account: AccountInfo<'info>,
+    }
+
+    /// Invoke `execute_purchase`. The session PDA signs inside the session
+    /// wallet program, so the caller passes no seeds for it. `authority_seeds`
+    /// signs for an authority that is a PDA of the calling program; pass `&[]`
+    /// when the authority signed the transaction. An authority other than the
+    /// session's own needs an Operator assignment, which this wrapper omits.
+    pub fn execute_purchase<'info>(
+        session_wallet_program: AccountInfo<'info>,
+        accounts: ExecutePurchaseAccounts<'info>,
+        amount: u64,
+        service_id: String,
+        authority_seeds: &[&[&[u8]]],
+    ) -> Result<()> {
+        let cpi_accounts = crate::cpi::accounts::ExecutePurchase {
+            session_wallet: accounts.session_wallet,
+            session_token_account: accounts.session_token_account,
+            service_provider_token_account: accounts.service_provider_token_account,
+            authority: accounts.authority,
+            token_program: accounts.token_program,
+            instructions: accounts.instructions,
+            config: accounts.config,
+            global_stats: accounts.global_stats,
+            policy: None,
+            role_assignment: None,
+            currency_balance: None,
+            category_codes: None,
+            service_listing: accounts.service_listing,
+            fee_config: accounts.fee_config,
+            treasury: accounts.treasury,
+            treasury_vault: accounts.treasury_vault,
+            agent_fee_tier: None,
+            provider_fee_tier: None,
+            provider_account: accounts.provider_account,
+        };
+
+        crate::cpi::execute_purchase(
+            CpiContext::new_with_signer(session_wallet_program, cpi_accounts, authority_seeds),
+            amount,
+            service_id,
+        )
+    }
+
+    /// Accounts `fund_session` requires
+    pub struct FundSessionAccounts<'info> {
+        pub session_wallet: AccountInfo<'info>,
+        pub funder_token_account: AccountInfo<'info>,
+        pub session_token_account: AccountInfo<'info>,
+        pub funder: AccountInfo<'info>,
+        pub token_program: AccountInfo<'info>,
+    }
+
+    /// Invoke `fund_session`. `funder_seeds` signs for a funder that is a PDA
+    /// of the calling program; pass `&[]` when the funder signed the transaction.
+    pub fn fund_session<'info>(
+        session_wallet_program: AccountInfo<'info>,
+        accounts: FundSessionAccounts<'info>,
+        amount: u64,
+        funder_seeds: &[&[&[u8]]],
+    ) -> Result<()> {
+        let cpi_accounts = crate::cpi::accounts::FundSession {
+            session_wallet: accounts.session_wallet,
+            funder_token_account: accounts.funder_token_account,
+            session_token_account: accounts.session_token_account,
+            funder: accounts.funder,
+            token_program: accounts.token_program,
+            agent_account: None,
+            debt: None,
+            treasury: None,
+            treasury_vault: None,
+        };
+
+        crate::cpi::fund_session(
+            CpiContext::new_with_signer(session_wallet_program, cpi_accounts, funder_seeds),
+            amount,
+        )
+    }
+}
+
+/// Off-chain helpers for building transactions and reading accounts, behind
+/// the `client` feature. Every instruction is built from its Anchor-generated
+/// `accounts::*` and `instruction::*` types with `instruction`, e.g.
+/// `instruction(accounts::FundSession { .. }, instruction::FundSession { amount })`.
+#[cfg(feature = "client")]
+pub mod client {
+    use super::*;
+    use anchor_lang::{InstructionData, ZeroCopy};
+
+    /// Build an instruction for this program from its accounts and arguments
+    pub fn instruction(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: accounts.to_account_metas(None),
+            data: data.data(),
+        }
+    }
+
+    /// Global config
+    pub fn config_pda() -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[CONFIG_SEED], &crate::ID)
+    }
+
+    /// Treasury for a mint
+    pub fn treasury_pda(mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[TREASURY_SEED, mint.as_ref()], &crate::ID)
+    }
+
+    /// Treasury vault for a mint
+    pub fn treasury_vault_pda(mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[TREASURY_VAULT_SEED, mint.as_ref()], &crate::ID)
+    }
+
+    /// Session wallet for a session id
+    pub fn session_pda(session_id: &str) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SESSION_SEED, session_id.as_bytes()], &crate::ID)
+    }
+
+    /// Receipt of the session's purchase at `purchase_index`
+    pub fn receipt_pda(session: &Pubkey, purchase_index: u64) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[RECEIPT_SEED, session.as_ref(), &purchase_index.to_le_bytes()], &crate::ID)
+    }
+
+    /// Mint of a receipt's token
+    pub fn receipt_mint_pda(receipt: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[RECEIPT_MINT_SEED, receipt.as_ref()], &crate::ID)
+    }
+
+    /// Metadata of a receipt token mint
+    pub fn receipt_metadata_pda(mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[RECEIPT_METADATA_SEED, mint.as_ref()], &crate::ID)
+    }
+
+    /// Redemption record of an HTTP 402 voucher
+    pub fn voucher_pda(session: &Pubkey, pay_to: &Pubkey, voucher: &Http402Voucher) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[VOUCHER_SEED, session.as_ref(), hash(&http402_voucher_message(session, pay_to, voucher)).as_ref()], &crate::ID)
+    }
+
+    /// Session snapshot at `snapshot_index`
+    pub fn snapshot_pda(session: &Pubkey, snapshot_index: u64) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SNAPSHOT_SEED, session.as_ref(), &snapshot_index.to_le_bytes()], &crate::ID)
+    }
+
+    /// Spending policy
+    pub fn policy_pda(authority: &Pubkey, policy_id: &str) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[POLICY_SEED, authority.as_ref(), policy_id.as_bytes()], &crate::ID)
+    }
+
+    /// Agent account for an agent key
+    pub fn agent_pda(agent: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[AGENT_SEED, agent.as_ref()], &crate::ID)
+    }
+
+    /// Credit line of an agent account in a mint
+    pub fn debt_pda(agent_account: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[DEBT_SEED, agent_account.as_ref(), mint.as_ref()], &crate::ID)
+    }
+
+    /// Netting channel between a session and a provider
+    pub fn channel_pda(session: &Pubkey, provider: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[CHANNEL_SEED, session.as_ref(), provider.as_ref()], &crate::ID)
+    }
+
+    /// Session template
+    pub fn template_pda(authority: &Pubkey, template_id: &str) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[TEMPLATE_SEED, authority.as_ref(), template_id.as_bytes()], &crate::ID)
+    }
+
+    /// Secondary mint balance of a session
+    pub fn currency_balance_pda(session: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[BALANCE_SEED, session.as_ref(), mint.as_ref()], &crate::ID)
+    }
+
+    /// Vault of a secondary mint balance
+    pub fn currency_vault_pda(session: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[BALANCE_VAULT_SEED, session.as_ref(), mint.as_ref()], &crate::ID)
+    }
+
+    /// Swap configuration
+    pub fn swap_config_pda() -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SWAP_CONFIG_SEED], &crate::ID)
+    }
+
+    /// Conditional escrow
+    pub fn escrow_pda(session: &Pubkey, escrow_id: u64) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[ESCROW_SEED, session.as_ref(), &escrow_id.to_le_bytes()], &crate::ID)
+    }
+
+    /// Payout ledger of a provider in a mint
+    pub fn payout_ledger_pda(provider: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[PAYOUT_SEED, provider.as_ref(), mint.as_ref()], &crate::ID)
+    }
+
+    /// Vault of a payout ledger
+    pub fn payout_vault_pda(ledger: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[PAYOUT_VAULT_SEED, ledger.as_ref()], &crate::ID)
+    }
+
+    /// Vault shard of a session
+    pub fn shard_pda(session: &Pubkey, index: u8) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SHARD_SEED, session.as_ref(), &[index]], &crate::ID)
+    }
+
+    /// Resumable purchase batch
+    pub fn batch_pda(session: &Pubkey, batch_id: u64) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[BATCH_SEED, session.as_ref(), &batch_id.to_le_bytes()], &crate::ID)
+    }
+
+    /// Role assignment of a member on a session
+    pub fn role_pda(session: &Pubkey, member: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[ROLE_SEED, session.as_ref(), member.as_ref()], &crate::ID)
+    }
+
+    /// Category code table
+    pub fn category_codes_pda() -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[CATEGORY_CODES_SEED], &crate::ID)
+    }
+
+    /// Automation configuration
+    pub fn automation_config_pda() -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[AUTOMATION_CONFIG_SEED], &crate::ID)
+    }
+
+    /// Bridge configuration
+    pub fn bridge_config_pda() -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[BRIDGE_CONFIG_SEED], &crate::ID)
+    }
+
+    /// Exported state of a session
+    pub fn checkpoint_pda(session: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[CHECKPOINT_SEED, session.as_ref()], &crate::ID)
+    }
+
+    /// Provider account
+    pub fn provider_pda(provider: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[PROVIDER_SEED, provider.as_ref()], &crate::ID)
+    }
+
+    /// Capability issued to an ephemeral key
+    pub fn capability_pda(session: &Pubkey, key: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[CAPABILITY_SEED, session.as_ref(), key.as_ref()], &crate::ID)
+    }
+
+    /// Compressed session tree of a Merkle tree
+    pub fn session_tree_pda(merkle_tree: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SESSION_TREE_SEED, merkle_tree.as_ref()], &crate::ID)
+    }
+
+    /// Pooled vault of a compressed session tree
+    pub fn session_tree_vault_pda(merkle_tree: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SESSION_TREE_VAULT_SEED, merkle_tree.as_ref()], &crate::ID)
+    }
+
+    /// Fee configuration
+    pub fn fee_config_pda() -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[FEE_CONFIG_SEED], &crate::ID)
+    }
+
+    /// Fee tier of an agent or provider key
+    pub fn fee_tier_pda(subject: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[FEE_TIER_SEED, subject.as_ref()], &crate::ID)
+    }
+
+    /// Bonded purchase claim
+    pub fn bond_claim_pda(session: &Pubkey, claim_id: u64) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[BOND_CLAIM_SEED, session.as_ref(), &claim_id.to_le_bytes()], &crate::ID)
+    }
+
+    /// Vault holding a claim's bond
+    pub fn bond_vault_pda(claim: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[BOND_VAULT_SEED, claim.as_ref()], &crate::ID)
+    }
+
+    /// Rating of a receipted purchase
+    pub fn rating_pda(receipt: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[RATING_SEED, receipt.as_ref()], &crate::ID)
+    }
+
+    /// Fee sweep totals for a mint
+    pub fn global_stats_pda(mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[GLOBAL_STATS_SEED, mint.as_ref()], &crate::ID)
+    }
+
+    /// Provider's listing of a service
+    pub fn service_listing_pda(provider: &Pubkey, service_id: &str) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SERVICE_SEED, provider.as_ref(), &service_listing_seed(service_id)], &crate::ID)
+    }
+
+    /// Redemption record of a funder's permit `nonce` for a session
+    pub fn permit_pda(session: &Pubkey, funder: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[PERMIT_SEED, session.as_ref(), funder.as_ref(), &nonce.to_le_bytes()],
+            &crate::ID,
+        )
+    }
+
+    /// Composite receipt of a session's workflow purchase
+    pub fn workflow_receipt_pda(session: &Pubkey, workflow_id: &str) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[WORKFLOW_SEED, session.as_ref(), workflow_id.as_bytes()], &crate::ID)
+    }
+
+    /// Session opened by `initialize_session_auto` for an agent's `index`th session
+    pub fn derived_session_pda(agent: &Pubkey, index: u64) -> (Pubkey, u8) {
+        session_pda(&derived_session_id(agent, index))
+    }
+
+    /// Durable nonce account derived from a session, see `SESSION_NONCE_SEED`
+    pub fn nonce_account_address(session: &Pubkey) -> Pubkey {
+        Pubkey::create_with_seed(session, SESSION_NONCE_SEED, &System::id())
+            .expect("nonce seed is within the length limit")
+    }
+
+    /// Decode an account stored with Borsh, checking its discriminator
+    pub fn decode<T: AccountDeserialize>(data: &[u8]) -> Result<T> {
+        T::try_deserialize(&mut &data[..])
+    }
+
+    /// Copy out a zero-copy account (`SessionWallet`, `CurrencyBalance`,
+    /// `PayoutLedger`), checking its discriminator and length
+    pub fn decode_zero_copy<T: ZeroCopy>(data: &[u8]) -> Result<T> {
+        let body = data
+            .strip_prefix(&T::discriminator())
+            .ok_or(anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch)?;
+        body.get(..std::mem::size_of::<T>())
+            .and_then(|body| bytemuck::try_pod_read_unaligned(body).ok())
+            .ok_or_else(|| error!(anchor_lang::error::ErrorCode::AccountDidNotDeserialize))
+    }
+}
+
+/// The provider's trial discount on `amount`, when this is the session's first
+/// purchase from it. A session whose provider table is full cannot show that
+/// and pays full price.
+fn trial_discount(
+    provider_account: Option<&ProviderAccount>,
+    session_wallet: &SessionWallet,
+    amount: u64,
+) -> u64 {
+    match provider_account {
+        Some(provider_account)
+            if provider_account.trial_discount_bps > 0
+                && session_wallet.provider_purchase_count(provider_account.provider) == Some(0) =>
+        {
+            (amount as u128 * provider_account.trial_discount_bps as u128 / BPS_DENOMINATOR as u128)
+                as u64
+        }
+        _ => 0,
+    }
+}
+
+/// Amount to charge for a purchase listed at `list_amount`, less any trial
+/// discount, emitting TrialRedeemed when one applies
+fn apply_trial_discount(
+    provider_account: Option<&ProviderAccount>,
+    session_wallet: &SessionWallet,
+    service_id: &str,
+    list_amount: u64,
+) -> Result<u64> {
+    let discount = trial_discount(provider_account, session_wallet, list_amount);
+    let Some(provider_account) = provider_account.filter(|_| discount > 0) else {
+        return Ok(list_amount);
+    };
+
+    let amount = list_amount - discount;
+    emit!(TrialRedeemed {
+        session_id: session_wallet.session_id().to_string(),
+        service_id: service_id.to_string(),
+        provider: provider_account.provider,
+        list_amount,
+        discount,
+        amount,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(amount)
+}
+
+/// Circuit breaker window after a purchase of `amount`, as (start slot,
+/// volume), or `CircuitBreakerTripped` when it would pass the configured cap
+fn next_window_volume(config: &Config, global_stats: &GlobalStats, amount: u64) -> Result<(u64, u64)> {
+    if config.max_window_volume == 0 {
+        return Ok((global_stats.window_start_slot, global_stats.window_volume));
+    }
+
+    let slot = Clock::get()?.slot;
+    let (window_start_slot, window_volume) =
+        if slot >= global_stats.window_start_slot.saturating_add(config.volume_window_slots) {
+            (slot, 0)
+        } else {
+            (global_stats.window_start_slot, global_stats.window_volume)
+        };
+
+    let window_volume = window_volume.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+    require!(
+        window_volume <= config.max_window_volume,
+        ErrorCode::CircuitBreakerTripped
+    );
+    Ok((window_start_slot, window_volume))
+}
+
+/// Count a purchase against its mint's circuit breaker window
+fn track_purchase_volume(config: &Config, global_stats: &mut GlobalStats, amount: u64) -> Result<()> {
+    let (window_start_slot, window_volume) = next_window_volume(config, global_stats, amount)?;
+    global_stats.window_start_slot = window_start_slot;
+    global_stats.window_volume = window_volume;
+    Ok(())
+}
+
+/// View raw account data as a SessionWallet, checking its discriminator and
+/// length. For handlers that cannot hold an AccountLoader on the session.
+fn session_wallet_from_bytes(data: &[u8]) -> Result<&SessionWallet> {
+    let body = data
+        .strip_prefix(&<SessionWallet as anchor_lang::Discriminator>::discriminator())
+        .ok_or(anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch)?;
+    body.get(..SessionWallet::SIZE)
+        .and_then(|body| bytemuck::try_from_bytes(body).ok())
+        .ok_or_else(|| error!(anchor_lang::error::ErrorCode::AccountDidNotDeserialize))
+}
+
+// ============================================================================
+// Accounts
+// ============================================================================
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Config::SIZE,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, program::SessionWallet>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(payer.key()) @ ErrorCode::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeTreasury<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Treasury::SIZE,
+        seeds = [TREASURY_SEED, mint.key().as_ref()],
+        bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(
+        init,
+        payer = admin,
+        seeds = [TREASURY_VAULT_SEED, mint.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = treasury
+    )]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct DepositTreasury<'info> {
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, treasury.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub depositor_token_account: Account<'info, TokenAccount>,
+
+    pub depositor: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, treasury.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub destination_token_account: Account<'info, TokenAccount>,
+
+    pub admin: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(session_id: String)]
+pub struct InitializeSession<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + SessionWallet::SIZE,
+        seeds = [SESSION_SEED, session_id.as_bytes()],
+        bump
+    )]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == authority.key() @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, treasury.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    /// CHECK: Session token account will be created externally
+    #[account(mut)]
+    pub session_token_account: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+
+    /// CHECK: Durable nonce address derived from the session PDA, verified in handler
+    #[account(mut)]
+    pub session_nonce_account: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Recent blockhashes sysvar, required to initialize the nonce
+    #[account(address = recent_blockhashes::ID)]
+    pub recent_blockhashes: Option<UncheckedAccount<'info>>,
+
+    #[account(seeds = [AGENT_SEED, agent_account.agent.as_ref()], bump = agent_account.bump)]
+    pub agent_account: Option<Account<'info, AgentAccount>>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, service_id: String)]
+pub struct ExecutePurchase<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    /// The session's vault for the mint the purchase pays in
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = match &currency_balance {
+            Some(currency_balance) => session_token_account.key() == currency_balance.load()?.vault,
+            None => session_token_account.mint == session_wallet.load()?.mint,
+        } @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub service_provider_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(
+        mut,
+        constraint = currency_balance.load()?.session == session_wallet.key() @ ErrorCode::InvalidCurrencyBalance
+    )]
+    pub currency_balance: Option<AccountLoader<'info, CurrencyBalance>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    /// Treasury of the mint the purchase pays in, the session mint or a
+    /// secondary currency
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_token_account.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, service_provider_token_account.owner.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, service_provider_token_account.owner.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, service_id: String)]
+pub struct ExecutePurchaseWithReceipt<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub service_provider_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PurchaseReceipt::SIZE,
+        seeds = [
+            RECEIPT_SEED,
+            session_wallet.key().as_ref(),
+            session_wallet.load()?.purchase_count.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub receipt: Box<Account<'info, PurchaseReceipt>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [RECEIPT_MINT_SEED, receipt.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = session_wallet
+    )]
+    pub receipt_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = authority,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = authority
+    )]
+    pub receipt_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ReceiptMetadata::SIZE,
+        seeds = [RECEIPT_METADATA_SEED, receipt_mint.key().as_ref()],
+        bump
+    )]
+    pub receipt_metadata: Box<Account<'info, ReceiptMetadata>>,
+
+    #[account(
+        mut,
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, service_provider_token_account.owner.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, service_provider_token_account.owner.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(agent_pubkey: Pubkey)]
+pub struct SetAgentKey<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    /// CHECK: The new key's AgentAccount PDA; checked for debt when it exists
+    #[account(seeds = [AGENT_SEED, agent_pubkey.as_ref()], bump)]
+    pub agent_account: UncheckedAccount<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, service_id: String)]
+pub struct ExecutePurchaseWithIntent<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub service_provider_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, service_provider_token_account.owner.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, service_provider_token_account.owner.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+
+    /// The agent key's role assignment, charged with its Operator limits
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+#[instruction(voucher: Http402Voucher)]
+pub struct RedeemHttp402Voucher<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub service_provider_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = redeemer,
+        space = 8 + RedeemedVoucher::SIZE,
+        seeds = [
+            VOUCHER_SEED,
+            session_wallet.key().as_ref(),
+            hash(&http402_voucher_message(
+                &session_wallet.key(),
+                &service_provider_token_account.key(),
+                &voucher
+            ))
+            .as_ref()
+        ],
+        bump
+    )]
+    pub redeemed_voucher: Account<'info, RedeemedVoucher>,
+
+    #[account(mut)]
+    pub redeemer: Signer<'info>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, service_provider_token_account.owner.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+
+    /// The agent key's role assignment, charged with its Operator limits
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct FundSession<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(mut)]
+    pub funder_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    pub funder: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    #[account(mut)]
+    pub agent_account: Option<Account<'info, AgentAccount>>,
+
+    #[account(mut)]
+    pub debt: Option<Account<'info, Debt>>,
+
+    #[account(mut)]
+    pub treasury: Option<Account<'info, Treasury>>,
+
+    #[account(mut)]
+    pub treasury_vault: Option<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseSession<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(mut)]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)?
+            || runs_automation(&session_wallet, authority.key(), AutomationTask::CloseAtExpiry)?
+            @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseSessionNonce<'info> {
+    #[account(
+        mut,
+        has_one = authority
+    )]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    /// CHECK: Must be the nonce recorded on the session; the system program checks its state
+    #[account(
+        mut,
+        constraint = session_wallet.load()?.nonce_account() == Some(session_nonce_account.key())
+            @ ErrorCode::InvalidNonceAccount
+    )]
+    pub session_nonce_account: UncheckedAccount<'info>,
+
+    /// CHECK: Recent blockhashes sysvar, required by the nonce withdraw instruction
+    #[account(address = recent_blockhashes::ID)]
+    pub recent_blockhashes: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct SnapshotSession<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + SessionSnapshot::SIZE,
+        seeds = [
+            SNAPSHOT_SEED,
+            session_wallet.key().as_ref(),
+            session_wallet.load()?.snapshot_count.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub snapshot: Account<'info, SessionSnapshot>,
+
+    #[account(
+        mut,
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Auditor)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+#[instruction(policy_id: String)]
+pub struct CreatePolicy<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Policy::SIZE,
+        seeds = [POLICY_SEED, authority.key().as_ref(), policy_id.as_bytes()],
+        bump
+    )]
+    pub policy: Account<'info, Policy>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePolicy<'info> {
+    #[account(
+        mut,
+        seeds = [POLICY_SEED, authority.key().as_ref(), policy.policy_id.as_bytes()],
+        bump = policy.bump,
+        has_one = authority
+    )]
+    pub policy: Account<'info, Policy>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AttachPolicy<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(has_one = authority)]
+    pub policy: Account<'info, Policy>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct DetachPolicy<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterAgent<'info> {
+    #[account(
+        init,
+        payer = agent,
+        space = 8 + AgentAccount::SIZE,
+        seeds = [AGENT_SEED, agent.key().as_ref()],
+        bump
+    )]
+    pub agent_account: Account<'info, AgentAccount>,
+
+    #[account(mut)]
+    pub agent: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetCreditLimit<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [AGENT_SEED, agent_account.agent.as_ref()],
+        bump = agent_account.bump
+    )]
+    pub agent_account: Account<'info, AgentAccount>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct OpenCreditLine<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [AGENT_SEED, agent_account.agent.as_ref()],
+        bump = agent_account.bump
+    )]
+    pub agent_account: Account<'info, AgentAccount>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Debt::SIZE,
+        seeds = [DEBT_SEED, agent_account.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub debt: Account<'info, Debt>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, service_id: String)]
+pub struct ExecutePurchaseOnCredit<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub service_provider_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [AGENT_SEED, agent_account.agent.as_ref()],
+        bump = agent_account.bump,
+        constraint = session_wallet.load()?.agent_pubkey() == Some(agent_account.agent)
+            @ ErrorCode::InvalidCreditAccounts
+    )]
+    pub agent_account: Account<'info, AgentAccount>,
+
+    /// The agent co-signs every purchase that may draw on its credit line
+    #[account(address = agent_account.agent @ ErrorCode::InvalidCreditAccounts)]
+    pub agent: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [DEBT_SEED, agent_account.key().as_ref(), session_wallet.load()?.mint.as_ref()],
+        bump = debt.bump
+    )]
+    pub debt: Account<'info, Debt>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, service_provider_token_account.owner.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, service_provider_token_account.owner.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct OpenNettingChannel<'info> {
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + NettingChannel::SIZE,
+        seeds = [CHANNEL_SEED, session_wallet.key().as_ref(), provider.key().as_ref()],
+        bump
+    )]
+    pub channel: Account<'info, NettingChannel>,
+
+    /// CHECK: Provider wallet; only used as a seed and expected signer
+    pub provider: UncheckedAccount<'info>,
+
+    #[account(
+        token::mint = session_wallet.load()?.mint,
+        token::authority = provider
+    )]
+    pub provider_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, service_id: String)]
+pub struct ChannelPurchase<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        seeds = [CHANNEL_SEED, session_wallet.key().as_ref(), channel.provider.as_ref()],
+        bump = channel.bump
+    )]
+    pub channel: Account<'info, NettingChannel>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, channel.provider.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, channel.provider.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ChannelRefund<'info> {
+    #[account(
+        mut,
+        seeds = [CHANNEL_SEED, channel.session.as_ref(), provider.key().as_ref()],
+        bump = channel.bump,
+        has_one = provider
+    )]
+    pub channel: Account<'info, NettingChannel>,
+
+    pub provider: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SettleChannel<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        seeds = [CHANNEL_SEED, session_wallet.key().as_ref(), channel.provider.as_ref()],
+        bump = channel.bump,
+        has_one = provider_token_account,
+        constraint = settler.key() == session_wallet.load()?.authority || settler.key() == channel.provider
+            @ ErrorCode::Unauthorized
+    )]
+    pub channel: Account<'info, NettingChannel>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub provider_token_account: Account<'info, TokenAccount>,
+
+    pub settler: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitReceiptTree<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    /// CHECK: Allocated by the client; initialized and validated by the compression program
+    #[account(mut, owner = spl_account_compression::ID)]
+    pub merkle_tree: AccountInfo<'info>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_noop::ID)]
+    pub noop_program: AccountInfo<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_account_compression::ID)]
+    pub compression_program: AccountInfo<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, service_id: String)]
+pub struct ExecutePurchaseCompressed<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub service_provider_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Must be the tree recorded on the session
+    #[account(
+        mut,
+        constraint = session_wallet.load()?.receipt_tree() == Some(merkle_tree.key())
+            @ ErrorCode::InvalidReceiptTree
+    )]
+    pub merkle_tree: AccountInfo<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_noop::ID)]
+    pub noop_program: AccountInfo<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_account_compression::ID)]
+    pub compression_program: AccountInfo<'info>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, service_provider_token_account.owner.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, service_provider_token_account.owner.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitCompressedSessionTree<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + CompressedSessionTree::SIZE,
+        seeds = [SESSION_TREE_SEED, merkle_tree.key().as_ref()],
+        bump
+    )]
+    pub session_tree: Account<'info, CompressedSessionTree>,
+
+    /// CHECK: Allocated by the client; initialized and validated by the compression program
+    #[account(mut, owner = spl_account_compression::ID)]
+    pub merkle_tree: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = admin,
+        seeds = [SESSION_TREE_VAULT_SEED, merkle_tree.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = session_tree
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_noop::ID)]
+    pub noop_program: AccountInfo<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_account_compression::ID)]
+    pub compression_program: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeCompressedSession<'info> {
+    #[account(
+        mut,
+        seeds = [SESSION_TREE_SEED, merkle_tree.key().as_ref()],
+        bump = session_tree.bump
+    )]
+    pub session_tree: Account<'info, CompressedSessionTree>,
+
+    /// CHECK: Must be the session tree's Merkle tree
+    #[account(mut, address = session_tree.merkle_tree)]
+    pub merkle_tree: AccountInfo<'info>,
+
+    #[account(mut, address = session_tree.vault)]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == authority.key() @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_tree.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_noop::ID)]
+    pub noop_program: AccountInfo<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_account_compression::ID)]
+    pub compression_program: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct FundCompressedSession<'info> {
+    #[account(
+        seeds = [SESSION_TREE_SEED, merkle_tree.key().as_ref()],
+        bump = session_tree.bump
+    )]
+    pub session_tree: Account<'info, CompressedSessionTree>,
+
+    /// CHECK: Must be the session tree's Merkle tree
+    #[account(mut, address = session_tree.merkle_tree)]
+    pub merkle_tree: AccountInfo<'info>,
+
+    #[account(mut, address = session_tree.vault)]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub funder_token_account: Account<'info, TokenAccount>,
+
+    pub funder: Signer<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_noop::ID)]
+    pub noop_program: AccountInfo<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_account_compression::ID)]
+    pub compression_program: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExecutePurchaseCompressedSession<'info> {
+    #[account(
+        seeds = [SESSION_TREE_SEED, merkle_tree.key().as_ref()],
+        bump = session_tree.bump
+    )]
+    pub session_tree: Box<Account<'info, CompressedSessionTree>>,
+
+    /// CHECK: Must be the session tree's Merkle tree
+    #[account(mut, address = session_tree.merkle_tree)]
+    pub merkle_tree: AccountInfo<'info>,
+
+    #[account(mut, address = session_tree.vault)]
+    pub vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = service_provider_token_account.mint == session_tree.mint @ ErrorCode::PayoutMintMismatch
+    )]
+    pub service_provider_token_account: Box<Account<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_tree.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Box<Account<'info, GlobalStats>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_tree.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_noop::ID)]
+    pub noop_program: AccountInfo<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_account_compression::ID)]
+    pub compression_program: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CloseCompressedSession<'info> {
+    #[account(
+        seeds = [SESSION_TREE_SEED, merkle_tree.key().as_ref()],
+        bump = session_tree.bump
+    )]
+    pub session_tree: Account<'info, CompressedSessionTree>,
+
+    /// CHECK: Must be the session tree's Merkle tree
+    #[account(mut, address = session_tree.merkle_tree)]
+    pub merkle_tree: AccountInfo<'info>,
+
+    #[account(mut, address = session_tree.vault)]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_tree.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_noop::ID)]
+    pub noop_program: AccountInfo<'info>,
+
+    /// CHECK: Address is constrained
+    #[account(address = spl_account_compression::ID)]
+    pub compression_program: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(template_id: String)]
+pub struct CreateSessionTemplate<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + SessionTemplate::SIZE,
+        seeds = [TEMPLATE_SEED, authority.key().as_ref(), template_id.as_bytes()],
+        bump
+    )]
+    pub template: Account<'info, SessionTemplate>,
+
+    #[account(has_one = authority)]
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(session_id: String)]
+pub struct InitializeSessionFromTemplate<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + SessionWallet::SIZE,
+        seeds = [SESSION_SEED, session_id.as_bytes()],
+        bump
+    )]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(has_one = authority)]
+    pub template: Box<Account<'info, SessionTemplate>>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == authority.key() @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, treasury.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    /// CHECK: Session token account will be created externally
+    #[account(mut)]
+    pub session_token_account: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    #[account(seeds = [AGENT_SEED, agent_account.agent.as_ref()], bump = agent_account.bump)]
+    pub agent_account: Option<Account<'info, AgentAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct ExtendSession<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    #[account(mut)]
+    pub authority_token_account: Option<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::MissingFundingAccounts
+    )]
+    pub session_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureAnomalyLock<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct AddSessionCurrency<'info> {
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + CurrencyBalance::SIZE,
+        seeds = [BALANCE_SEED, session_wallet.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub currency_balance: AccountLoader<'info, CurrencyBalance>,
+
+    #[account(
+        init,
+        payer = authority,
+        token::mint = mint,
+        token::authority = session_wallet,
+        seeds = [BALANCE_VAULT_SEED, session_wallet.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct FundSessionCurrency<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = currency_balance.load()?.session == session_wallet.key() @ ErrorCode::InvalidCurrencyBalance,
+        has_one = vault
+    )]
+    pub currency_balance: AccountLoader<'info, CurrencyBalance>,
+
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub funder_token_account: Account<'info, TokenAccount>,
+
+    pub funder: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawSessionCurrency<'info> {
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = currency_balance.load()?.session == session_wallet.key() @ ErrorCode::InvalidCurrencyBalance,
+        has_one = vault
+    )]
+    pub currency_balance: AccountLoader<'info, CurrencyBalance>,
+
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeSwapConfig<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + SwapConfig::SIZE,
+        seeds = [SWAP_CONFIG_SEED],
+        bump
+    )]
+    pub swap_config: Account<'info, SwapConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetSwapProgram<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [SWAP_CONFIG_SEED], bump = swap_config.bump)]
+    pub swap_config: Account<'info, SwapConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, service_id: String)]
+pub struct ExecutePurchaseWithSwap<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSwapAccounts,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSwapAccounts
+    )]
+    pub session_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// Session-owned account in the provider's mint that receives swap output
+    #[account(
+        mut,
+        constraint = swap_destination.owner == session_wallet.key() @ ErrorCode::InvalidSwapAccounts,
+        constraint = swap_destination.mint == service_provider_token_account.mint @ ErrorCode::InvalidSwapAccounts
+    )]
+    pub swap_destination: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub service_provider_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [SWAP_CONFIG_SEED], bump = swap_config.bump)]
+    pub swap_config: Account<'info, SwapConfig>,
+
+    /// CHECK: Checked against the configured swap program
+    #[account(executable, address = swap_config.swap_program @ ErrorCode::InvalidSwapAccounts)]
+    pub swap_program: AccountInfo<'info>,
+
+    /// The session's own policy; its MaxSlippage bounds the swap
+    #[account(constraint = session_wallet.load()?.policy() == Some(policy.key()) @ ErrorCode::PolicyAccountMismatch)]
+    pub policy: Box<Account<'info, Policy>>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, service_provider_token_account.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, service_provider_token_account.owner.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, service_provider_token_account.owner.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(escrow_id: u64, amount: u64, service_id: String)]
+pub struct OpenConditionalEscrow<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ConditionalEscrow::SIZE,
+        seeds = [ESCROW_SEED, session_wallet.key().as_ref(), &escrow_id.to_le_bytes()],
+        bump
+    )]
+    pub escrow: Box<Account<'info, ConditionalEscrow>>,
+
+    /// Escrows reserve and capture in the session mint
+    #[account(
+        constraint = service_provider_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidEscrowAccounts
+    )]
+    pub service_provider_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, service_provider_token_account.owner.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, service_provider_token_account.owner.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ConditionalCapture<'info> {
+    #[account(
+        mut,
+        has_one = authority
+    )]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        close = authority,
+        constraint = escrow.session == session_wallet.key() @ ErrorCode::InvalidEscrowAccounts,
+        constraint = escrow.provider_token_account == service_provider_token_account.key() @ ErrorCode::InvalidEscrowAccounts
+    )]
+    pub escrow: Box<Account<'info, ConditionalEscrow>>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub service_provider_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Session authority, receives the escrow rent
+    #[account(mut)]
+    pub authority: UncheckedAccount<'info>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct RefundEscrow<'info> {
+    #[account(
+        mut,
+        has_one = authority
+    )]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        close = authority,
+        constraint = escrow.session == session_wallet.key() @ ErrorCode::InvalidEscrowAccounts
+    )]
+    pub escrow: Box<Account<'info, ConditionalEscrow>>,
+
+    /// CHECK: Session authority, receives the escrow rent
+    #[account(mut)]
+    pub authority: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct OpenPayoutLedger<'info> {
+    #[account(
+        init,
+        payer = provider,
+        space = 8 + PayoutLedger::SIZE,
+        seeds = [PAYOUT_SEED, provider.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub ledger: AccountLoader<'info, PayoutLedger>,
+
+    #[account(
+        init,
+        payer = provider,
+        token::mint = mint,
+        token::authority = ledger,
+        seeds = [PAYOUT_VAULT_SEED, ledger.key().as_ref()],
+        bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub provider: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, service_id: String)]
+pub struct ExecutePurchaseAccrued<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        has_one = vault,
+        constraint = ledger.load()?.mint == session_wallet.load()?.mint @ ErrorCode::InvalidPayoutLedger
+    )]
+    pub ledger: AccountLoader<'info, PayoutLedger>,
+
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, ledger.load()?.provider.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, ledger.load()?.provider.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimPayout<'info> {
+    #[account(
+        mut,
+        has_one = provider,
+        has_one = vault
+    )]
+    pub ledger: AccountLoader<'info, PayoutLedger>,
+
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+
+    pub provider: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u8)]
+pub struct CreateVaultShard<'info> {
+    #[account(
+        mut,
+        has_one = mint
+    )]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        init,
+        payer = authority,
+        token::mint = mint,
+        token::authority = session_wallet,
+        seeds = [SHARD_SEED, session_wallet.key().as_ref(), &[index]],
+        bump
+    )]
+    pub shard: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct RebalanceShards<'info> {
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+#[instruction(batch_id: u64)]
+pub struct OpenBatch<'info> {
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + BatchState::SIZE,
+        seeds = [BATCH_SEED, session_wallet.key().as_ref(), &batch_id.to_le_bytes()],
+        bump
+    )]
+    pub batch_state: Account<'info, BatchState>,
+
+    #[account(
+        mut,
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct ExecutePurchasesBatch<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(
+        mut,
+        constraint = batch_state.session == session_wallet.key() @ ErrorCode::InvalidBatch
+    )]
+    pub batch_state: Option<Account<'info, BatchState>>,
+
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureInactivitySweep<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct SweepInactiveSession<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(mut)]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = Some(beneficiary_token_account.owner) == session_wallet.load()?.beneficiary() @ ErrorCode::InvalidBeneficiary,
+        constraint = beneficiary_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidBeneficiary
+    )]
+    pub beneficiary_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(member: Pubkey)]
+pub struct AssignRole<'info> {
+    #[account(has_one = authority)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + RoleAssignment::SIZE,
+        seeds = [ROLE_SEED, session_wallet.key().as_ref(), member.as_ref()],
+        bump
+    )]
+    pub role_assignment: Account<'info, RoleAssignment>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeRole<'info> {
+    #[account(has_one = authority)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        close = authority,
+        constraint = role_assignment.session == session_wallet.key() @ ErrorCode::InvalidRoleAssignment
+    )]
+    pub role_assignment: Account<'info, RoleAssignment>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeCategoryCodes<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + CategoryCodeTable::SIZE,
+        seeds = [CATEGORY_CODES_SEED],
+        bump
+    )]
+    pub category_codes: Account<'info, CategoryCodeTable>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetCategoryCodes<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Account<'info, CategoryCodeTable>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetCategoryCodesRequired<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeAutomationConfig<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + AutomationConfig::SIZE,
+        seeds = [AUTOMATION_CONFIG_SEED],
+        bump
+    )]
+    pub automation_config: Account<'info, AutomationConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetAutomationProgram<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [AUTOMATION_CONFIG_SEED], bump = automation_config.bump)]
+    pub automation_config: Account<'info, AutomationConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterAutomationThread<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(seeds = [AUTOMATION_CONFIG_SEED], bump = automation_config.bump)]
+    pub automation_config: Account<'info, AutomationConfig>,
+
+    /// CHECK: Checked against the configured automation program
+    #[account(
+        executable,
+        address = automation_config.automation_program @ ErrorCode::InvalidAutomationProgram
+    )]
+    pub automation_program: AccountInfo<'info>,
+
+    /// CHECK: Created by the automation program, which checks its address
+    pub thread: AccountInfo<'info>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct UnregisterAutomationThread<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(seeds = [AUTOMATION_CONFIG_SEED], bump = automation_config.bump)]
+    pub automation_config: Account<'info, AutomationConfig>,
+
+    /// CHECK: Checked against the configured automation program
+    #[account(
+        executable,
+        address = automation_config.automation_program @ ErrorCode::InvalidAutomationProgram
+    )]
+    pub automation_program: AccountInfo<'info>,
+
+    /// CHECK: Must be the thread recorded on the session
+    #[account(
+        constraint = session_wallet.load()?.automation_thread() == Some(thread.key())
+            @ ErrorCode::InvalidAutomationThread
+    )]
+    pub thread: AccountInfo<'info>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeBridgeConfig<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + BridgeConfig::SIZE,
+        seeds = [BRIDGE_CONFIG_SEED],
+        bump
+    )]
+    pub bridge_config: Account<'info, BridgeConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetBridgeProgram<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [BRIDGE_CONFIG_SEED], bump = bridge_config.bump)]
+    pub bridge_config: Account<'info, BridgeConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FundSessionFromBridge<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    /// Must also be the mint recipient named in the bridge message
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidBridgeTransfer,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidBridgeTransfer
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(seeds = [BRIDGE_CONFIG_SEED], bump = bridge_config.bump)]
+    pub bridge_config: Account<'info, BridgeConfig>,
+
+    /// CHECK: Checked against the configured bridge program
+    #[account(executable, address = bridge_config.bridge_program @ ErrorCode::InvalidBridgeTransfer)]
+    pub bridge_program: AccountInfo<'info>,
+
+    pub relayer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeSessionAuto<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + SessionWallet::SIZE,
+        seeds = [
+            SESSION_SEED,
+            derived_session_id(&agent_account.agent, agent_account.session_count).as_bytes()
+        ],
+        bump
+    )]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        seeds = [AGENT_SEED, agent_account.agent.as_ref()],
+        bump = agent_account.bump
+    )]
+    pub agent_account: Account<'info, AgentAccount>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == authority.key() @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, treasury.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    /// CHECK: Session token account will be created externally
+    #[account(mut)]
+    pub session_token_account: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExportSessionState<'info> {
+    #[account(mut, has_one = authority, close = authority)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + SessionCheckpoint::SIZE,
+        seeds = [CHECKPOINT_SEED, session_wallet.key().as_ref()],
+        bump
+    )]
+    pub checkpoint: Account<'info, SessionCheckpoint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ImportSessionState<'info> {
+    #[account(
+        mut,
+        seeds = [CHECKPOINT_SEED, checkpoint.session.as_ref()],
+        bump = checkpoint.bump,
+        constraint = checkpoint.version == SessionCheckpoint::VERSION @ ErrorCode::UnsupportedCheckpointVersion,
+        constraint = checkpoint.state.authority == authority.key() @ ErrorCode::Unauthorized,
+        close = authority
+    )]
+    pub checkpoint: Account<'info, SessionCheckpoint>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + SessionWallet::SIZE,
+        seeds = [SESSION_SEED, checkpoint.state.session_id.as_bytes()],
+        bump,
+        constraint = session_wallet.key() == checkpoint.session @ ErrorCode::InvalidCheckpoint
+    )]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(provider: Pubkey)]
+pub struct RegisterProvider<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + ProviderAccount::SIZE,
+        seeds = [PROVIDER_SEED, provider.as_ref()],
+        bump
+    )]
+    pub provider_account: Account<'info, ProviderAccount>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PayRebate<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidRebateAccounts,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidRebateAccounts
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [PROVIDER_SEED, provider.key().as_ref()],
+        bump = provider_account.bump
+    )]
+    pub provider_account: Account<'info, ProviderAccount>,
+
+    #[account(mut)]
+    pub provider_token_account: Account<'info, TokenAccount>,
+
+    pub provider: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct IssueCapability<'info> {
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Capability::SIZE,
+        seeds = [CAPABILITY_SEED, session_wallet.key().as_ref(), capability_key.key().as_ref()],
+        bump
+    )]
+    pub capability: Account<'info, Capability>,
+
+    /// CHECK: Ephemeral key the capability is issued to; only its address is used
+    pub capability_key: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeCapability<'info> {
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        close = authority,
+        constraint = capability.session == session_wallet.key() @ ErrorCode::InvalidCapability
+    )]
+    pub capability: Account<'info, Capability>,
+
+    #[account(
+        mut,
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, service_id: String)]
+pub struct ExecutePurchaseWithCapability<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        seeds = [CAPABILITY_SEED, session_wallet.key().as_ref(), capability_key.key().as_ref()],
+        bump = capability.bump
+    )]
+    pub capability: Account<'info, Capability>,
+
+    pub capability_key: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub service_provider_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, service_provider_token_account.owner.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, service_provider_token_account.owner.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+
+    /// The capability issuer's role assignment
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeFeeConfig<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + FeeConfig::SIZE,
+        seeds = [FEE_CONFIG_SEED],
+        bump
+    )]
+    pub fee_config: Account<'info, FeeConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetFeeConfig<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Account<'info, FeeConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(subject: Pubkey)]
+pub struct AssignFeeTier<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + FeeTier::SIZE,
+        seeds = [FEE_TIER_SEED, subject.as_ref()],
+        bump
+    )]
+    pub fee_tier: Account<'info, FeeTier>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveFeeTier<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [FEE_TIER_SEED, fee_tier.subject.as_ref()],
+        bump = fee_tier.bump
+    )]
+    pub fee_tier: Account<'info, FeeTier>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(claim_id: u64, amount: u64, service_id: String)]
+pub struct ExecuteBondedPurchase<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        init,
+        payer = provider,
+        space = 8 + BondClaim::SIZE,
+        seeds = [BOND_CLAIM_SEED, session_wallet.key().as_ref(), &claim_id.to_le_bytes()],
+        bump
+    )]
+    pub claim: Box<Account<'info, BondClaim>>,
+
+    #[account(
+        init,
+        payer = provider,
+        token::mint = bond_mint,
+        token::authority = claim,
+        seeds = [BOND_VAULT_SEED, claim.key().as_ref()],
+        bump
+    )]
+    pub bond_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(address = session_wallet.load()?.mint @ ErrorCode::InvalidBond)]
+    pub bond_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = service_provider_token_account.owner == provider.key() @ ErrorCode::InvalidBond
+    )]
+    pub service_provider_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// Provider's account in the session mint the bond is posted from
+    #[account(mut)]
+    pub provider_bond_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub provider: Signer<'info>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, service_provider_token_account.owner.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, service_provider_token_account.owner.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ChallengeBondedPurchase<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        close = provider,
+        has_one = provider,
+        constraint = claim.session == session_wallet.key() @ ErrorCode::InvalidBond
+    )]
+    pub claim: Account<'info, BondClaim>,
+
+    #[account(mut, seeds = [BOND_VAULT_SEED, claim.key().as_ref()], bump)]
+    pub bond_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidBond,
+        constraint = session_token_account.mint == bond_vault.mint @ ErrorCode::InvalidBond
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Claim provider, receives the claim and vault rent
+    #[account(mut)]
+    pub provider: UncheckedAccount<'info>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseBond<'info> {
+    #[account(mut, close = provider, has_one = provider)]
+    pub claim: Account<'info, BondClaim>,
+
+    #[account(mut, seeds = [BOND_VAULT_SEED, claim.key().as_ref()], bump)]
+    pub bond_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = provider_bond_account.owner == provider.key() @ ErrorCode::InvalidBond
+    )]
+    pub provider_bond_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Claim provider, receives the claim and vault rent
+    #[account(mut)]
+    pub provider: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(new_session_id: String)]
+pub struct CloneSession<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + SessionWallet::SIZE,
+        seeds = [SESSION_SEED, new_session_id.as_bytes()],
+        bump
+    )]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        has_one = authority,
+        constraint = source_session.load()?.mint == treasury.mint @ ErrorCode::InvalidCloneSource
+    )]
+    pub source_session: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.admin == authority.key() @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, treasury.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    /// CHECK: Session token account will be created externally
+    #[account(mut)]
+    pub session_token_account: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: AgentAccount PDA of the copied agent key; checked for debt when it exists
+    #[account(seeds = [AGENT_SEED, source_session.load()?.agent_pubkey.as_ref()], bump)]
+    pub agent_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RatePurchase<'info> {
+    #[account(has_one = authority)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(constraint = receipt.session == session_wallet.key() @ ErrorCode::InvalidRating)]
+    pub receipt: Account<'info, PurchaseReceipt>,
+
+    #[account(
+        mut,
+        seeds = [PROVIDER_SEED, receipt.provider.as_ref()],
+        bump = provider_account.bump
+    )]
+    pub provider_account: Account<'info, ProviderAccount>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PurchaseRating::SIZE,
+        seeds = [RATING_SEED, receipt.key().as_ref()],
+        bump
+    )]
+    pub rating: Account<'info, PurchaseRating>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureFundingCap<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, service_id: String)]
+pub struct ValidatePurchase<'info> {
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    pub service_provider_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Signer the purchase would be made by, checked as execute_purchase does
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: UncheckedAccount<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(
+        constraint = currency_balance.load()?.session == session_wallet.key() @ ErrorCode::InvalidCurrencyBalance
+    )]
+    pub currency_balance: Option<AccountLoader<'info, CurrencyBalance>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+
+    #[account(seeds = [FEE_TIER_SEED, provider_fee_tier.subject.as_ref()], bump = provider_fee_tier.bump)]
+    pub provider_fee_tier: Option<Account<'info, FeeTier>>,
+
+    /// CHECK: The provider's listing for the service id, read when it was created
+    #[account(seeds = [SERVICE_SEED, service_provider_token_account.owner.as_ref(), service_listing_seed(&service_id).as_ref()], bump)]
+    pub service_listing: UncheckedAccount<'info>,
+
+    /// CHECK: The provider's registry PDA, read when the provider has registered
+    #[account(seeds = [PROVIDER_SEED, service_provider_token_account.owner.as_ref()], bump)]
+    pub provider_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct InitializeGlobalStats<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + GlobalStats::SIZE,
+        seeds = [GLOBAL_STATS_SEED, mint.as_ref()],
+        bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct SweepFees<'info> {
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Account<'info, FeeConfig>,
+
+    #[account(seeds = [TREASURY_SEED, mint.as_ref()], bump = treasury.bump)]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    #[account(mut, seeds = [GLOBAL_STATS_SEED, mint.as_ref()], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    #[account(
+        mut,
+        constraint = fee_config.fee_destination().is_some()
+            && destination_token_account.owner == fee_config.fee_destination @ ErrorCode::InvalidFeeDestination
+    )]
+    pub destination_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct EscheatPayout<'info> {
+    #[account(mut, has_one = vault)]
+    pub ledger: AccountLoader<'info, PayoutLedger>,
+
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, ledger.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(service_id: String)]
+pub struct ListService<'info> {
+    #[account(
+        init,
+        payer = provider,
+        space = 8 + ServiceListing::SIZE,
+        seeds = [SERVICE_SEED, provider.key().as_ref(), service_listing_seed(&service_id).as_ref()],
+        bump
+    )]
+    pub service_listing: Account<'info, ServiceListing>,
+
+    #[account(mut)]
+    pub provider: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DeprecateService<'info> {
+    #[account(mut, has_one = provider)]
+    pub service_listing: Account<'info, ServiceListing>,
+
+    pub provider: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetAllowCpi<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Owner)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, deadline: i64, nonce: u64)]
+pub struct FundWithPermit<'info> {
+    #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(mut)]
+    pub funder_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key()
+            && session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
+    pub session_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = submitter,
+        space = 8 + FundingPermit::SIZE,
+        seeds = [
+            PERMIT_SEED,
+            session_wallet.key().as_ref(),
+            funder_token_account.owner.as_ref(),
+            nonce.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub permit: Account<'info, FundingPermit>,
+
+    #[account(mut)]
+    pub submitter: Signer<'info>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetProviderConfig<'info> {
+    #[account(
+        mut,
+        seeds = [PROVIDER_SEED, provider.key().as_ref()],
+        bump = provider_account.bump,
+        has_one = provider
+    )]
+    pub provider_account: Account<'info, ProviderAccount>,
+
+    pub provider: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetCircuitBreaker<'info> {
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct ResetCircuitBreaker<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [GLOBAL_STATS_SEED, mint.as_ref()], bump = global_stats.bump)]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ArchiveSession<'info> {
+    /// CHECK: Read as a SessionWallet in the handler and rewritten as a
+    /// SessionTombstone; an AccountLoader would restore the SessionWallet
+    /// discriminator on exit
+    #[account(mut, owner = crate::ID)]
+    pub session_wallet: UncheckedAccount<'info>,
+
+    /// CHECK: Receives the reclaimed rent, must be the session authority
+    #[account(mut)]
+    pub session_authority: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+}
+
+#[derive(Accounts)]
+#[instruction(workflow_id: String)]
+pub struct ExecuteWorkflowPurchase<'info> {
     #[account(mut)]
+    pub session_wallet: AccountLoader<'info, SessionWallet>,
+
+    #[account(
+        mut,
+        constraint = session_token_account.owner == session_wallet.key() @ ErrorCode::InvalidSessionTokenAccount,
+        constraint = session_token_account.mint == session_wallet.load()?.mint @ ErrorCode::InvalidSessionTokenAccount
+    )]
     pub session_token_account: Account<'info, TokenAccount>,
 
-    pub funder: Signer<'info>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + WorkflowReceipt::SIZE,
+        seeds = [WORKFLOW_SEED, session_wallet.key().as_ref(), workflow_id.as_bytes()],
+        bump
+    )]
+    pub workflow_receipt: Box<Account<'info, WorkflowReceipt>>,
+
+    #[account(
+        mut,
+        constraint = has_role(&session_wallet, authority.key(), role_assignment.as_ref(), Role::Operator)? @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: Instructions sysvar, address is constrained
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATS_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = global_stats.bump
+    )]
+    pub global_stats: Account<'info, GlobalStats>,
+
+    pub policy: Option<Account<'info, Policy>>,
+
+    #[account(mut)]
+    pub role_assignment: Option<Account<'info, RoleAssignment>>,
+
+    #[account(seeds = [CATEGORY_CODES_SEED], bump = category_codes.bump)]
+    pub category_codes: Option<Account<'info, CategoryCodeTable>>,
+
+    #[account(seeds = [FEE_CONFIG_SEED], bump = fee_config.bump)]
+    pub fee_config: Box<Account<'info, FeeConfig>>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED, session_wallet.load()?.mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Box<Account<'info, Treasury>>,
+
+    #[account(mut, address = treasury.vault)]
+    pub treasury_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [FEE_TIER_SEED, agent_fee_tier.subject.as_ref()], bump = agent_fee_tier.bump)]
+    pub agent_fee_tier: Option<Account<'info, FeeTier>>,
+}
+
+// ============================================================================
+// State
+// ============================================================================
+
+#[account]
+pub struct Config {
+    pub admin: Pubkey,            // Program admin
+    pub volume_window_slots: u64, // Slots per circuit breaker window
+    pub max_window_volume: u64,   // Purchase volume per mint and window, 0 = breaker off
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl Config {
+    pub const SIZE: usize = 32 + // admin
+                            8 +  // volume_window_slots
+                            8 +  // max_window_volume
+                            1;   // bump
+}
+
+#[account]
+pub struct Treasury {
+    pub mint: Pubkey,             // Token mint held by the vault
+    pub vault: Pubkey,            // Program-owned token account
+    pub total_deposited: u64,     // Lifetime deposits
+    pub total_withdrawn: u64,     // Lifetime admin withdrawals
+    pub total_disbursed: u64,     // Lifetime session funding
+    pub total_refunded: u64,      // Lifetime session refunds
+    pub total_fees: u64,          // Lifetime platform fees from purchases
+    pub total_escheated: u64,     // Lifetime abandoned provider payouts taken in
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl Treasury {
+    pub const SIZE: usize = 32 + // mint
+                            32 + // vault
+                            8 +  // total_deposited
+                            8 +  // total_withdrawn
+                            8 +  // total_disbursed
+                            8 +  // total_refunded
+                            8 +  // total_fees
+                            8 +  // total_escheated
+                            1;   // bump
+}
+
+/// Session state, laid out for zero-copy access so the purchase path reads
+/// and writes fields in place instead of (de)serializing the whole account
+#[account(zero_copy)]
+pub struct SessionWallet {
+    pub authority: Pubkey,        // Program authority (your backend)
+    pub mint: Pubkey,             // Token mint, matches the funding treasury
+    pub agent_pubkey: Pubkey,     // Key that signs purchase intents, default = unset
+    pub nonce_account: Pubkey,    // Durable nonce derived from this PDA, default = unset
+    pub policy: Pubkey,           // Attached spending policy, default = unset
+    pub receipt_tree: Pubkey,     // Compressed receipt Merkle tree, default = unset
+    pub beneficiary: Pubkey,      // Owner of swept funds after inactivity, default = unset
+    pub automation_thread: Pubkey, // Registered automation thread, default = unset
+    pub session_id: [u8; 32],     // Unique session ID, first session_id_len bytes
+    pub created_at: i64,          // Unix timestamp
+    pub last_activity: i64,       // Unix timestamp
+    pub initial_balance: u64,     // USDC (6 decimals)
+    pub current_balance: u64,     // USDC (6 decimals)
+    pub purchase_count: u64,      // Purchases executed (receipt index)
+    pub total_funded: u64,        // Lifetime funding, including initial
+    pub total_spent: u64,         // Lifetime purchases
+    pub snapshot_count: u64,      // Snapshots taken (snapshot index)
+    pub last_intent_nonce: u64,   // Highest intent nonce redeemed
+    pub compressed_receipt_count: u64, // Leaves appended to receipt_tree
+    pub expires_at: i64,          // Unix timestamp, 0 = no expiry
+    pub expiry_grace_period: i64, // Seconds before expires_at that purchases warn
+    pub velocity_window: i64,     // Anomaly window in seconds, 0 = disabled
+    pub window_start: i64,        // Unix timestamp the current window opened
+    pub window_spent: u64,        // Spend in the current window
+    pub average_window_spend: u64, // Moving average of spend per closed window
+    pub escrowed_balance: u64,    // Held by open conditional escrows
+    pub inactivity_sweep_secs: i64, // Inactivity before the balance can be swept, 0 = disabled
+    pub total_rebated: u64,       // Lifetime provider rebates, not part of total_funded
+    pub total_channel_refunds: u64, // Lifetime netting channel refunds, not part of total_funded
+    pub untracked_provider_spend: u64, // Spend with providers past provider_spend's capacity
+    pub max_total_funding: u64,   // Ceiling on total_funded, 0 = uncapped
+    pub pending_funding_cap: u64, // Proposed higher ceiling, 0 = none
+    pub funding_cap_raise_at: i64, // Unix timestamp pending_funding_cap can be applied
+    pub last_event_slot: u64,     // Slot of the last state-changing instruction
+    pub last_event_seq: u64,      // State-changing instructions applied, for gap detection
+    pub category_budgets: [CategoryBudgetEntry; MAX_CATEGORY_BUDGETS], // Per-category spend caps
+    pub provider_spend: [ProviderSpendEntry; MAX_REPORT_PROVIDERS], // Per-provider totals for SessionReport
+    pub velocity_multiple: u32,   // Window spend limit as a multiple of the average
+    pub session_id_len: u8,       // Bytes of session_id in use
+    pub is_active: u8,            // Session active status, see is_active()
+    pub is_suspended: u8,         // Locked by the anomaly detector, see is_suspended()
+    pub bump: u8,                 // PDA bump seed
+    pub shard_count: u8,          // Extra vault shards created for this session
+    pub category_budget_count: u8, // Entries of category_budgets in use
+    pub category_codes_required: u8, // Purchases must map to a reporting code, see category_codes_required()
+    pub automation_task: u8,      // AutomationTask the thread runs, see automation_task()
+    pub provider_spend_count: u8, // Entries of provider_spend in use
+    pub allow_cpi: u8,            // Purchases may be composed with other programs, see allow_cpi()
+    pub _padding: [u8; 2],        // Keeps the layout 8-byte aligned
+}
+
+impl SessionWallet {
+    /// Longest session id; it is used whole as a PDA seed
+    pub const MAX_SESSION_ID_LEN: usize = 32;
+
+    pub const SIZE: usize = std::mem::size_of::<SessionWallet>();
+
+    pub fn session_id(&self) -> &str {
+        std::str::from_utf8(&self.session_id[..self.session_id_len as usize]).unwrap_or_default()
+    }
+
+    pub fn set_session_id(&mut self, session_id: &str) -> Result<()> {
+        require!(
+            session_id.len() <= Self::MAX_SESSION_ID_LEN,
+            ErrorCode::SessionIdTooLong
+        );
+        self.session_id = [0; Self::MAX_SESSION_ID_LEN];
+        self.session_id[..session_id.len()].copy_from_slice(session_id.as_bytes());
+        self.session_id_len = session_id.len() as u8;
+        Ok(())
+    }
+
+    /// The PDA's signer seeds, copied out so no borrow of the account is held
+    /// across a CPI that passes the session as signer
+    pub fn signer_seeds(&self) -> SessionSeeds {
+        SessionSeeds {
+            session_id: self.session_id,
+            session_id_len: self.session_id_len,
+            bump: [self.bump],
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_active != 0
+    }
+
+    pub fn set_active(&mut self, is_active: bool) {
+        self.is_active = is_active as u8;
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.is_suspended != 0
+    }
+
+    pub fn set_suspended(&mut self, is_suspended: bool) {
+        self.is_suspended = is_suspended as u8;
+    }
+
+    /// Advance the event sequence. Every state-changing instruction calls this
+    /// once, so an indexer whose count lags `last_event_seq` knows to backfill.
+    pub fn record_event(&mut self) -> Result<()> {
+        self.last_event_slot = Clock::get()?.slot;
+        self.last_event_seq = self
+            .last_event_seq
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
+        Ok(())
+    }
+
+    /// Whether funding `amount` more would push `total_funded` past the cap
+    pub fn exceeds_funding_cap(&self, amount: u64) -> bool {
+        self.max_total_funding != 0
+            && self.total_funded.saturating_add(amount) > self.max_total_funding
+    }
+
+    pub fn allow_cpi(&self) -> bool {
+        self.allow_cpi != 0
+    }
+
+    pub fn set_allow_cpi(&mut self, allow_cpi: bool) {
+        self.allow_cpi = allow_cpi as u8;
+    }
+
+    pub fn category_codes_required(&self) -> bool {
+        self.category_codes_required != 0
+    }
+
+    pub fn set_category_codes_required(&mut self, required: bool) {
+        self.category_codes_required = required as u8;
+    }
+
+    pub fn agent_pubkey(&self) -> Option<Pubkey> {
+        optional_key(self.agent_pubkey)
+    }
+
+    pub fn set_agent_pubkey(&mut self, agent_pubkey: Option<Pubkey>) {
+        self.agent_pubkey = agent_pubkey.unwrap_or_default();
+    }
+
+    pub fn nonce_account(&self) -> Option<Pubkey> {
+        optional_key(self.nonce_account)
+    }
+
+    pub fn set_nonce_account(&mut self, nonce_account: Option<Pubkey>) {
+        self.nonce_account = nonce_account.unwrap_or_default();
+    }
+
+    pub fn policy(&self) -> Option<Pubkey> {
+        optional_key(self.policy)
+    }
+
+    pub fn set_policy(&mut self, policy: Option<Pubkey>) {
+        self.policy = policy.unwrap_or_default();
+    }
+
+    pub fn receipt_tree(&self) -> Option<Pubkey> {
+        optional_key(self.receipt_tree)
+    }
+
+    pub fn set_receipt_tree(&mut self, receipt_tree: Option<Pubkey>) {
+        self.receipt_tree = receipt_tree.unwrap_or_default();
+    }
+
+    pub fn beneficiary(&self) -> Option<Pubkey> {
+        optional_key(self.beneficiary)
+    }
+
+    pub fn set_beneficiary(&mut self, beneficiary: Option<Pubkey>) {
+        self.beneficiary = beneficiary.unwrap_or_default();
+    }
+
+    pub fn automation_thread(&self) -> Option<Pubkey> {
+        optional_key(self.automation_thread)
+    }
+
+    pub fn automation_task(&self) -> Option<AutomationTask> {
+        self.automation_thread()?;
+        AutomationTask::from_u8(self.automation_task)
+    }
+
+    pub fn set_automation(&mut self, automation: Option<(Pubkey, AutomationTask)>) {
+        match automation {
+            Some((thread, task)) => {
+                self.automation_thread = thread;
+                self.automation_task = task as u8;
+            }
+            None => {
+                self.automation_thread = Pubkey::default();
+                self.automation_task = 0;
+            }
+        }
+    }
+
+    pub fn category_budgets(&self) -> &[CategoryBudgetEntry] {
+        &self.category_budgets[..self.category_budget_count as usize]
+    }
+
+    pub fn category_budgets_mut(&mut self) -> &mut [CategoryBudgetEntry] {
+        &mut self.category_budgets[..self.category_budget_count as usize]
+    }
+
+    /// Replace the category budgets; callers validate them first
+    pub fn set_category_budgets(&mut self, category_budgets: &[CategoryBudget]) {
+        self.category_budgets = [CategoryBudgetEntry::default(); MAX_CATEGORY_BUDGETS];
+        for (entry, budget) in self.category_budgets.iter_mut().zip(category_budgets) {
+            *entry = CategoryBudgetEntry::from(budget);
+        }
+        self.category_budget_count = category_budgets.len().min(MAX_CATEGORY_BUDGETS) as u8;
+    }
+
+    pub fn provider_spend(&self) -> &[ProviderSpendEntry] {
+        &self.provider_spend[..self.provider_spend_count as usize]
+    }
+
+    pub fn set_provider_spend(&mut self, provider_spend: &[ProviderSpend]) {
+        self.provider_spend = [ProviderSpendEntry::default(); MAX_REPORT_PROVIDERS];
+        for (entry, spend) in self.provider_spend.iter_mut().zip(provider_spend) {
+            *entry = ProviderSpendEntry::from(spend);
+        }
+        self.provider_spend_count = provider_spend.len().min(MAX_REPORT_PROVIDERS) as u8;
+    }
+
+    /// Purchases paid to `provider`, or `None` when the provider has no entry
+    /// and every entry is taken, so its purchases are untracked
+    pub fn provider_purchase_count(&self, provider: Pubkey) -> Option<u64> {
+        match self.provider_spend().iter().find(|entry| entry.provider == provider) {
+            Some(entry) => Some(entry.purchase_count),
+            None if (self.provider_spend_count as usize) < MAX_REPORT_PROVIDERS => Some(0),
+            None => None,
+        }
+    }
+
+    /// Add a purchase to the provider's running total, or to
+    /// `untracked_provider_spend` once every entry is taken
+    pub fn record_provider_spend(&mut self, provider: Pubkey, amount: u64) -> Result<()> {
+        let count = self.provider_spend_count as usize;
+        let index = match self.provider_spend[..count]
+            .iter()
+            .position(|entry| entry.provider == provider)
+        {
+            Some(index) => index,
+            None if count < MAX_REPORT_PROVIDERS => {
+                self.provider_spend[count].provider = provider;
+                self.provider_spend_count += 1;
+                count
+            }
+            None => {
+                self.untracked_provider_spend = self
+                    .untracked_provider_spend
+                    .checked_add(amount)
+                    .ok_or(ErrorCode::Overflow)?;
+                return Ok(());
+            }
+        };
+
+        let entry = &mut self.provider_spend[index];
+        entry.amount = entry.amount.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+        entry.purchase_count = entry
+            .purchase_count
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
+        Ok(())
+    }
+}
+
+/// Owned copy of a session PDA's signer seeds
+pub struct SessionSeeds {
+    session_id: [u8; SessionWallet::MAX_SESSION_ID_LEN],
+    session_id_len: u8,
+    bump: [u8; 1],
+}
+
+impl SessionSeeds {
+    pub fn seeds(&self) -> [&[u8]; 3] {
+        [
+            SESSION_SEED,
+            &self.session_id[..self.session_id_len as usize],
+            &self.bump,
+        ]
+    }
+}
+
+/// Zero-copy accounts store unset keys as the default pubkey
+fn optional_key(key: Pubkey) -> Option<Pubkey> {
+    (key != Pubkey::default()).then_some(key)
+}
+
+#[account]
+pub struct RedeemedVoucher {
+    pub session: Pubkey,          // Session wallet PDA that paid
+    pub resource_hash: [u8; 32],  // Hash of the 402-gated resource
+    pub pay_to: Pubkey,           // Token account that was paid
+    pub amount: u64,              // USDC (6 decimals)
+    pub redeemed_at: i64,         // Unix timestamp
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl RedeemedVoucher {
+    pub const SIZE: usize = 32 + // session
+                            32 + // resource_hash
+                            32 + // pay_to
+                            8 +  // amount
+                            8 +  // redeemed_at
+                            1;   // bump
+}
+
+/// Written once by `snapshot_session`; no instruction mutates or closes it
+#[account]
+pub struct SessionSnapshot {
+    pub session: Pubkey,          // Session wallet PDA
+    pub snapshot_index: u64,      // Index within the session
+    pub initial_balance: u64,     // USDC (6 decimals)
+    pub current_balance: u64,     // USDC (6 decimals)
+    pub total_funded: u64,        // USDC (6 decimals)
+    pub total_spent: u64,         // USDC (6 decimals)
+    pub purchase_count: u64,      // Purchases executed so far
+    pub is_active: bool,          // Session active status
+    pub slot: u64,                // Slot the snapshot was taken in
+    pub timestamp: i64,           // Unix timestamp
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl SessionSnapshot {
+    pub const SIZE: usize = 32 + // session
+                            8 +  // snapshot_index
+                            8 +  // initial_balance
+                            8 +  // current_balance
+                            8 +  // total_funded
+                            8 +  // total_spent
+                            8 +  // purchase_count
+                            1 +  // is_active
+                            8 +  // slot
+                            8 +  // timestamp
+                            1;   // bump
+}
+
+#[account]
+pub struct PurchaseReceipt {
+    pub session: Pubkey,          // Session wallet PDA that paid
+    pub mint: Pubkey,             // Receipt token mint (supply 1)
+    pub provider: Pubkey,         // Owner of the paid token account
+    pub service_id: String,       // Purchased service
+    pub category_code: Option<u32>, // Reporting code the service id mapped to
+    pub amount: u64,              // USDC (6 decimals)
+    pub purchase_index: u64,      // Index within the session
+    pub timestamp: i64,           // Unix timestamp
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl PurchaseReceipt {
+    pub const MAX_SERVICE_ID_LEN: usize = 64;
+
+    pub const SIZE: usize = 32 + // session
+                            32 + // mint
+                            32 + // provider
+                            4 + Self::MAX_SERVICE_ID_LEN + // service_id
+                            1 + 4 + // category_code
+                            8 +  // amount
+                            8 +  // purchase_index
+                            8 +  // timestamp
+                            1;   // bump
+}
+
+/// Token metadata of a receipt mint, the program-owned stand-in for a
+/// Metaplex metadata account
+#[account]
+pub struct ReceiptMetadata {
+    pub mint: Pubkey,             // Receipt token mint
+    pub receipt: Pubkey,          // PurchaseReceipt the token stands for
+    pub name: String,             // "Receipt #<purchase_index>"
+    pub symbol: String,           // Always SYMBOL
+    pub uri: String,              // Off-chain JSON metadata, may be empty
+    pub service_id: String,       // Purchased service
+    pub amount: u64,              // USDC (6 decimals)
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl ReceiptMetadata {
+    pub const SYMBOL: &'static str = "RCPT";
+    pub const MAX_NAME_LEN: usize = 32;
+    pub const MAX_SYMBOL_LEN: usize = 10;
+    pub const MAX_URI_LEN: usize = 200;
+
+    pub const SIZE: usize = 32 + // mint
+                            32 + // receipt
+                            4 + Self::MAX_NAME_LEN + // name
+                            4 + Self::MAX_SYMBOL_LEN + // symbol
+                            4 + Self::MAX_URI_LEN + // uri
+                            4 + PurchaseReceipt::MAX_SERVICE_ID_LEN + // service_id
+                            8 +  // amount
+                            1;   // bump
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Http402Voucher {
+    pub amount: u64,              // USDC (6 decimals)
+    pub resource_hash: [u8; 32],  // Hash of the 402-gated resource
+    pub expiry: i64,              // Unix timestamp
+}
+
+#[account]
+pub struct Policy {
+    pub authority: Pubkey,        // Owner, must match the session authority
+    pub policy_id: String,        // Unique per authority
+    pub rules: Vec<PolicyRule>,   // Evaluated in order
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl Policy {
+    pub const MAX_POLICY_ID_LEN: usize = 32;
+    pub const MAX_RULES: usize = 16;
+
+    pub const SIZE: usize = 32 + // authority
+                            4 + Self::MAX_POLICY_ID_LEN + // policy_id
+                            4 + Self::MAX_RULES * PolicyRule::SIZE + // rules
+                            1;   // bump
+
+    /// Check a purchase against every rule. Limit rules fail on the first
+    /// violation; allow rules of the same kind combine into an allowlist.
+    pub fn evaluate(
+        &self,
+        purchase: &PurchaseContext,
+    ) -> std::result::Result<(), RejectionReason> {
+        let mut provider_listed = None;
+        let mut category_listed = None;
+
+        for rule in self.rules.iter() {
+            match rule {
+                PolicyRule::MaxAmount { amount } => {
+                    if purchase.amount > *amount {
+                        return Err(RejectionReason::PolicyAmountExceeded);
+                    }
+                }
+                PolicyRule::AllowProvider { provider } => {
+                    let listed = provider_listed.get_or_insert(false);
+                    *listed |= *provider == purchase.provider;
+                }
+                PolicyRule::TimeWindow {
+                    not_before,
+                    not_after,
+                } => {
+                    if purchase.timestamp < *not_before || purchase.timestamp > *not_after {
+                        return Err(RejectionReason::PolicyOutsideTimeWindow);
+                    }
+                }
+                PolicyRule::AllowCategory { prefix } => {
+                    let listed = category_listed.get_or_insert(false);
+                    *listed |= purchase.service_id.starts_with(prefix.as_str());
+                }
+                PolicyRule::MaxSlippage { .. } => {}
+            }
+        }
+
+        if !provider_listed.unwrap_or(true) {
+            return Err(RejectionReason::PolicyProviderNotAllowed);
+        }
+        if !category_listed.unwrap_or(true) {
+            return Err(RejectionReason::PolicyCategoryNotAllowed);
+        }
+
+        Ok(())
+    }
+
+    /// Tightest slippage bound across `MaxSlippage` rules; swaps are off without one
+    pub fn max_slippage_bps(&self) -> Option<u16> {
+        self.rules
+            .iter()
+            .filter_map(|rule| match rule {
+                PolicyRule::MaxSlippage { bps } => Some(*bps),
+                _ => None,
+            })
+            .min()
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub enum PolicyRule {
+    /// Reject purchases above `amount`
+    MaxAmount { amount: u64 },
+    /// Allow purchases paying this provider (token account owner)
+    AllowProvider { provider: Pubkey },
+    /// Reject purchases outside `[not_before, not_after]`
+    TimeWindow { not_before: i64, not_after: i64 },
+    /// Allow service ids in this category, i.e. starting with `prefix`
+    AllowCategory { prefix: String },
+    /// Allow swap-routed purchases paying at most `bps` over the quoted input
+    MaxSlippage { bps: u16 },
+}
+
+impl PolicyRule {
+    pub const MAX_CATEGORY_LEN: usize = 32;
+
+    // Tag plus the largest variant (AllowCategory)
+    pub const SIZE: usize = 1 + 4 + Self::MAX_CATEGORY_LEN;
+}
+
+/// Why a purchase or funding attempt was denied, carried on `PurchaseRejected`
+/// and `FundingRejected`. Each reason fails the instruction with the
+/// `ErrorCode` of the same name, so the event is never committed: indexers
+/// and webhooks decode it from the failed transaction's `Program data:` log
+/// lines, or from a simulation run before sending.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectionReason {
+    SessionClosed,
+    InsufficientBalance,
+    PolicyAccountMismatch,
+    PolicyAmountExceeded,
+    PolicyProviderNotAllowed,
+    PolicyOutsideTimeWindow,
+    PolicyCategoryNotAllowed,
+    SessionExpired,
+    CategoryBudgetExceeded,
+    SessionSuspended,
+    UnknownCategoryCode,
+    FundingCapExceeded,
+    ServiceDeprecated,
+    PayoutMintMismatch,
+}
+
+impl From<RejectionReason> for ErrorCode {
+    fn from(reason: RejectionReason) -> Self {
+        match reason {
+            RejectionReason::SessionClosed => ErrorCode::SessionClosed,
+            RejectionReason::InsufficientBalance => ErrorCode::InsufficientBalance,
+            RejectionReason::PolicyAccountMismatch => ErrorCode::PolicyAccountMismatch,
+            RejectionReason::PolicyAmountExceeded => ErrorCode::PolicyAmountExceeded,
+            RejectionReason::PolicyProviderNotAllowed => ErrorCode::PolicyProviderNotAllowed,
+            RejectionReason::PolicyOutsideTimeWindow => ErrorCode::PolicyOutsideTimeWindow,
+            RejectionReason::PolicyCategoryNotAllowed => ErrorCode::PolicyCategoryNotAllowed,
+            RejectionReason::SessionExpired => ErrorCode::SessionExpired,
+            RejectionReason::CategoryBudgetExceeded => ErrorCode::CategoryBudgetExceeded,
+            RejectionReason::SessionSuspended => ErrorCode::SessionSuspended,
+            RejectionReason::UnknownCategoryCode => ErrorCode::UnknownCategoryCode,
+            RejectionReason::FundingCapExceeded => ErrorCode::FundingCapExceeded,
+            RejectionReason::ServiceDeprecated => ErrorCode::ServiceDeprecated,
+            RejectionReason::PayoutMintMismatch => ErrorCode::PayoutMintMismatch,
+        }
+    }
+}
+
+#[account]
+pub struct AgentAccount {
+    pub agent: Pubkey,            // Agent key
+    pub credit_limit: u64,        // Admin-assigned, from reputation
+    pub outstanding_debt: u64,    // Sum of open Debt balances
+    pub session_count: u64,       // Sessions opened by initialize_session_auto
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl AgentAccount {
+    pub const SIZE: usize = 32 + // agent
+                            8 +  // credit_limit
+                            8 +  // outstanding_debt
+                            8 +  // session_count
+                            1;   // bump
+}
+
+#[account]
+pub struct Debt {
+    pub agent_account: Pubkey,    // Borrowing agent account
+    pub mint: Pubkey,             // Mint the credit was drawn in
+    pub outstanding: u64,         // Owed to the treasury
+    pub total_borrowed: u64,      // Lifetime draws
+    pub total_repaid: u64,        // Lifetime repayments
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl Debt {
+    pub const SIZE: usize = 32 + // agent_account
+                            32 + // mint
+                            8 +  // outstanding
+                            8 +  // total_borrowed
+                            8 +  // total_repaid
+                            1;   // bump
+}
+
+#[account]
+pub struct NettingChannel {
+    pub session: Pubkey,          // Session wallet PDA
+    pub provider: Pubkey,         // Provider wallet
+    pub provider_token_account: Pubkey, // Provider settlement account
+    pub session_owes: u64,        // Unsettled purchases (reserved from balance)
+    pub provider_owes: u64,       // Unsettled refunds
+    pub total_purchases: u64,     // Lifetime purchases through the channel
+    pub total_refunds: u64,       // Lifetime refunds through the channel
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl NettingChannel {
+    pub const SIZE: usize = 32 + // session
+                            32 + // provider
+                            32 + // provider_token_account
+                            8 +  // session_owes
+                            8 +  // provider_owes
+                            8 +  // total_purchases
+                            8 +  // total_refunds
+                            1;   // bump
+}
+
+/// Most category budgets a session or template can carry
+pub const MAX_CATEGORY_BUDGETS: usize = 4;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CategoryBudget {
+    pub prefix: String,           // Service id prefix, as in PolicyRule::AllowCategory
+    pub limit: u64,               // USDC (6 decimals)
+    pub spent: u64,               // USDC (6 decimals), always 0 on templates
+}
+
+impl CategoryBudget {
+    pub const SIZE: usize = 4 + PolicyRule::MAX_CATEGORY_LEN + // prefix
+                            8 +  // limit
+                            8;   // spent
+}
+
+/// Providers a session tracks individually for its closing report
+pub const MAX_REPORT_PROVIDERS: usize = 8;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProviderSpend {
+    pub provider: Pubkey,         // Owner of the paid token account
+    pub amount: u64,              // USDC (6 decimals)
+    pub purchase_count: u64,      // Purchases paid to the provider
+}
+
+impl ProviderSpend {
+    pub const SIZE: usize = 32 + // provider
+                            8 +  // amount
+                            8;   // purchase_count
+}
+
+impl From<&ProviderSpendEntry> for ProviderSpend {
+    fn from(entry: &ProviderSpendEntry) -> Self {
+        Self {
+            provider: entry.provider,
+            amount: entry.amount,
+            purchase_count: entry.purchase_count,
+        }
+    }
+}
+
+/// Fixed-size form of a `ProviderSpend`, as stored on a session
+#[zero_copy]
+#[derive(Default)]
+pub struct ProviderSpendEntry {
+    pub provider: Pubkey,         // Owner of the paid token account
+    pub amount: u64,              // USDC (6 decimals)
+    pub purchase_count: u64,      // Purchases paid to the provider
+}
+
+impl From<&ProviderSpend> for ProviderSpendEntry {
+    fn from(spend: &ProviderSpend) -> Self {
+        Self {
+            provider: spend.provider,
+            amount: spend.amount,
+            purchase_count: spend.purchase_count,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CategorySpend {
+    pub prefix: String,           // Category budget prefix
+    pub amount: u64,              // USDC (6 decimals)
+}
+
+/// Fixed-size form of a `CategoryBudget`, as stored on a session
+#[zero_copy]
+#[derive(Default)]
+pub struct CategoryBudgetEntry {
+    pub limit: u64,               // USDC (6 decimals)
+    pub spent: u64,               // USDC (6 decimals)
+    pub prefix: [u8; 32],         // Service id prefix, first prefix_len bytes
+    pub prefix_len: u8,           // Bytes of prefix in use
+    pub _padding: [u8; 7],        // Keeps the layout 8-byte aligned
+}
+
+impl CategoryBudgetEntry {
+    pub fn prefix(&self) -> &str {
+        std::str::from_utf8(&self.prefix[..self.prefix_len as usize]).unwrap_or_default()
+    }
+}
+
+impl From<&CategoryBudget> for CategoryBudgetEntry {
+    fn from(budget: &CategoryBudget) -> Self {
+        let len = budget.prefix.len().min(PolicyRule::MAX_CATEGORY_LEN);
+        let mut prefix = [0; PolicyRule::MAX_CATEGORY_LEN];
+        prefix[..len].copy_from_slice(&budget.prefix.as_bytes()[..len]);
+        Self {
+            limit: budget.limit,
+            spent: budget.spent,
+            prefix,
+            prefix_len: len as u8,
+            _padding: [0; 7],
+        }
+    }
+}
+
+#[account]
+pub struct SessionTemplate {
+    pub authority: Pubkey,        // Owner, becomes the session authority
+    pub template_id: String,      // Unique per authority
+    pub policy: Option<Pubkey>,   // Policy attached to new sessions
+    pub expiry_duration: i64,     // Seconds from creation, 0 = no expiry
+    pub expiry_grace_period: i64, // Copied to new sessions
+    pub category_budgets: Vec<CategoryBudget>, // Copied to new sessions
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl SessionTemplate {
+    pub const MAX_TEMPLATE_ID_LEN: usize = 32;
+
+    pub const SIZE: usize = 32 + // authority
+                            4 + Self::MAX_TEMPLATE_ID_LEN + // template_id
+                            33 + // policy
+                            8 +  // expiry_duration
+                            8 +  // expiry_grace_period
+                            4 + MAX_CATEGORY_BUDGETS * CategoryBudget::SIZE + // category_budgets
+                            1;   // bump
+}
+
+#[account(zero_copy)]
+pub struct CurrencyBalance {
+    pub session: Pubkey,          // Session wallet PDA
+    pub mint: Pubkey,             // Secondary mint held by the session
+    pub vault: Pubkey,            // Token account owned by the session PDA
+    pub current_balance: u64,     // Base units of mint
+    pub total_funded: u64,        // Lifetime funding
+    pub total_spent: u64,         // Lifetime purchases
+    pub bump: u8,                 // PDA bump seed
+    pub _padding: [u8; 7],        // Keeps the layout 8-byte aligned
+}
+
+impl CurrencyBalance {
+    pub const SIZE: usize = std::mem::size_of::<CurrencyBalance>();
+}
+
+#[account]
+pub struct SwapConfig {
+    pub swap_program: Pubkey,     // Program used to convert between mints
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl SwapConfig {
+    pub const SIZE: usize = 32 + // swap_program
+                            1;   // bump
+}
+
+#[account]
+pub struct ConditionalEscrow {
+    pub session: Pubkey,          // Session wallet PDA holding the funds
+    pub escrow_id: u64,           // Caller-chosen, unique per session
+    pub provider_token_account: Pubkey, // Paid on capture
+    pub verifier: Pubkey,         // Key whose attestation releases the funds
+    pub amount: u64,              // USDC (6 decimals)
+    pub service_id: String,       // Service being paid for
+    pub category_code: Option<u32>, // Reporting code the service id mapped to
+    pub output_hash: [u8; 32],    // Expected hash of the call's output
+    pub created_at: i64,          // Unix timestamp
+    pub expires_at: i64,          // Refundable after this
+    pub bump: u8,                 // PDA bump seed
+    pub fee: u64,                 // Platform fee, fixed when opened
+    pub fee_bps: u16,             // Basis points the fee was charged at
+}
+
+impl ConditionalEscrow {
+    pub const SIZE: usize = 32 + // session
+                            8 +  // escrow_id
+                            32 + // provider_token_account
+                            32 + // verifier
+                            8 +  // amount
+                            4 + PurchaseReceipt::MAX_SERVICE_ID_LEN + // service_id
+                            1 + 4 + // category_code
+                            32 + // output_hash
+                            8 +  // created_at
+                            8 +  // expires_at
+                            1 +  // bump
+                            8 +  // fee
+                            2;   // fee_bps
+}
+
+#[account(zero_copy)]
+pub struct PayoutLedger {
+    pub provider: Pubkey,         // Provider that claims the earnings
+    pub mint: Pubkey,             // Token mint earned
+    pub vault: Pubkey,            // Token account owned by the ledger PDA
+    pub accrued: u64,             // Unclaimed earnings
+    pub total_accrued: u64,       // Lifetime earnings
+    pub total_claimed: u64,       // Lifetime claims
+    pub purchase_count: u64,      // Purchases accrued
+    pub total_escheated: u64,     // Lifetime earnings swept after going unclaimed
+    pub last_claimed_at: i64,     // Unix timestamp of the last claim, or of opening
+    pub bump: u8,                 // PDA bump seed
+    pub _padding: [u8; 7],        // Keeps the layout 8-byte aligned
+}
+
+impl PayoutLedger {
+    pub const SIZE: usize = std::mem::size_of::<PayoutLedger>();
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct BatchPurchase {
+    pub provider_token_account: Pubkey,
+    pub service_id: String,
+    pub amount: u64,
+}
+
+#[account]
+pub struct BatchState {
+    pub session: Pubkey,          // Session wallet PDA
+    pub batch_id: u64,            // Caller-chosen id, part of the PDA seeds
+    pub entries_hash: [u8; 32],   // batch_entries_hash of the full entry list
+    pub entry_count: u16,         // Entries in the batch
+    pub cursor: u16,              // Index of the next entry to run
+    pub total_amount: u64,        // Spent by the entries run so far
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl BatchState {
+    pub const SIZE: usize = 32 + // session
+                            8 +  // batch_id
+                            32 + // entries_hash
+                            2 +  // entry_count
+                            2 +  // cursor
+                            8 +  // total_amount
+                            1;   // bump
+}
+
+/// What a non-authority key may do on a session
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Owner,    // Fund, close and change limits
+    Operator, // Purchase within its limits
+    Auditor,  // Take snapshots
+}
+
+impl Role {
+    /// Owners may do anything the other roles can
+    pub fn permits(&self, required: Role) -> bool {
+        *self == Role::Owner || *self == required
+    }
+}
+
+#[account]
+pub struct RoleAssignment {
+    pub session: Pubkey,          // Session wallet PDA
+    pub member: Pubkey,           // Key holding the role
+    pub role: Role,               // Granted role
+    pub purchase_limit: u64,      // Operator cap per purchase, 0 = unlimited
+    pub spend_limit: u64,         // Operator cap across purchases, 0 = unlimited
+    pub spent: u64,               // Operator spend so far
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl RoleAssignment {
+    pub const SIZE: usize = 32 + // session
+                            32 + // member
+                            1 +  // role
+                            8 +  // purchase_limit
+                            8 +  // spend_limit
+                            8 +  // spent
+                            1;   // bump
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CategoryCode {
+    pub prefix: String,           // Service id prefix, empty matches everything
+    pub code: u32,                // Tax/reporting code
+}
+
+impl CategoryCode {
+    pub const SIZE: usize = 4 + PolicyRule::MAX_CATEGORY_LEN + // prefix
+                            4;   // code
+}
+
+#[account]
+pub struct CategoryCodeTable {
+    pub codes: Vec<CategoryCode>, // Checked in order, first match wins
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl CategoryCodeTable {
+    pub const MAX_CATEGORY_CODES: usize = 16;
+
+    pub const SIZE: usize = 4 + Self::MAX_CATEGORY_CODES * CategoryCode::SIZE + // codes
+                            1;   // bump
+}
+
+#[account]
+pub struct AutomationConfig {
+    pub automation_program: Pubkey, // Thread program that runs scheduled operations
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl AutomationConfig {
+    pub const SIZE: usize = 32 + // automation_program
+                            1;   // bump
+}
+
+/// Scheduled operation an automation thread runs for a session
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AutomationTask {
+    CloseAtExpiry,
+    ProcessSubscriptions,
+    Refill,
+}
+
+impl AutomationTask {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(AutomationTask::CloseAtExpiry),
+            1 => Some(AutomationTask::ProcessSubscriptions),
+            2 => Some(AutomationTask::Refill),
+            _ => None,
+        }
+    }
+}
+
+#[account]
+pub struct BridgeConfig {
+    pub bridge_program: Pubkey,   // Program that redeems bridged USDC transfers
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl BridgeConfig {
+    pub const SIZE: usize = 32 + // bridge_program
+                            1;   // bump
+}
+
+/// Session state in a canonical, layout-independent form
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SessionState {
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    pub agent_pubkey: Option<Pubkey>,
+    pub nonce_account: Option<Pubkey>,
+    pub policy: Option<Pubkey>,
+    pub receipt_tree: Option<Pubkey>,
+    pub beneficiary: Option<Pubkey>,
+    pub automation_thread: Option<Pubkey>,
+    pub automation_task: Option<AutomationTask>,
+    pub session_id: String,
+    pub created_at: i64,
+    pub last_activity: i64,
+    pub initial_balance: u64,
+    pub current_balance: u64,
+    pub purchase_count: u64,
+    pub total_funded: u64,
+    pub total_spent: u64,
+    pub snapshot_count: u64,
+    pub last_intent_nonce: u64,
+    pub compressed_receipt_count: u64,
+    pub expires_at: i64,
+    pub expiry_grace_period: i64,
+    pub velocity_window: i64,
+    pub window_start: i64,
+    pub window_spent: u64,
+    pub average_window_spend: u64,
+    pub escrowed_balance: u64,
+    pub inactivity_sweep_secs: i64,
+    pub total_rebated: u64,
+    pub total_channel_refunds: u64,
+    pub untracked_provider_spend: u64,
+    pub max_total_funding: u64,
+    pub pending_funding_cap: u64,
+    pub funding_cap_raise_at: i64,
+    pub last_event_slot: u64,
+    pub last_event_seq: u64,
+    pub category_budgets: Vec<CategoryBudget>,
+    pub provider_spend: Vec<ProviderSpend>,
+    pub velocity_multiple: u32,
+    pub is_active: bool,
+    pub is_suspended: bool,
+    pub shard_count: u8,
+    pub category_codes_required: bool,
+    pub allow_cpi: bool,
+}
+
+impl SessionState {
+    pub const SIZE: usize = 32 + // authority
+                            32 + // mint
+                            5 * (1 + 32) + // agent_pubkey, nonce_account, policy, receipt_tree, beneficiary
+                            1 + 32 + // automation_thread
+                            1 + 1 + // automation_task
+                            4 + SessionWallet::MAX_SESSION_ID_LEN + // session_id
+                            8 * 26 + // timestamps, balances and counters
+                            4 + MAX_CATEGORY_BUDGETS * CategoryBudget::SIZE + // category_budgets
+                            4 + MAX_REPORT_PROVIDERS * ProviderSpend::SIZE + // provider_spend
+                            4 +  // velocity_multiple
+                            1 +  // is_active
+                            1 +  // is_suspended
+                            1 +  // shard_count
+                            1 +  // category_codes_required
+                            1;   // allow_cpi
+
+    pub fn capture(session: &SessionWallet) -> Self {
+        Self {
+            authority: session.authority,
+            mint: session.mint,
+            agent_pubkey: session.agent_pubkey(),
+            nonce_account: session.nonce_account(),
+            policy: session.policy(),
+            receipt_tree: session.receipt_tree(),
+            beneficiary: session.beneficiary(),
+            automation_thread: session.automation_thread(),
+            automation_task: session.automation_task(),
+            session_id: session.session_id().to_string(),
+            created_at: session.created_at,
+            last_activity: session.last_activity,
+            initial_balance: session.initial_balance,
+            current_balance: session.current_balance,
+            purchase_count: session.purchase_count,
+            total_funded: session.total_funded,
+            total_spent: session.total_spent,
+            snapshot_count: session.snapshot_count,
+            last_intent_nonce: session.last_intent_nonce,
+            compressed_receipt_count: session.compressed_receipt_count,
+            expires_at: session.expires_at,
+            expiry_grace_period: session.expiry_grace_period,
+            velocity_window: session.velocity_window,
+            window_start: session.window_start,
+            window_spent: session.window_spent,
+            average_window_spend: session.average_window_spend,
+            escrowed_balance: session.escrowed_balance,
+            inactivity_sweep_secs: session.inactivity_sweep_secs,
+            total_rebated: session.total_rebated,
+            total_channel_refunds: session.total_channel_refunds,
+            untracked_provider_spend: session.untracked_provider_spend,
+            max_total_funding: session.max_total_funding,
+            pending_funding_cap: session.pending_funding_cap,
+            funding_cap_raise_at: session.funding_cap_raise_at,
+            last_event_slot: session.last_event_slot,
+            last_event_seq: session.last_event_seq,
+            category_budgets: session
+                .category_budgets()
+                .iter()
+                .map(|entry| CategoryBudget {
+                    prefix: entry.prefix().to_string(),
+                    limit: entry.limit,
+                    spent: entry.spent,
+                })
+                .collect(),
+            provider_spend: session.provider_spend().iter().map(ProviderSpend::from).collect(),
+            velocity_multiple: session.velocity_multiple,
+            is_active: session.is_active(),
+            is_suspended: session.is_suspended(),
+            shard_count: session.shard_count,
+            category_codes_required: session.category_codes_required(),
+            allow_cpi: session.allow_cpi(),
+        }
+    }
+
+    pub fn restore(&self, session: &mut SessionWallet, bump: u8) -> Result<()> {
+        session.authority = self.authority;
+        session.mint = self.mint;
+        session.set_agent_pubkey(self.agent_pubkey);
+        session.set_nonce_account(self.nonce_account);
+        session.set_policy(self.policy);
+        session.set_receipt_tree(self.receipt_tree);
+        session.set_beneficiary(self.beneficiary);
+        session.set_automation(self.automation_thread.zip(self.automation_task));
+        session.set_session_id(&self.session_id)?;
+        session.created_at = self.created_at;
+        session.last_activity = self.last_activity;
+        session.initial_balance = self.initial_balance;
+        session.current_balance = self.current_balance;
+        session.purchase_count = self.purchase_count;
+        session.total_funded = self.total_funded;
+        session.total_spent = self.total_spent;
+        session.snapshot_count = self.snapshot_count;
+        session.last_intent_nonce = self.last_intent_nonce;
+        session.compressed_receipt_count = self.compressed_receipt_count;
+        session.expires_at = self.expires_at;
+        session.expiry_grace_period = self.expiry_grace_period;
+        session.velocity_window = self.velocity_window;
+        session.window_start = self.window_start;
+        session.window_spent = self.window_spent;
+        session.average_window_spend = self.average_window_spend;
+        session.escrowed_balance = self.escrowed_balance;
+        session.inactivity_sweep_secs = self.inactivity_sweep_secs;
+        session.total_rebated = self.total_rebated;
+        session.total_channel_refunds = self.total_channel_refunds;
+        session.untracked_provider_spend = self.untracked_provider_spend;
+        session.max_total_funding = self.max_total_funding;
+        session.pending_funding_cap = self.pending_funding_cap;
+        session.funding_cap_raise_at = self.funding_cap_raise_at;
+        session.last_event_slot = self.last_event_slot;
+        session.last_event_seq = self.last_event_seq;
+        session.set_category_budgets(&self.category_budgets);
+        session.set_provider_spend(&self.provider_spend);
+        session.velocity_multiple = self.velocity_multiple;
+        session.set_active(self.is_active);
+        session.set_suspended(self.is_suspended);
+        session.bump = bump;
+        session.shard_count = self.shard_count;
+        session.set_category_codes_required(self.category_codes_required);
+        session.set_allow_cpi(self.allow_cpi);
+        Ok(())
+    }
+}
+
+#[account]
+pub struct SessionCheckpoint {
+    pub session: Pubkey,          // Session wallet PDA the state belongs to
+    pub version: u8,              // Checkpoint format, see SessionCheckpoint::VERSION
+    pub state: SessionState,      // Exported session state
+    pub exported_at: i64,         // Unix timestamp
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl SessionCheckpoint {
+    /// Format written by this program version. Later versions keep reading
+    /// every earlier format.
+    pub const VERSION: u8 = 1;
+
+    pub const SIZE: usize = 32 + // session
+                            1 +  // version
+                            SessionState::SIZE + // state
+                            8 +  // exported_at
+                            1;   // bump
+}
+
+#[account]
+pub struct ProviderAccount {
+    pub provider: Pubkey,         // Provider key
+    pub total_rebated: u64,       // Lifetime rebates paid into sessions
+    pub rating_count: u64,        // Purchases rated by buyers
+    pub rating_total: u64,        // Sum of scores, average = rating_total / rating_count
+    pub payout_mint: Pubkey,      // Mint the provider accepts payment in, default = any
+    pub trial_discount_bps: u16,  // Discount on a session's first purchase, 10000 = free
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl ProviderAccount {
+    pub const SIZE: usize = 32 + // provider
+                            8 +  // total_rebated
+                            8 +  // rating_count
+                            8 +  // rating_total
+                            32 + // payout_mint
+                            2 +  // trial_discount_bps
+                            1;   // bump
+
+    pub fn payout_mint(&self) -> Option<Pubkey> {
+        optional_key(self.payout_mint)
+    }
+}
+
+#[account]
+pub struct Capability {
+    pub session: Pubkey,          // Session wallet PDA the right spends from
+    pub key: Pubkey,              // Ephemeral key that signs purchases
+    pub scope_hash: [u8; 32],     // capability_scope_hash of the allowed service id prefix
+    pub max_amount: u64,          // USDC (6 decimals), lifetime
+    pub spent: u64,               // USDC (6 decimals)
+    pub expires_at: i64,          // Unix timestamp
+    pub bump: u8,                 // PDA bump seed
+    pub issuer: Pubkey,           // Owner who issued the capability
+}
+
+impl Capability {
+    pub const SIZE: usize = 32 + // session
+                            32 + // key
+                            32 + // scope_hash
+                            8 +  // max_amount
+                            8 +  // spent
+                            8 +  // expires_at
+                            1 +  // bump
+                            32;  // issuer
+}
+
+#[account]
+pub struct FeeConfig {
+    pub fee_bps: u16,             // Default platform fee on purchases
+    pub fee_destination: Pubkey,  // Owner of the token accounts fees are swept to, default = unset
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl FeeConfig {
+    pub const SIZE: usize = 2 +  // fee_bps
+                            32 + // fee_destination
+                            1;   // bump
+
+    pub fn fee_destination(&self) -> Option<Pubkey> {
+        optional_key(self.fee_destination)
+    }
+}
+
+#[account]
+pub struct GlobalStats {
+    pub mint: Pubkey,             // Token mint the totals are denominated in
+    pub total_fees_swept: u64,    // Lifetime fees moved out of the treasury
+    pub sweep_count: u64,         // Sweeps performed
+    pub last_swept_at: i64,       // Unix timestamp, 0 = never swept
+    pub window_start_slot: u64,   // Slot the circuit breaker window opened
+    pub window_volume: u64,       // Purchase volume in the current window
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl GlobalStats {
+    pub const SIZE: usize = 32 + // mint
+                            8 +  // total_fees_swept
+                            8 +  // sweep_count
+                            8 +  // last_swept_at
+                            8 +  // window_start_slot
+                            8 +  // window_volume
+                            1;   // bump
+}
+
+#[account]
+pub struct FeeTier {
+    pub subject: Pubkey,          // Agent key or provider key
+    pub fee_bps: u16,             // Negotiated fee, 0 = exempt
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl FeeTier {
+    pub const SIZE: usize = 32 + // subject
+                            2 +  // fee_bps
+                            1;   // bump
+}
+
+#[account]
+pub struct BondClaim {
+    pub session: Pubkey,          // Session wallet PDA that paid
+    pub claim_id: u64,            // Caller-chosen, unique per session
+    pub provider: Pubkey,         // Provider that posted the bond
+    pub arbiter: Pubkey,          // Key whose attestation upholds a challenge
+    pub amount: u64,              // USDC (6 decimals), paid up front
+    pub bond: u64,                // USDC (6 decimals), held in the bond vault
+    pub challenge_deadline: i64,  // Unix timestamp the challenge window closes
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl BondClaim {
+    pub const SIZE: usize = 32 + // session
+                            8 +  // claim_id
+                            32 + // provider
+                            32 + // arbiter
+                            8 +  // amount
+                            8 +  // bond
+                            8 +  // challenge_deadline
+                            1;   // bump
+}
+
+#[account]
+pub struct PurchaseRating {
+    pub receipt: Pubkey,          // Receipt the rating is for, one rating each
+    pub provider: Pubkey,         // Rated provider
+    pub score: u8,                // MIN_SCORE..=MAX_SCORE
+    pub comment_hash: [u8; 32],   // Hash of the off-chain comment
+    pub rated_at: i64,            // Unix timestamp
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl PurchaseRating {
+    pub const MIN_SCORE: u8 = 1;
+    pub const MAX_SCORE: u8 = 5;
+
+    pub const SIZE: usize = 32 + // receipt
+                            32 + // provider
+                            1 +  // score
+                            32 + // comment_hash
+                            8 +  // rated_at
+                            1;   // bump
+}
+
+#[account]
+pub struct ServiceListing {
+    pub provider: Pubkey,         // Provider offering the service
+    pub service_id: String,       // Service id purchases name
+    pub listed_at: i64,           // Unix timestamp
+    pub deprecated_after: i64,    // Unix timestamp purchases start failing, 0 = not deprecated
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl ServiceListing {
+    pub const MAX_SERVICE_ID_LEN: usize = PurchaseReceipt::MAX_SERVICE_ID_LEN;
+
+    pub const SIZE: usize = 32 + // provider
+                            4 + Self::MAX_SERVICE_ID_LEN + // service_id
+                            8 +  // listed_at
+                            8 +  // deprecated_after
+                            1;   // bump
+
+    pub fn deprecated_after(&self) -> Option<i64> {
+        (self.deprecated_after != 0).then_some(self.deprecated_after)
+    }
+}
+
+#[account]
+pub struct FundingPermit {
+    pub session: Pubkey,          // Session wallet PDA that was funded
+    pub funder: Pubkey,           // Owner that signed the permit
+    pub nonce: u64,               // Funder-chosen permit nonce
+    pub amount: u64,              // USDC (6 decimals)
+    pub redeemed_at: i64,         // Unix timestamp
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl FundingPermit {
+    pub const SIZE: usize = 32 + // session
+                            32 + // funder
+                            8 +  // nonce
+                            8 +  // amount
+                            8 +  // redeemed_at
+                            1;   // bump
+}
+
+#[account]
+pub struct SessionTombstone {
+    pub authority: Pubkey,        // Authority of the archived session
+    pub mint: Pubkey,             // Token mint of the archived session
+    pub session_id: String,       // Session ID, keeps the PDA seed in use
+    pub created_at: i64,          // Unix timestamp the session was created
+    pub archived_at: i64,         // Unix timestamp
+    pub total_funded: u64,        // Final lifetime funding
+    pub total_spent: u64,         // Final lifetime purchases
+    pub total_rebated: u64,       // Final lifetime provider rebates
+    pub purchase_count: u64,      // Final purchase count
+    pub last_event_seq: u64,      // Final event sequence number
+    pub state_hash: [u8; 32],     // Hash of the full SessionWallet at archive time
+    pub bump: u8,                 // PDA bump seed of the session
+}
+
+impl SessionTombstone {
+    pub const SIZE: usize = 32 + // authority
+                            32 + // mint
+                            4 + SessionWallet::MAX_SESSION_ID_LEN + // session_id
+                            8 +  // created_at
+                            8 +  // archived_at
+                            8 +  // total_funded
+                            8 +  // total_spent
+                            8 +  // total_rebated
+                            8 +  // purchase_count
+                            8 +  // last_event_seq
+                            32 + // state_hash
+                            1;   // bump
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WorkflowLeg {
+    pub provider: Pubkey,         // Owner of the paid token account
+    pub service_id: String,       // Service purchased in this step
+    pub amount: u64,              // USDC (6 decimals)
+}
+
+impl WorkflowLeg {
+    pub const SIZE: usize = 32 + // provider
+                            4 + PurchaseReceipt::MAX_SERVICE_ID_LEN + // service_id
+                            8;   // amount
+}
+
+#[account]
+pub struct WorkflowReceipt {
+    pub session: Pubkey,          // Session wallet PDA that paid
+    pub workflow_id: String,      // Unique per session
+    pub legs: Vec<WorkflowLeg>,   // Legs in the order they were paid
+    pub total_amount: u64,        // Sum of leg amounts
+    pub first_purchase_index: u64, // Session purchase index of the first leg
+    pub timestamp: i64,           // Unix timestamp
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl WorkflowReceipt {
+    pub const MAX_WORKFLOW_ID_LEN: usize = 32;
+    pub const MAX_LEGS: usize = 8;
+
+    pub const SIZE: usize = 32 + // session
+                            4 + Self::MAX_WORKFLOW_ID_LEN + // workflow_id
+                            4 + Self::MAX_LEGS * WorkflowLeg::SIZE + // legs
+                            8 +  // total_amount
+                            8 +  // first_purchase_index
+                            8 +  // timestamp
+                            1;   // bump
+}
+
+/// Tree of compressed sessions in one mint
+#[account]
+pub struct CompressedSessionTree {
+    pub merkle_tree: Pubkey,      // Concurrent Merkle tree holding one leaf per session
+    pub mint: Pubkey,             // Token mint every session in the tree holds
+    pub vault: Pubkey,            // Token account owned by this PDA, pooled across sessions
+    pub session_count: u64,       // Sessions opened (next session's leaf index)
+    pub bump: u8,                 // PDA bump seed
+}
+
+impl CompressedSessionTree {
+    pub const SIZE: usize = 32 + // merkle_tree
+                            32 + // mint
+                            32 + // vault
+                            8 +  // session_count
+                            1;   // bump
+}
+
+/// Session state kept as a `CompressedSessionTree` leaf instead of an account
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CompressedSession {
+    pub authority: Pubkey,        // Program authority (your backend)
+    pub session_id: String,       // Session ID, at most MAX_SESSION_ID_LEN bytes
+    pub created_at: i64,          // Unix timestamp
+    pub initial_balance: u64,     // Token base units
+    pub current_balance: u64,     // Token base units, held in the tree's vault
+    pub total_funded: u64,        // Lifetime funding, including initial
+    pub total_spent: u64,         // Lifetime purchases
+    pub purchase_count: u64,      // Purchases executed
+    pub is_active: bool,          // Cleared when the session is closed
+}
+
+impl CompressedSession {
+    /// Leaf this state is stored as at `leaf_index` of `merkle_tree`
+    pub fn leaf(&self, merkle_tree: &Pubkey, leaf_index: u32) -> [u8; 32] {
+        hashv(&[
+            merkle_tree.as_ref(),
+            &leaf_index.to_le_bytes(),
+            self.authority.as_ref(),
+            &[self.session_id.len() as u8],
+            self.session_id.as_bytes(),
+            &self.created_at.to_le_bytes(),
+            &self.initial_balance.to_le_bytes(),
+            &self.current_balance.to_le_bytes(),
+            &self.total_funded.to_le_bytes(),
+            &self.total_spent.to_le_bytes(),
+            &self.purchase_count.to_le_bytes(),
+            &[self.is_active as u8],
+        ])
+        .to_bytes()
+    }
+}
+
+// ============================================================================
+// Events
+// ============================================================================
+
+#[event]
+pub struct ConfigInitialized {
+    pub admin: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TreasuryInitialized {
+    pub treasury: Pubkey,
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TreasuryDeposited {
+    pub treasury: Pubkey,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TreasuryWithdrawn {
+    pub treasury: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionCreated {
+    pub session_id: String,
+    pub pda: Pubkey,
+    pub initial_funding: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionNonceCreated {
+    pub session_id: String,
+    pub nonce_account: Pubkey,
+    pub nonce_authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionNonceClosed {
+    pub session_id: String,
+    pub nonce_account: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PurchaseExecuted {
+    pub session_id: String,
+    pub service_id: String,
+    pub category_code: Option<u32>,
+    pub amount: u64,
+    pub remaining_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReceiptMinted {
+    pub session_id: String,
+    pub receipt: Pubkey,
+    pub mint: Pubkey,
+    pub metadata: Pubkey,
+    pub purchase_index: u64,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SnapshotTaken {
+    pub session_id: String,
+    pub snapshot: Pubkey,
+    pub snapshot_index: u64,
+    pub current_balance: u64,
+    pub total_funded: u64,
+    pub total_spent: u64,
+    pub purchase_count: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AgentKeySet {
+    pub session_id: String,
+    pub agent_pubkey: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PurchaseIntentVerified {
+    pub session_id: String,
+    pub agent_pubkey: Pubkey,
+    pub nonce: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VoucherRedeemed {
+    pub session_id: String,
+    pub resource_hash: [u8; 32],
+    pub pay_to: Pubkey,
+    pub amount: u64,
+    pub remaining_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FundsAdded {
+    pub session_id: String,
+    pub amount: u64,
+    pub new_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionClosed {
+    pub session_id: String,
+    pub refunded_amount: u64,
+    pub total_spent: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PolicyUpdated {
+    pub policy: Pubkey,
+    pub authority: Pubkey,
+    pub rule_count: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PolicyAttached {
+    pub session_id: String,
+    pub policy: Option<Pubkey>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PurchaseRejected {
+    pub session_id: String,
+    pub service_id: String,
+    pub provider: Pubkey,
+    pub amount: u64,
+    pub reason: RejectionReason,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FundingRejected {
+    pub session_id: String,
+    pub funder: Pubkey,
+    pub amount: u64,
+    pub reason: RejectionReason,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AgentRegistered {
+    pub agent: Pubkey,
+    pub agent_account: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CreditLimitSet {
+    pub agent: Pubkey,
+    pub credit_limit: u64,
+    pub outstanding_debt: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CreditDrawn {
+    pub session_id: String,
+    pub agent: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub outstanding: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DebtRepaid {
+    pub agent: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub outstanding: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct NettingChannelOpened {
+    pub session_id: String,
+    pub channel: Pubkey,
+    pub provider: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ChannelObligationRecorded {
+    pub channel: Pubkey,
+    pub service_id: String,
+    pub category_code: Option<u32>,
+    pub session_owes: u64,
+    pub provider_owes: u64,
+    pub amount: u64,
+    pub is_refund: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ChannelSettled {
+    pub session_id: String,
+    pub channel: Pubkey,
+    pub session_owed: u64,
+    pub provider_owed: u64,
+    pub net_amount: u64,
+    pub provider_paid: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReceiptTreeInitialized {
+    pub session_id: String,
+    pub merkle_tree: Pubkey,
+    pub max_depth: u32,
+    pub max_buffer_size: u32,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CompressedReceiptAppended {
+    pub session_id: String,
+    pub merkle_tree: Pubkey,
+    pub leaf: [u8; 32],
+    pub leaf_index: u64,
+    pub provider: Pubkey,
+    pub service_id: String,
+    pub category_code: Option<u32>,
+    pub amount: u64,
+    pub purchase_index: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionTemplateCreated {
+    pub template: Pubkey,
+    pub authority: Pubkey,
+    pub policy: Option<Pubkey>,
+    pub expiry_duration: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionCreatedFromTemplate {
+    pub session_id: String,
+    pub template: Pubkey,
+    pub expires_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionExpiringSoon {
+    pub session_id: String,
+    pub expires_at: i64,
+    pub seconds_remaining: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionExtended {
+    pub session_id: String,
+    pub previous_expiry: i64,
+    pub new_expiry: i64,
+    pub grace_period: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AnomalyLockConfigured {
+    pub session_id: String,
+    pub velocity_window: i64,
+    pub velocity_multiple: u32,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AnomalyDetected {
+    pub session_id: String,
+    pub window_spent: u64,
+    pub average_window_spend: u64,
+    pub velocity_multiple: u32,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionResumed {
+    pub session_id: String,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionCurrencyAdded {
+    pub session_id: String,
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionCurrencyFunded {
+    pub session_id: String,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub new_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionCurrencyWithdrawn {
+    pub session_id: String,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub new_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SwapProgramSet {
+    pub swap_program: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PurchaseSwapped {
+    pub session_id: String,
+    pub service_id: String,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EscrowOpened {
+    pub session_id: String,
+    pub escrow: Pubkey,
+    pub verifier: Pubkey,
+    pub amount: u64,
+    pub expires_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EscrowCaptured {
+    pub session_id: String,
+    pub escrow: Pubkey,
+    pub status_code: u16,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EscrowRefunded {
+    pub session_id: String,
+    pub escrow: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PayoutLedgerOpened {
+    pub provider: Pubkey,
+    pub mint: Pubkey,
+    pub ledger: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PayoutClaimed {
+    pub provider: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub total_claimed: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultShardCreated {
+    pub session_id: String,
+    pub shard: Pubkey,
+    pub index: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ShardsRebalanced {
+    pub session_id: String,
+    pub vaults: u8,
+    pub total: u64,
+    pub moved: u64,
+    pub timestamp: i64,
+}
 
-    pub token_program: Program<'info, Token>,
+#[event]
+pub struct BatchOpened {
+    pub session_id: String,
+    pub batch: Pubkey,
+    pub entry_count: u16,
+    pub timestamp: i64,
 }
 
-#[derive(Accounts)]
-pub struct CloseSession<'info> {
-    #[account(
-        mut,
-        seeds = [b"session", session_wallet.session_id.as_bytes()],
-        bump = session_wallet.bump,
-        has_one = authority
-    )]
-    pub session_wallet: Account<'info, SessionWallet>,
+#[event]
+pub struct BatchProgress {
+    pub session_id: String,
+    pub batch: Option<Pubkey>,
+    pub processed: u16,
+    pub cursor: u16,
+    pub entry_count: u16,
+    pub amount: u64,
+    pub total_amount: u64,
+    pub remaining_balance: u64,
+    pub complete: bool,
+    pub timestamp: i64,
+}
 
-    #[account(mut)]
-    pub session_token_account: Account<'info, TokenAccount>,
+#[event]
+pub struct InactivitySweepConfigured {
+    pub session_id: String,
+    pub beneficiary: Option<Pubkey>,
+    pub inactivity_sweep_secs: i64,
+    pub timestamp: i64,
+}
 
-    #[account(mut)]
-    pub treasury_token_account: Account<'info, TokenAccount>,
+#[event]
+pub struct InactiveSessionSwept {
+    pub session_id: String,
+    pub beneficiary: Pubkey,
+    pub amount: u64,
+    pub last_activity: i64,
+    pub timestamp: i64,
+}
 
-    pub authority: Signer<'info>,
+#[event]
+pub struct RoleAssigned {
+    pub session_id: String,
+    pub member: Pubkey,
+    pub role: Role,
+    pub purchase_limit: u64,
+    pub spend_limit: u64,
+    pub timestamp: i64,
+}
 
-    pub token_program: Program<'info, Token>,
+#[event]
+pub struct RoleRevoked {
+    pub session_id: String,
+    pub member: Pubkey,
+    pub role: Role,
+    pub timestamp: i64,
 }
 
-// ============================================================================
-// State
-// ============================================================================
+#[event]
+pub struct CategoryCodesUpdated {
+    pub code_count: u8,
+    pub timestamp: i64,
+}
 
-#[account]
-pub struct SessionWallet {
-    pub authority: Pubkey,        // Program authority (your backend)
-    pub session_id: String,       // Unique session ID
-    pub created_at: i64,          // Unix timestamp
-    pub last_activity: i64,       // Unix timestamp
-    pub initial_balance: u64,     // USDC (6 decimals)
-    pub current_balance: u64,     // USDC (6 decimals)
-    pub is_active: bool,          // Session active status
-    pub bump: u8,                 // PDA bump seed
+#[event]
+pub struct CategoryCodesRequiredSet {
+    pub session_id: String,
+    pub required: bool,
+    pub timestamp: i64,
 }
 
-impl SessionWallet {
-    pub const SIZE: usize = 32 + // authority
-                            64 + // session_id (max length)
-                            8 +  // created_at
-                            8 +  // last_activity
-                            8 +  // initial_balance
-                            8 +  // current_balance
-                            1 +  // is_active
-                            1;   // bump
+#[event]
+pub struct AutomationProgramSet {
+    pub automation_program: Pubkey,
+    pub timestamp: i64,
 }
 
-// ============================================================================
-// Events
-// ============================================================================
+#[event]
+pub struct AutomationThreadRegistered {
+    pub session_id: String,
+    pub thread: Pubkey,
+    pub task: AutomationTask,
+    pub timestamp: i64,
+}
 
 #[event]
-pub struct SessionCreated {
+pub struct AutomationThreadUnregistered {
     pub session_id: String,
-    pub pda: Pubkey,
-    pub initial_funding: u64,
+    pub thread: Pubkey,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct PurchaseExecuted {
+pub struct BridgeProgramSet {
+    pub bridge_program: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BridgeFundsRedeemed {
     pub session_id: String,
-    pub service_id: String,
+    pub bridge_program: Pubkey,
     pub amount: u64,
-    pub remaining_balance: u64,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct FundsAdded {
+pub struct SessionExported {
+    pub session_id: String,
+    pub checkpoint: Pubkey,
+    pub version: u8,
+    pub current_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionImported {
+    pub session_id: String,
+    pub checkpoint: Pubkey,
+    pub version: u8,
+    pub current_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProviderRegistered {
+    pub provider: Pubkey,
+    pub provider_account: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RebateReceived {
     pub session_id: String,
+    pub provider: Pubkey,
     pub amount: u64,
     pub new_balance: u64,
+    pub total_rebated: u64,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct SessionClosed {
+pub struct CapabilityIssued {
     pub session_id: String,
-    pub refunded_amount: u64,
+    pub capability: Pubkey,
+    pub key: Pubkey,
+    pub scope_hash: [u8; 32],
+    pub max_amount: u64,
+    pub expires_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CapabilityRevoked {
+    pub session_id: String,
+    pub capability: Pubkey,
+    pub spent: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeBpsSet {
+    pub fee_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeTierAssigned {
+    pub subject: Pubkey,
+    pub fee_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeTierRemoved {
+    pub subject: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PurchaseFeeCollected {
+    pub session_id: String,
+    pub service_id: String,
+    pub provider: Pubkey,
+    pub fee: u64,
+    pub fee_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionReport {
+    pub session_id: String,
+    pub total_funded: u64,
+    pub total_rebated: u64,
     pub total_spent: u64,
+    pub refunded_amount: u64,
+    pub purchase_count: u64,
+    pub providers: Vec<ProviderSpend>,
+    pub untracked_provider_spend: u64,
+    pub categories: Vec<CategorySpend>,
+    pub uncategorized_spend: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BondedPurchaseOpened {
+    pub session_id: String,
+    pub claim: Pubkey,
+    pub provider: Pubkey,
+    pub amount: u64,
+    pub bond: u64,
+    pub challenge_deadline: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BondForfeited {
+    pub session_id: String,
+    pub claim: Pubkey,
+    pub provider: Pubkey,
+    pub bond: u64,
+    pub new_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BondReleased {
+    pub claim: Pubkey,
+    pub provider: Pubkey,
+    pub bond: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionCloned {
+    pub session_id: String,
+    pub source_session_id: String,
+    pub expires_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PurchaseRated {
+    pub session_id: String,
+    pub receipt: Pubkey,
+    pub provider: Pubkey,
+    pub score: u8,
+    pub comment_hash: [u8; 32],
+    pub rating_count: u64,
+    pub rating_total: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FundingCapSet {
+    pub session_id: String,
+    pub max_total_funding: u64,
+    pub total_funded: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FundingCapRaiseProposed {
+    pub session_id: String,
+    pub max_total_funding: u64,
+    pub raise_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PurchaseValidated {
+    pub session_id: String,
+    pub service_id: String,
+    pub provider: Pubkey,
+    pub category_code: Option<u32>,
+    pub amount: u64,
+    pub fee: u64,                  // Platform fee cut from the provider's share
+    pub remaining_balance: u64,    // Balance the purchase would leave
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeDestinationSet {
+    pub fee_destination: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeesSwept {
+    pub mint: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub total_fees_swept: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PayoutEscheated {
+    pub provider: Pubkey,
+    pub mint: Pubkey,
+    pub ledger: Pubkey,
+    pub amount: u64,
+    pub last_claimed_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ServiceListed {
+    pub provider: Pubkey,
+    pub service_id: String,
+    pub listing: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ServiceDeprecationScheduled {
+    pub provider: Pubkey,
+    pub service_id: String,
+    pub deprecated_after: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ServiceDeprecated {
+    pub session_id: String,
+    pub service_id: String,
+    pub provider: Pubkey,
+    pub deprecated_after: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AllowCpiSet {
+    pub session_id: String,
+    pub allow_cpi: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PermitRedeemed {
+    pub session_id: String,
+    pub funder: Pubkey,
+    pub nonce: u64,
+    pub amount: u64,
+    pub deadline: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PayoutMintSet {
+    pub provider: Pubkey,
+    pub payout_mint: Option<Pubkey>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CircuitBreakerSet {
+    pub volume_window_slots: u64,
+    pub max_window_volume: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CircuitBreakerReset {
+    pub mint: Pubkey,
+    pub cleared_volume: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SessionArchived {
+    pub session_id: String,
+    pub tombstone: Pubkey,
+    pub state_hash: [u8; 32],
+    pub reclaimed_lamports: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TrialDiscountSet {
+    pub provider: Pubkey,
+    pub trial_discount_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TrialRedeemed {
+    pub session_id: String,
+    pub service_id: String,
+    pub provider: Pubkey,
+    pub list_amount: u64,
+    pub discount: u64,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WorkflowPurchaseExecuted {
+    pub session_id: String,
+    pub workflow_id: String,
+    pub receipt: Pubkey,
+    pub leg_count: u8,
+    pub total_amount: u64,
+    pub remaining_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CompressedSessionTreeInitialized {
+    pub merkle_tree: Pubkey,
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub max_depth: u32,
+    pub max_buffer_size: u32,
+    pub timestamp: i64,
+}
+
+/// New state of a compressed session; clients keep the latest one to pass
+/// back with the leaf's proof
+#[event]
+pub struct CompressedSessionUpdated {
+    pub merkle_tree: Pubkey,
+    pub leaf_index: u32,
+    pub leaf: [u8; 32],
+    pub session: CompressedSession,
     pub timestamp: i64,
 }
 
@@ -348,4 +12433,178 @@ pub enum ErrorCode {
     InsufficientBalance,
     #[msg("Math overflow")]
     Overflow,
+    #[msg("Service ID too long")]
+    ServiceIdTooLong,
+    #[msg("No agent key registered for this session")]
+    AgentKeyNotSet,
+    #[msg("Intent nonce already used")]
+    IntentNonceReused,
+    #[msg("Missing or invalid ed25519 signature instruction")]
+    InvalidSignature,
+    #[msg("Invalid session nonce account")]
+    InvalidNonceAccount,
+    #[msg("Voucher has expired")]
+    VoucherExpired,
+    #[msg("Signer is not authorized for this action")]
+    Unauthorized,
+    #[msg("Policy ID too long")]
+    PolicyIdTooLong,
+    #[msg("Too many policy rules")]
+    TooManyPolicyRules,
+    #[msg("Invalid policy rule")]
+    InvalidPolicyRule,
+    #[msg("Policy account does not match the session's policy")]
+    PolicyAccountMismatch,
+    #[msg("Purchase amount exceeds policy limit")]
+    PolicyAmountExceeded,
+    #[msg("Provider not allowed by policy")]
+    PolicyProviderNotAllowed,
+    #[msg("Purchase outside policy time window")]
+    PolicyOutsideTimeWindow,
+    #[msg("Service category not allowed by policy")]
+    PolicyCategoryNotAllowed,
+    #[msg("Agent has outstanding credit to repay")]
+    DebtOutstanding,
+    #[msg("Credit accounts do not match the session")]
+    InvalidCreditAccounts,
+    #[msg("Session already has a receipt tree")]
+    ReceiptTreeAlreadySet,
+    #[msg("Merkle tree does not match the session's receipt tree")]
+    InvalidReceiptTree,
+    #[msg("Template ID too long")]
+    TemplateIdTooLong,
+    #[msg("Invalid expiry")]
+    InvalidExpiry,
+    #[msg("Too many category budgets")]
+    TooManyCategoryBudgets,
+    #[msg("Invalid category budget")]
+    InvalidCategoryBudget,
+    #[msg("Session has expired")]
+    SessionExpired,
+    #[msg("Category budget exceeded")]
+    CategoryBudgetExceeded,
+    #[msg("Funding token accounts missing or invalid")]
+    MissingFundingAccounts,
+    #[msg("Session suspended by anomaly lock")]
+    SessionSuspended,
+    #[msg("Session is not suspended")]
+    SessionNotSuspended,
+    #[msg("Invalid anomaly lock configuration")]
+    InvalidAnomalyConfig,
+    #[msg("Invalid currency balance")]
+    InvalidCurrencyBalance,
+    #[msg("Policy does not allow swap-routed purchases")]
+    SwapNotAllowed,
+    #[msg("Swap exceeded the allowed slippage")]
+    SlippageExceeded,
+    #[msg("Invalid swap accounts")]
+    InvalidSwapAccounts,
+    #[msg("Session has open escrows")]
+    EscrowOutstanding,
+    #[msg("Escrow has expired")]
+    EscrowExpired,
+    #[msg("Escrow has not expired")]
+    EscrowNotExpired,
+    #[msg("Attestation does not report success")]
+    AttestationRejected,
+    #[msg("Invalid escrow accounts")]
+    InvalidEscrowAccounts,
+    #[msg("Nothing to claim")]
+    NothingToClaim,
+    #[msg("Invalid vault shard")]
+    InvalidShard,
+    #[msg("Session ID too long")]
+    SessionIdTooLong,
+    #[msg("Batch entries do not match the batch state or accounts")]
+    InvalidBatch,
+    #[msg("Invalid inactivity sweep configuration")]
+    InvalidSweepConfig,
+    #[msg("Session has not been inactive long enough to sweep")]
+    SessionNotInactive,
+    #[msg("Token account does not belong to the session beneficiary")]
+    InvalidBeneficiary,
+    #[msg("Role assignment does not belong to this session")]
+    InvalidRoleAssignment,
+    #[msg("Purchase exceeds the operator's limits")]
+    OperatorLimitExceeded,
+    #[msg("Category code table is too large or has an invalid prefix")]
+    InvalidCategoryCodes,
+    #[msg("Service id does not map to a reporting code")]
+    UnknownCategoryCode,
+    #[msg("Program is not the configured automation program")]
+    InvalidAutomationProgram,
+    #[msg("Thread is not the session's automation thread")]
+    InvalidAutomationThread,
+    #[msg("Session already has an automation thread")]
+    AutomationThreadRegistered,
+    #[msg("Session has not expired")]
+    SessionNotExpired,
+    #[msg("Bridge redemption did not credit the session token account")]
+    InvalidBridgeTransfer,
+    #[msg("Checkpoint was written in an unsupported format")]
+    UnsupportedCheckpointVersion,
+    #[msg("Checkpoint does not belong to this session")]
+    InvalidCheckpoint,
+    #[msg("Rebate must be paid into the session's token account")]
+    InvalidRebateAccounts,
+    #[msg("Invalid capability")]
+    InvalidCapability,
+    #[msg("Capability has expired")]
+    CapabilityExpired,
+    #[msg("Service id is outside the capability's scope")]
+    CapabilityScopeMismatch,
+    #[msg("Purchase exceeds the capability's limit")]
+    CapabilityLimitExceeded,
+    #[msg("Fee must be at most 10000 basis points")]
+    InvalidFeeBps,
+    #[msg("Fee accounts are incomplete or do not match the purchase")]
+    InvalidFeeAccounts,
+    #[msg("Invalid bond or bond accounts")]
+    InvalidBond,
+    #[msg("Challenge window has closed")]
+    ChallengeWindowClosed,
+    #[msg("Challenge window is still open")]
+    ChallengeWindowOpen,
+    #[msg("Source session uses a different mint than the treasury")]
+    InvalidCloneSource,
+    #[msg("Invalid rating")]
+    InvalidRating,
+    #[msg("Funding would exceed the session's funding cap")]
+    FundingCapExceeded,
+    #[msg("Invalid funding cap")]
+    InvalidFundingCap,
+    #[msg("No funding cap raise is ready to apply")]
+    FundingCapRaisePending,
+    #[msg("Fee destination is unset or does not own the destination account")]
+    InvalidFeeDestination,
+    #[msg("No fees have accrued since the last sweep")]
+    NoFeesToSweep,
+    #[msg("Payout ledger has been claimed within the escheat period")]
+    PayoutNotAbandoned,
+    #[msg("Service has been deprecated by its provider")]
+    ServiceDeprecated,
+    #[msg("Purchase is composed with unexpected instructions or callers")]
+    UnexpectedComposition,
+    #[msg("Funding permit deadline has passed")]
+    PermitExpired,
+    #[msg("Token account is not the session's account in its mint")]
+    InvalidSessionTokenAccount,
+    #[msg("Provider takes payment in a different mint; route the purchase through a swap")]
+    PayoutMintMismatch,
+    #[msg("Protocol purchase volume cap reached for this window")]
+    CircuitBreakerTripped,
+    #[msg("Circuit breaker needs a window of at least one slot")]
+    InvalidCircuitBreaker,
+    #[msg("Session must be closed before it can be archived")]
+    SessionStillOpen,
+    #[msg("Workflow ID too long")]
+    WorkflowIdTooLong,
+    #[msg("Workflow legs are empty, too many, or do not match the accounts")]
+    InvalidWorkflow,
+    #[msg("Payout ledger does not accrue in the session mint")]
+    InvalidPayoutLedger,
+    #[msg("Compressed sessions are not enabled in this build")]
+    CompressedSessionsDisabled,
+    #[msg("Receipt metadata URI too long")]
+    ReceiptUriTooLong,
 }
@@ -0,0 +1,375 @@
+//! Client helper tests for the purchase, funding, close and role flows.
+//!
+//! These check what a client sends and reads back: account order, signer and
+//! writable flags, PDAs, instruction data and account decoding. Executing the
+//! instructions needs a solana-program-test bank, which this build does not
+//! have.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::sysvar::instructions as instructions_sysvar;
+use anchor_lang::Discriminator;
+use anchor_spl::token;
+use session_wallet::client::*;
+use session_wallet::{accounts, instruction, Role, RoleAssignment, SessionWallet};
+
+fn key(seed: u8) -> Pubkey {
+    Pubkey::new_from_array([seed; 32])
+}
+
+fn sighash(name: &str) -> [u8; 8] {
+    let mut discriminator = [0; 8];
+    discriminator.copy_from_slice(&hash(format!("global:{}", name).as_bytes()).to_bytes()[..8]);
+    discriminator
+}
+
+/// Optional accounts left out are passed as the program id
+fn omitted() -> AccountMeta {
+    AccountMeta::new_readonly(session_wallet::ID, false)
+}
+
+fn purchase_instruction(session: Pubkey, provider_token_account: Pubkey, provider: Pubkey, mint: Pubkey) -> Instruction {
+    instruction(
+        accounts::ExecutePurchase {
+            session_wallet: session,
+            session_token_account: key(10),
+            service_provider_token_account: provider_token_account,
+            authority: key(11),
+            token_program: token::ID,
+            instructions: instructions_sysvar::ID,
+            config: config_pda().0,
+            global_stats: global_stats_pda(&mint).0,
+            policy: None,
+            role_assignment: Some(role_pda(&session, &key(11)).0),
+            currency_balance: None,
+            category_codes: None,
+            service_listing: service_listing_pda(&provider, "weather/forecast").0,
+            fee_config: fee_config_pda().0,
+            treasury: treasury_pda(&mint).0,
+            treasury_vault: treasury_vault_pda(&mint).0,
+            agent_fee_tier: None,
+            provider_fee_tier: Some(fee_tier_pda(&provider).0),
+            provider_account: provider_pda(&provider).0,
+        },
+        instruction::ExecutePurchase {
+            amount: 250_000,
+            service_id: "weather/forecast".to_string(),
+        },
+    )
+}
+
+#[test]
+fn purchase_instruction_lists_accounts_in_program_order() {
+    let mint = key(1);
+    let provider = key(2);
+    let session = session_pda("chat-42").0;
+    let ix = purchase_instruction(session, key(3), provider, mint);
+
+    assert_eq!(ix.program_id, session_wallet::ID);
+    assert_eq!(
+        ix.accounts,
+        vec![
+            AccountMeta::new(session, false),
+            AccountMeta::new(key(10), false),
+            AccountMeta::new(key(3), false),
+            AccountMeta::new_readonly(key(11), true),
+            AccountMeta::new_readonly(token::ID, false),
+            AccountMeta::new_readonly(instructions_sysvar::ID, false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(global_stats_pda(&mint).0, false),
+            omitted(),
+            AccountMeta::new(role_pda(&session, &key(11)).0, false),
+            omitted(),
+            omitted(),
+            AccountMeta::new_readonly(fee_config_pda().0, false),
+            AccountMeta::new(treasury_pda(&mint).0, false),
+            AccountMeta::new(treasury_vault_pda(&mint).0, false),
+            omitted(),
+            AccountMeta::new_readonly(fee_tier_pda(&provider).0, false),
+            AccountMeta::new_readonly(service_listing_pda(&provider, "weather/forecast").0, false),
+            AccountMeta::new_readonly(provider_pda(&provider).0, false),
+        ]
+    );
+}
+
+#[test]
+fn purchase_instruction_data_round_trips() {
+    let ix = purchase_instruction(session_pda("chat-42").0, key(3), key(2), key(1));
+
+    assert_eq!(ix.data[..8], sighash("execute_purchase"));
+    assert_eq!(ix.data[..8], instruction::ExecutePurchase::DISCRIMINATOR);
+
+    let args = instruction::ExecutePurchase::try_from_slice(&ix.data[8..]).unwrap();
+    assert_eq!(args.amount, 250_000);
+    assert_eq!(args.service_id, "weather/forecast");
+}
+
+#[test]
+fn service_listing_pda_hashes_long_service_ids() {
+    let provider = key(2);
+    let service_id = "x".repeat(64);
+
+    assert_eq!(
+        service_listing_pda(&provider, &service_id),
+        Pubkey::find_program_address(
+            &[b"service", provider.as_ref(), &hash(service_id.as_bytes()).to_bytes()],
+            &session_wallet::ID,
+        )
+    );
+    assert_ne!(
+        service_listing_pda(&provider, &service_id).0,
+        service_listing_pda(&provider, "x").0
+    );
+}
+
+#[test]
+fn fund_session_instruction_omits_credit_accounts() {
+    let session = session_pda("chat-42").0;
+    let ix = instruction(
+        accounts::FundSession {
+            session_wallet: session,
+            funder_token_account: key(4),
+            session_token_account: key(10),
+            funder: key(5),
+            token_program: token::ID,
+            agent_account: None,
+            debt: None,
+            treasury: None,
+            treasury_vault: None,
+        },
+        instruction::FundSession { amount: 1_000_000 },
+    );
+
+    assert_eq!(
+        ix.accounts,
+        vec![
+            AccountMeta::new(session, false),
+            AccountMeta::new(key(4), false),
+            AccountMeta::new(key(10), false),
+            AccountMeta::new_readonly(key(5), true),
+            AccountMeta::new_readonly(token::ID, false),
+            omitted(),
+            omitted(),
+            omitted(),
+            omitted(),
+        ]
+    );
+    assert_eq!(ix.data[..8], sighash("fund_session"));
+    assert_eq!(ix.data[8..], 1_000_000u64.to_le_bytes());
+}
+
+#[test]
+fn fund_session_instruction_passes_credit_accounts() {
+    let agent_account = agent_pda(&key(6)).0;
+    let ix = instruction(
+        accounts::FundSession {
+            session_wallet: session_pda("chat-42").0,
+            funder_token_account: key(4),
+            session_token_account: key(10),
+            funder: key(5),
+            token_program: token::ID,
+            agent_account: Some(agent_account),
+            debt: Some(debt_pda(&agent_account, &key(1)).0),
+            treasury: Some(treasury_pda(&key(1)).0),
+            treasury_vault: Some(treasury_vault_pda(&key(1)).0),
+        },
+        instruction::FundSession { amount: 1_000_000 },
+    );
+
+    assert_eq!(
+        ix.accounts[5..],
+        [
+            AccountMeta::new(agent_account, false),
+            AccountMeta::new(debt_pda(&agent_account, &key(1)).0, false),
+            AccountMeta::new(treasury_pda(&key(1)).0, false),
+            AccountMeta::new(treasury_vault_pda(&key(1)).0, false),
+        ]
+    );
+}
+
+#[test]
+fn close_session_instruction_refunds_through_the_treasury() {
+    let mint = key(1);
+    let session = session_pda("chat-42").0;
+    let ix = instruction(
+        accounts::CloseSession {
+            session_wallet: session,
+            session_token_account: key(10),
+            treasury: treasury_pda(&mint).0,
+            treasury_vault: treasury_vault_pda(&mint).0,
+            authority: key(11),
+            token_program: token::ID,
+            role_assignment: None,
+        },
+        instruction::CloseSession {},
+    );
+
+    assert_eq!(
+        ix.accounts,
+        vec![
+            AccountMeta::new(session, false),
+            AccountMeta::new(key(10), false),
+            AccountMeta::new(treasury_pda(&mint).0, false),
+            AccountMeta::new(treasury_vault_pda(&mint).0, false),
+            AccountMeta::new_readonly(key(11), true),
+            AccountMeta::new_readonly(token::ID, false),
+            omitted(),
+        ]
+    );
+    assert_eq!(ix.data, sighash("close_session"));
+}
+
+#[test]
+fn assign_role_instruction_creates_the_member_pda() {
+    let session = session_pda("chat-42").0;
+    let member = key(7);
+    let ix = instruction(
+        accounts::AssignRole {
+            session_wallet: session,
+            role_assignment: role_pda(&session, &member).0,
+            authority: key(11),
+            system_program: System::id(),
+        },
+        instruction::AssignRole {
+            member,
+            role: Role::Operator,
+            purchase_limit: 100_000,
+            spend_limit: 1_000_000,
+        },
+    );
+
+    assert_eq!(
+        ix.accounts,
+        vec![
+            AccountMeta::new_readonly(session, false),
+            AccountMeta::new(role_pda(&session, &member).0, false),
+            AccountMeta::new(key(11), true),
+            AccountMeta::new_readonly(System::id(), false),
+        ]
+    );
+
+    let args = instruction::AssignRole::try_from_slice(&ix.data[8..]).unwrap();
+    assert_eq!(args.member, member);
+    assert_eq!(args.role, Role::Operator);
+    assert_eq!(args.purchase_limit, 100_000);
+    assert_eq!(args.spend_limit, 1_000_000);
+}
+
+#[test]
+fn role_pda_is_per_session_and_member() {
+    let session = session_pda("chat-42").0;
+
+    assert_ne!(role_pda(&session, &key(7)).0, role_pda(&session, &key(8)).0);
+    assert_ne!(
+        role_pda(&session, &key(7)).0,
+        role_pda(&session_pda("chat-43").0, &key(7)).0
+    );
+}
+
+#[test]
+fn owners_hold_every_role() {
+    assert!(Role::Owner.permits(Role::Operator));
+    assert!(Role::Owner.permits(Role::Auditor));
+    assert!(Role::Operator.permits(Role::Operator));
+    assert!(!Role::Operator.permits(Role::Owner));
+    assert!(!Role::Auditor.permits(Role::Operator));
+}
+
+#[test]
+fn decode_reads_a_role_assignment() {
+    let assignment = RoleAssignment {
+        session: session_pda("chat-42").0,
+        member: key(7),
+        role: Role::Operator,
+        purchase_limit: 100_000,
+        spend_limit: 1_000_000,
+        spent: 42,
+        bump: 254,
+    };
+    let mut data = Vec::new();
+    assignment.try_serialize(&mut data).unwrap();
+
+    let decoded: RoleAssignment = decode(&data).unwrap();
+    assert_eq!(decoded.member, key(7));
+    assert_eq!(decoded.role, Role::Operator);
+    assert_eq!(decoded.spent, 42);
+
+    data[0] ^= 1;
+    assert!(decode::<RoleAssignment>(&data).is_err());
+}
+
+#[test]
+fn decode_zero_copy_reads_a_session_wallet() {
+    let mut session: SessionWallet = bytemuck::Zeroable::zeroed();
+    session.set_session_id("chat-42").unwrap();
+    session.set_active(true);
+    session.current_balance = 750_000;
+
+    let mut data = SessionWallet::discriminator().to_vec();
+    data.extend_from_slice(bytemuck::bytes_of(&session));
+
+    let decoded: SessionWallet = decode_zero_copy(&data).unwrap();
+    assert_eq!(decoded.session_id(), "chat-42");
+    assert!(decoded.is_active());
+    assert_eq!(decoded.current_balance, 750_000);
+
+    assert!(decode_zero_copy::<SessionWallet>(&data[..data.len() - 1]).is_err());
+    assert!(decode_zero_copy::<SessionWallet>(&data[8..]).is_err());
+}
+
+#[test]
+fn derived_session_pda_matches_its_session_id() {
+    let agent = key(6);
+
+    assert_eq!(
+        derived_session_pda(&agent, 3),
+        session_pda(&session_wallet::derived_session_id(&agent, 3))
+    );
+    assert_ne!(derived_session_pda(&agent, 3).0, derived_session_pda(&agent, 4).0);
+}
+
+/// The CPI interface builds the same accounts a client passes by hand
+#[cfg(feature = "cpi")]
+mod cpi_interface {
+    use super::*;
+    use anchor_lang::InstructionData;
+    use anchor_spl::associated_token::get_associated_token_address;
+    use session_wallet::interface;
+
+    #[test]
+    fn execute_purchase_instruction_matches_the_client() {
+        let mint = key(1);
+        let provider = key(2);
+        let ix = interface::execute_purchase_instruction(
+            "chat-42",
+            &mint,
+            &provider,
+            key(3),
+            key(11),
+            250_000,
+            "weather/forecast".to_string(),
+        );
+
+        let mut expected = purchase_instruction(session_pda("chat-42").0, key(3), provider, mint);
+        expected.accounts[1] = AccountMeta::new(interface::session_token_account("chat-42", &mint), false);
+        expected.accounts[9] = omitted();
+        expected.accounts[16] = omitted();
+        assert_eq!(ix, expected);
+    }
+
+    #[test]
+    fn fund_session_instruction_pays_the_session_ata() {
+        let mint = key(1);
+        let ix = interface::fund_session_instruction("chat-42", &mint, key(5), key(4), 1_000_000);
+
+        assert_eq!(
+            ix.accounts[2],
+            AccountMeta::new(
+                get_associated_token_address(&session_pda("chat-42").0, &mint),
+                false
+            )
+        );
+        assert_eq!(ix.data, instruction::FundSession { amount: 1_000_000 }.data());
+    }
+}
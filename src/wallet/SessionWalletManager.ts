@@ -1,9 +1,10 @@
-import { Connection, PublicKey, Transaction, SystemProgram, Keypair } from '@solana/web3.js';
+import { Connection, PublicKey, Transaction, SystemProgram, Keypair, SYSVAR_INSTRUCTIONS_PUBKEY } from '@solana/web3.js';
 import { Program, AnchorProvider, web3, BN, Provider, Wallet } from '@project-serum/anchor';
 import { Token, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID } from '@solana/spl-token';
 import { Database } from '../registry/database.js';
 import { logger } from '../utils/logger.js';
 import bs58 from 'bs58';
+import { createHash } from 'crypto';
 
 export interface SessionWallet {
   sessionId: string;
@@ -25,7 +26,9 @@ const SESSION_WALLET_IDL = {
       name: "initializeSession",
       accounts: [
         { name: "sessionWallet", isMut: true, isSigner: false },
-        { name: "treasuryTokenAccount", isMut: true, isSigner: false },
+        { name: "config", isMut: false, isSigner: false },
+        { name: "treasury", isMut: true, isSigner: false },
+        { name: "treasuryVault", isMut: true, isSigner: false },
         { name: "sessionTokenAccount", isMut: true, isSigner: false },
         { name: "authority", isMut: true, isSigner: true },
         { name: "tokenProgram", isMut: false, isSigner: false },
@@ -43,7 +46,22 @@ const SESSION_WALLET_IDL = {
         { name: "sessionWallet", isMut: true, isSigner: false },
         { name: "sessionTokenAccount", isMut: true, isSigner: false },
         { name: "serviceProviderTokenAccount", isMut: true, isSigner: false },
-        { name: "tokenProgram", isMut: false, isSigner: false }
+        { name: "authority", isMut: false, isSigner: true },
+        { name: "tokenProgram", isMut: false, isSigner: false },
+        { name: "instructions", isMut: false, isSigner: false },
+        { name: "config", isMut: false, isSigner: false },
+        { name: "globalStats", isMut: true, isSigner: false },
+        { name: "policy", isMut: false, isSigner: false, isOptional: true },
+        { name: "roleAssignment", isMut: true, isSigner: false, isOptional: true },
+        { name: "currencyBalance", isMut: true, isSigner: false, isOptional: true },
+        { name: "categoryCodes", isMut: false, isSigner: false, isOptional: true },
+        { name: "serviceListing", isMut: false, isSigner: false },
+        { name: "feeConfig", isMut: false, isSigner: false },
+        { name: "treasury", isMut: true, isSigner: false },
+        { name: "treasuryVault", isMut: true, isSigner: false },
+        { name: "agentFeeTier", isMut: false, isSigner: false, isOptional: true },
+        { name: "providerFeeTier", isMut: false, isSigner: false, isOptional: true },
+        { name: "providerAccount", isMut: false, isSigner: false }
       ],
       args: [
         { name: "amount", type: "u64" },
@@ -68,7 +86,8 @@ const SESSION_WALLET_IDL = {
       accounts: [
         { name: "sessionWallet", isMut: true, isSigner: false },
         { name: "sessionTokenAccount", isMut: true, isSigner: false },
-        { name: "treasuryTokenAccount", isMut: true, isSigner: false },
+        { name: "treasury", isMut: true, isSigner: false },
+        { name: "treasuryVault", isMut: true, isSigner: false },
         { name: "authority", isMut: false, isSigner: true },
         { name: "tokenProgram", isMut: false, isSigner: false }
       ],
@@ -82,13 +101,80 @@ const SESSION_WALLET_IDL = {
         kind: "struct",
         fields: [
           { name: "authority", type: "publicKey" },
-          { name: "sessionId", type: "string" },
+          { name: "mint", type: "publicKey" },
+          { name: "agentPubkey", type: "publicKey" },
+          { name: "nonceAccount", type: "publicKey" },
+          { name: "policy", type: "publicKey" },
+          { name: "receiptTree", type: "publicKey" },
+          { name: "beneficiary", type: "publicKey" },
+          { name: "automationThread", type: "publicKey" },
+          { name: "sessionId", type: { array: ["u8", 32] } },
           { name: "createdAt", type: "i64" },
           { name: "lastActivity", type: "i64" },
           { name: "initialBalance", type: "u64" },
           { name: "currentBalance", type: "u64" },
-          { name: "isActive", type: "bool" },
-          { name: "bump", type: "u8" }
+          { name: "purchaseCount", type: "u64" },
+          { name: "totalFunded", type: "u64" },
+          { name: "totalSpent", type: "u64" },
+          { name: "snapshotCount", type: "u64" },
+          { name: "lastIntentNonce", type: "u64" },
+          { name: "compressedReceiptCount", type: "u64" },
+          { name: "expiresAt", type: "i64" },
+          { name: "expiryGracePeriod", type: "i64" },
+          { name: "velocityWindow", type: "i64" },
+          { name: "windowStart", type: "i64" },
+          { name: "windowSpent", type: "u64" },
+          { name: "averageWindowSpend", type: "u64" },
+          { name: "escrowedBalance", type: "u64" },
+          { name: "inactivitySweepSecs", type: "i64" },
+          { name: "totalRebated", type: "u64" },
+          { name: "totalChannelRefunds", type: "u64" },
+          { name: "untrackedProviderSpend", type: "u64" },
+          { name: "maxTotalFunding", type: "u64" },
+          { name: "pendingFundingCap", type: "u64" },
+          { name: "fundingCapRaiseAt", type: "i64" },
+          { name: "lastEventSlot", type: "u64" },
+          { name: "lastEventSeq", type: "u64" },
+          { name: "categoryBudgets", type: { array: [{ defined: "CategoryBudgetEntry" }, 4] } },
+          { name: "providerSpend", type: { array: [{ defined: "ProviderSpendEntry" }, 8] } },
+          { name: "velocityMultiple", type: "u32" },
+          { name: "sessionIdLen", type: "u8" },
+          { name: "isActive", type: "u8" },
+          { name: "isSuspended", type: "u8" },
+          { name: "bump", type: "u8" },
+          { name: "shardCount", type: "u8" },
+          { name: "categoryBudgetCount", type: "u8" },
+          { name: "categoryCodesRequired", type: "u8" },
+          { name: "automationTask", type: "u8" },
+          { name: "providerSpendCount", type: "u8" },
+          { name: "allowCpi", type: "u8" },
+          { name: "padding", type: { array: ["u8", 2] } }
+        ]
+      }
+    }
+  ],
+  types: [
+    {
+      name: "CategoryBudgetEntry",
+      type: {
+        kind: "struct",
+        fields: [
+          { name: "limit", type: "u64" },
+          { name: "spent", type: "u64" },
+          { name: "prefix", type: { array: ["u8", 32] } },
+          { name: "prefixLen", type: "u8" },
+          { name: "padding", type: { array: ["u8", 7] } }
+        ]
+      }
+    },
+    {
+      name: "ProviderSpendEntry",
+      type: {
+        kind: "struct",
+        fields: [
+          { name: "provider", type: "publicKey" },
+          { name: "amount", type: "u64" },
+          { name: "purchaseCount", type: "u64" }
         ]
       }
     }
@@ -123,6 +209,10 @@ export class SessionWalletManager {
   private programId: PublicKey;
   private usdcMint: PublicKey;
   private treasuryTokenAccount: PublicKey | null = null;
+  private configPda: PublicKey | null = null;
+  private treasuryPda: PublicKey | null = null;
+  private treasuryVault: PublicKey | null = null;
+  private globalStatsPda: PublicKey | null = null;
   private db: Database;
 
   constructor(db: Database) {
@@ -173,6 +263,22 @@ export class SessionWalletManager {
         this.authority.publicKey
       );
 
+      // Program-owned treasury that funds sessions and receives refunds
+      [this.configPda] = PublicKey.findProgramAddressSync([Buffer.from('config')], this.programId);
+      [this.treasuryPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from('treasury'), this.usdcMint.toBuffer()],
+        this.programId
+      );
+      [this.treasuryVault] = PublicKey.findProgramAddressSync(
+        [Buffer.from('treasury_vault'), this.usdcMint.toBuffer()],
+        this.programId
+      );
+      // Per-mint volume totals checked by the purchase circuit breaker
+      [this.globalStatsPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from('global_stats'), this.usdcMint.toBuffer()],
+        this.programId
+      );
+
       logger.info(`✓ Program initialized with authority: ${this.authority.publicKey.toBase58()}`);
       logger.info(`✓ Treasury token account: ${this.treasuryTokenAccount?.toBase58()}`);
 
@@ -221,7 +327,9 @@ export class SessionWalletManager {
         .initializeSession(sessionId, fundingAmount)
         .accounts({
           sessionWallet: pda,
-          treasuryTokenAccount: this.treasuryTokenAccount,
+          config: this.configPda,
+          treasury: this.treasuryPda,
+          treasuryVault: this.treasuryVault,
           sessionTokenAccount: sessionTokenAccount,
           authority: this.authority.publicKey,
           tokenProgram: TOKEN_PROGRAM_ID,
@@ -296,13 +404,38 @@ export class SessionWalletManager {
       // Get service provider token account
       const serviceProviderTokenAccount = new PublicKey(serviceProviderWallet);
 
+      // The provider's registry and listing PDAs are derived from the token account owner
+      const providerTokenInfo = await this.connection.getParsedAccountInfo(serviceProviderTokenAccount);
+      const providerOwner = new PublicKey((providerTokenInfo.value?.data as any).parsed.info.owner);
+      const [providerAccount] = await PublicKey.findProgramAddress(
+        [Buffer.from('provider'), providerOwner.toBuffer()],
+        this.programId
+      );
+      const [feeConfig] = await PublicKey.findProgramAddress(
+        [Buffer.from('fee_config')],
+        this.programId
+      );
+      const [serviceListing] = await PublicKey.findProgramAddress(
+        [Buffer.from('service'), providerOwner.toBuffer(), createHash('sha256').update(serviceId).digest()],
+        this.programId
+      );
+
       const tx = await this.program.methods
         .executePurchase(amountLamports, serviceId)
         .accounts({
           sessionWallet: pda,
           sessionTokenAccount: sessionTokenAccount,
           serviceProviderTokenAccount: serviceProviderTokenAccount,
+          authority: this.authority.publicKey,
           tokenProgram: TOKEN_PROGRAM_ID,
+          instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+          config: this.configPda,
+          globalStats: this.globalStatsPda,
+          serviceListing: serviceListing,
+          feeConfig: feeConfig,
+          treasury: this.treasuryPda,
+          treasuryVault: this.treasuryVault,
+          providerAccount: providerAccount,
         })
         .rpc();
 
@@ -404,7 +537,8 @@ export class SessionWalletManager {
         .accounts({
           sessionWallet: pda,
           sessionTokenAccount: sessionTokenAccount,
-          treasuryTokenAccount: this.treasuryTokenAccount,
+          treasury: this.treasuryPda,
+          treasuryVault: this.treasuryVault,
           authority: this.authority.publicKey,
           tokenProgram: TOKEN_PROGRAM_ID,
         })
@@ -500,7 +634,7 @@ export class SessionWalletManager {
       );
 
       const account = await this.program.account.sessionWallet.fetch(pda);
-      return account.isActive;
+      return account.isActive !== 0;
     } catch {
       return false;
     }